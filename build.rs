@@ -1,4 +1,7 @@
 fn main() {
-    let res = winres::WindowsResource::new();
-    res.compile().unwrap();
-}
\ No newline at end of file
+    // The version resource is only meaningful for the addon dll, the scenario runner is also built on other platforms
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+        let res = winres::WindowsResource::new();
+        res.compile().unwrap();
+    }
+}
@@ -0,0 +1,365 @@
+#![allow(non_snake_case)]
+
+//! Attendance of squad members during a squad session. A session starts when self joins a squad and ends when self
+//! leaves it, accounts can join and leave any number of times in between.
+//!
+//! Reports are written through the export subsystem to `addons/arcdps_squad_manager/attendance`, named
+//! `attendance_<session start as %Y%m%d_%H%M%S>.{csv,json}` so reports of different weeks can be compared. Writing the
+//! report again during the same session (manually or when self leaves the squad) replaces the earlier one. Rows
+//! (schema_version 1, ordered by time in squad, longest first):
+//! - `session_start` - RFC 3339 timestamp
+//! - `session_end` - RFC 3339 timestamp, the time of the report if the session is still running
+//! - `account_name` - string
+//! - `time_in_squad_s` - integer, total seconds the account spent in the squad during the session
+//! - `join_count` - integer, number of times the account joined the squad during the session
+//! - `first_joined` - RFC 3339 timestamp
+//! - `last_left` - RFC 3339 timestamp, empty/null if the account was still in the squad at the end of the session
+
+use crate::{
+    export::{export_file_name, export_rows, CsvRow, ExportMode},
+    persistence::addon_path,
+    squad_events::{SquadEvent, SquadEventKind},
+};
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountAttendance {
+    // Time spent in the squad in stints that already ended
+    pub completed_time: Duration,
+    // Start of the current stint if the account is in the squad right now
+    pub current_join: Option<DateTime<Local>>,
+    pub first_joined: DateTime<Local>,
+    pub last_left: Option<DateTime<Local>>,
+    pub join_count: u32,
+}
+
+impl AccountAttendance {
+    pub fn time_in_squad(&self, pNow: &DateTime<Local>) -> Duration {
+        let current = self
+            .current_join
+            .and_then(|x| (*pNow - x).to_std().ok())
+            .unwrap_or_default();
+
+        self.completed_time + current
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttendanceRow {
+    pub session_start: String,
+    pub session_end: String,
+    pub account_name: String,
+    pub time_in_squad_s: u64,
+    pub join_count: u32,
+    pub first_joined: String,
+    pub last_left: Option<String>,
+}
+
+impl CsvRow for AttendanceRow {
+    const HEADER: &'static [&'static str] = &[
+        "session_start",
+        "session_end",
+        "account_name",
+        "time_in_squad_s",
+        "join_count",
+        "first_joined",
+        "last_left",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.session_start.clone(),
+            self.session_end.clone(),
+            self.account_name.clone(),
+            self.time_in_squad_s.to_string(),
+            self.join_count.to_string(),
+            self.first_joined.clone(),
+            self.last_left.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub struct AttendanceSession {
+    start: DateTime<Local>,
+    accounts: HashMap<String, AccountAttendance>,
+}
+
+impl AttendanceSession {
+    pub fn new(pStart: DateTime<Local>) -> Self {
+        Self {
+            start: pStart,
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn start(&self) -> DateTime<Local> {
+        self.start
+    }
+
+    pub fn accounts(&self) -> &HashMap<String, AccountAttendance> {
+        &self.accounts
+    }
+
+    // pJoinTime is when the account joined according to the game, which can be earlier than the moment we first saw
+    // them (e.g. after a missed squad update). That time is counted as well, but only from the start of the session on,
+    // so everyone that was already in the squad when self joined counts from the session start
+    pub fn member_joined(&mut self, pAccountName: &str, pJoinTime: DateTime<Local>) {
+        let join_time = pJoinTime.max(self.start);
+        let attendance = self
+            .accounts
+            .entry(pAccountName.to_string())
+            .or_insert_with(|| AccountAttendance {
+                completed_time: Duration::default(),
+                current_join: None,
+                first_joined: join_time,
+                last_left: None,
+                join_count: 0,
+            });
+
+        if attendance.current_join.is_some() {
+            debug!("{} joined while already in the squad", pAccountName);
+            return;
+        }
+        attendance.current_join = Some(join_time);
+        attendance.join_count += 1;
+    }
+
+    pub fn member_left(&mut self, pAccountName: &str, pNow: DateTime<Local>) {
+        let attendance = match self.accounts.get_mut(pAccountName) {
+            Some(x) => x,
+            None => {
+                debug!("{} left without being tracked as joined", pAccountName);
+                return;
+            }
+        };
+
+        if let Some(join) = attendance.current_join.take() {
+            attendance.completed_time += (pNow - join).to_std().unwrap_or_default();
+            attendance.last_left = Some(pNow);
+        }
+    }
+
+    // Marks everyone as having left, used when self leaves the squad
+    pub fn end(&mut self, pNow: DateTime<Local>) {
+        let account_names = self.accounts.keys().cloned().collect::<Vec<_>>();
+        for account_name in account_names {
+            self.member_left(&account_name, pNow);
+        }
+    }
+
+    pub fn report(&self, pNow: &DateTime<Local>) -> Vec<AttendanceRow> {
+        let mut rows = self
+            .accounts
+            .iter()
+            .map(|(account_name, attendance)| AttendanceRow {
+                session_start: self.start.to_rfc3339(),
+                session_end: pNow.to_rfc3339(),
+                account_name: account_name.clone(),
+                time_in_squad_s: attendance.time_in_squad(pNow).as_secs(),
+                join_count: attendance.join_count,
+                first_joined: attendance.first_joined.to_rfc3339(),
+                last_left: if attendance.current_join.is_some() {
+                    None
+                } else {
+                    attendance.last_left.map(|x| x.to_rfc3339())
+                },
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|lhs, rhs| {
+            rhs.time_in_squad_s
+                .cmp(&lhs.time_in_squad_s)
+                .then_with(|| lhs.account_name.cmp(&rhs.account_name))
+        });
+
+        rows
+    }
+
+    pub fn write_report(&self, pNow: &DateTime<Local>) -> Option<PathBuf> {
+        // Named after the session start, so writing the report again later in the same session replaces it
+        export_rows(
+            &addon_path("attendance"),
+            &export_file_name("attendance", &self.start),
+            pNow,
+            self.report(pNow),
+            ExportMode::Replace,
+        )
+    }
+}
+
+// Keeps the attendance session of the current squad up to date from the squad events. A session starts with the first
+// member joining while self isn't in a squad, and is kept after self left the squad until the next one is joined
+pub struct AttendanceTracker {
+    session: Option<AttendanceSession>,
+    squad_ended: bool,
+    write_reports: bool,
+}
+
+impl AttendanceTracker {
+    pub fn new() -> Self {
+        Self {
+            session: None,
+            squad_ended: true,
+            write_reports: false,
+        }
+    }
+
+    pub fn session(&self) -> Option<&AttendanceSession> {
+        self.session.as_ref()
+    }
+
+    // Enables writing an attendance report whenever self leaves the squad (ending the session)
+    pub fn set_reports_enabled(&mut self, pEnabled: bool) {
+        self.write_reports = pEnabled;
+    }
+
+    pub fn handle_event(&mut self, pEvent: &SquadEvent) {
+        match &pEvent.kind {
+            SquadEventKind::MemberJoined {
+                account_name,
+                join_time,
+                ..
+            } => {
+                if self.squad_ended == true {
+                    self.session = Some(AttendanceSession::new(pEvent.time));
+                    self.squad_ended = false;
+                }
+                // Count the time since the member joined according to the game, unless that time is invalid
+                let join_time = Local
+                    .timestamp_opt(*join_time as i64, 0)
+                    .single()
+                    .filter(|x| *x <= pEvent.time)
+                    .unwrap_or(pEvent.time);
+                if let Some(session) = &mut self.session {
+                    session.member_joined(account_name, join_time);
+                }
+            }
+            SquadEventKind::MemberLeft { account_name } => {
+                if let Some(session) = &mut self.session {
+                    session.member_left(account_name, pEvent.time);
+                }
+            }
+            SquadEventKind::SelfLeftSquad => {
+                self.squad_ended = true;
+                if let Some(session) = &mut self.session {
+                    session.end(pEvent.time);
+                    if self.write_reports == true {
+                        session.write_report(&pEvent.time);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttendanceSession, AttendanceTracker};
+    use crate::infra::install_log_handler;
+    use crate::squad_events::{SquadEvent, SquadEventKind};
+    use arcdps::UserRole;
+    use chrono::{DateTime, Duration, Local};
+
+    // Test that time in squad is summed up over multiple join/leave cycles
+    #[test]
+    fn multiple_stints() {
+        install_log_handler().unwrap();
+
+        let start = Local::now();
+        let mut session = AttendanceSession::new(start);
+
+        session.member_joined("self", start);
+        session.member_joined("peer", start);
+        session.member_left("peer", start + Duration::minutes(10));
+        session.member_joined("peer", start + Duration::minutes(30));
+        // Duplicate join while in the squad doesn't restart the stint
+        session.member_joined("peer", start + Duration::minutes(35));
+        session.member_left("peer", start + Duration::minutes(40));
+        session.member_joined("late", start + Duration::minutes(50));
+
+        let now = start + Duration::minutes(60);
+        let report = session.report(&now);
+        assert_eq!(report.len(), 3);
+
+        assert_eq!(report[0].account_name, "self");
+        assert_eq!(report[0].time_in_squad_s, 60 * 60);
+        assert_eq!(report[0].join_count, 1);
+        assert_eq!(report[0].last_left, None);
+
+        assert_eq!(report[1].account_name, "peer");
+        assert_eq!(report[1].time_in_squad_s, 20 * 60);
+        assert_eq!(report[1].join_count, 2);
+        assert_eq!(
+            report[1].last_left,
+            Some((start + Duration::minutes(40)).to_rfc3339())
+        );
+
+        assert_eq!(report[2].account_name, "late");
+        assert_eq!(report[2].time_in_squad_s, 10 * 60);
+
+        session.end(now);
+        let report = session.report(&(now + Duration::minutes(5)));
+        assert_eq!(report[0].time_in_squad_s, 60 * 60);
+        assert_eq!(report[0].last_left, Some(now.to_rfc3339()));
+    }
+
+    // Test that a session follows the squad events, from the first member joining until self leaves, and that the next
+    // squad starts a new one
+    #[test]
+    fn tracker_sessions() {
+        install_log_handler().unwrap();
+
+        let start = Local::now();
+        let event = |pMinutes: i64, pKind: SquadEventKind| SquadEvent {
+            time: start + Duration::minutes(pMinutes),
+            kind: pKind,
+        };
+        let joined =
+            |pAccountName: &str, pJoinTime: DateTime<Local>| SquadEventKind::MemberJoined {
+                account_name: pAccountName.to_string(),
+                role: UserRole::Member,
+                subgroup: 0,
+                join_time: pJoinTime.timestamp() as u64,
+            };
+        // Peer joined according to the game 5 minutes before the session started, which is only counted from the
+        // session start. Late joined 2 minutes before self saw them. A join time in the future is invalid
+        let invalid_join_time = start + Duration::days(1);
+
+        let mut tracker = AttendanceTracker::new();
+        assert!(tracker.session().is_none());
+        tracker.handle_event(&event(0, joined("self", invalid_join_time)));
+        tracker.handle_event(&event(0, joined("peer", start - Duration::minutes(5))));
+        tracker.handle_event(&event(5, joined("late", start + Duration::minutes(3))));
+        tracker.handle_event(&event(
+            10,
+            SquadEventKind::MemberLeft {
+                account_name: "peer".to_string(),
+            },
+        ));
+        tracker.handle_event(&event(20, SquadEventKind::SelfLeftSquad));
+
+        let session = tracker.session().unwrap();
+        assert_eq!(session.start(), start);
+        let report = session.report(&(start + Duration::minutes(30)));
+        let times = report
+            .iter()
+            .map(|x| (x.account_name.as_str(), x.time_in_squad_s))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![("self", 20 * 60), ("late", 17 * 60), ("peer", 10 * 60)]
+        );
+        assert_eq!(report[2].first_joined, report[2].session_start);
+        assert_eq!(
+            report[1].first_joined,
+            (start + Duration::minutes(3)).to_rfc3339()
+        );
+
+        tracker.handle_event(&event(30, joined("self", invalid_join_time)));
+        let session = tracker.session().unwrap();
+        assert_eq!(session.start(), start + Duration::minutes(30));
+        assert_eq!(session.accounts().len(), 1);
+    }
+}
@@ -0,0 +1,42 @@
+#![allow(non_snake_case)]
+
+// Runs a scenario file (see src/scenario.rs for the format) against the squad tracker and chat log and prints the
+// events and resulting state, so behavior can be checked without the game. Warnings, e.g. for a malformed scenario,
+// are logged to stderr. The level can be changed through RUST_LOG
+
+use arcdps_squad_manager::scenario::{load_scenario, ScenarioRunner};
+use std::{io, path::PathBuf, process::ExitCode};
+
+fn main() -> ExitCode {
+    let path = match std::env::args_os().nth(1) {
+        Some(x) => PathBuf::from(x),
+        None => {
+            eprintln!("Usage: scenario_runner <scenario file>");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let _logger = match flexi_logger::Logger::try_with_env_or_str("warn").and_then(|x| x.start()) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            eprintln!("Starting log failed {}", e);
+            None
+        }
+    };
+
+    let scenario = match load_scenario(&path) {
+        Some(x) => x,
+        None => {
+            eprintln!("Failed to load scenario {}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let runner = ScenarioRunner::run(&scenario);
+    if let Err(e) = runner.write_report(&mut io::stdout().lock()) {
+        eprintln!("Writing the report failed {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
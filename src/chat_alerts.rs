@@ -0,0 +1,336 @@
+#![allow(non_snake_case)]
+
+use crate::chat_log::ChatMessage;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AlertRuleKind {
+    // Own account name, or the character name self last sent a message with
+    Mention,
+    // A word or phrase anywhere in the message, case insensitive and on word boundaries (so "ready" doesn't match
+    // "already")
+    Keyword(String),
+    // Every message sent by this account, case insensitive
+    Sender(String),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AlertRule {
+    pub kind: AlertRuleKind,
+    pub enabled: bool,
+}
+
+impl std::fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            AlertRuleKind::Mention => write!(f, "Mention"),
+            AlertRuleKind::Keyword(x) => write!(f, "Keyword \"{}\"", x),
+            AlertRuleKind::Sender(x) => write!(f, "Sender {}", x),
+        }
+    }
+}
+
+// An incoming message that matched one of the rules
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatAlert {
+    pub rule: AlertRule,
+    pub account_name: String,
+    pub character_name: String,
+    pub text: String,
+}
+
+pub fn default_rules() -> Vec<AlertRule> {
+    vec![AlertRule {
+        kind: AlertRuleKind::Mention,
+        enabled: true,
+    }]
+}
+
+fn word_regex(pWords: &str) -> Option<Regex> {
+    let words = pWords.trim();
+    if words.is_empty() == true {
+        return None;
+    }
+
+    // \b only matches next to a word character, so it's left out on a side where the words start or end with e.g.
+    // punctuation. Otherwise "+1" would only match right after a word character
+    let is_word_char =
+        |x: Option<char>| x.map_or(false, |c| c.is_alphanumeric() == true || c == '_');
+    let start = if is_word_char(words.chars().next()) == true {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word_char(words.chars().next_back()) == true {
+        r"\b"
+    } else {
+        ""
+    };
+
+    RegexBuilder::new(&format!("{}{}{}", start, regex::escape(words), end))
+        .case_insensitive(true)
+        .build()
+        .ok()
+}
+
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    // Keyword and mention patterns, rebuilt whenever the rules or own names change
+    keyword_patterns: Vec<Option<Regex>>,
+    mention_patterns: Vec<Regex>,
+    self_account_name: Option<String>,
+    self_character_name: Option<String>,
+    alerts: Vec<ChatAlert>,
+}
+
+impl AlertEngine {
+    pub fn new() -> Self {
+        let mut result = Self {
+            rules: default_rules(),
+            keyword_patterns: Vec::new(),
+            mention_patterns: Vec::new(),
+            self_account_name: None,
+            self_character_name: None,
+            alerts: Vec::new(),
+        };
+        result.rebuild_patterns();
+
+        result
+    }
+
+    fn rebuild_patterns(&mut self) {
+        self.keyword_patterns = self
+            .rules
+            .iter()
+            .map(|rule| match &rule.kind {
+                AlertRuleKind::Keyword(x) => word_regex(x),
+                _ => None,
+            })
+            .collect();
+
+        // Account names are written with or without the leading colon in chat, so match them without it
+        let account_name = self
+            .self_account_name
+            .as_ref()
+            .map(|x| x.trim_start_matches(':').to_string());
+        self.mention_patterns = account_name
+            .iter()
+            .chain(self.self_character_name.iter())
+            .filter_map(|x| word_regex(x))
+            .collect();
+    }
+
+    pub fn set_self_account_name(&mut self, pAccountName: &str) {
+        self.self_account_name = Some(pAccountName.to_string());
+        self.rebuild_patterns();
+    }
+
+    pub fn get_rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    pub fn set_rules(&mut self, pRules: Vec<AlertRule>) {
+        self.rules = pRules;
+        self.rebuild_patterns();
+    }
+
+    pub fn add_rule(&mut self, pRule: AlertRule) {
+        self.rules.push(pRule);
+        self.rebuild_patterns();
+    }
+
+    pub fn remove_rule(&mut self, pIndex: usize) {
+        if pIndex < self.rules.len() {
+            self.rules.remove(pIndex);
+            self.rebuild_patterns();
+        }
+    }
+
+    pub fn set_rule_enabled(&mut self, pIndex: usize, pEnabled: bool) {
+        if let Some(rule) = self.rules.get_mut(pIndex) {
+            rule.enabled = pEnabled;
+        }
+    }
+
+    fn is_self(&self, pAccountName: &str) -> bool {
+        self.self_account_name.as_ref().map_or(false, |x| {
+            x.trim_start_matches(':') == pAccountName.trim_start_matches(':')
+        })
+    }
+
+    // The first enabled rule pMessage matches. Messages sent by self never match
+    pub fn matching_rule(&self, pMessage: &ChatMessage) -> Option<&AlertRule> {
+        if self.is_self(&pMessage.account_name) == true {
+            return None;
+        }
+
+        self.rules
+            .iter()
+            .zip(self.keyword_patterns.iter())
+            .filter(|(rule, _)| rule.enabled == true)
+            .find(|(rule, keyword_pattern)| match &rule.kind {
+                AlertRuleKind::Mention => self
+                    .mention_patterns
+                    .iter()
+                    .any(|x| x.is_match(&pMessage.text)),
+                AlertRuleKind::Keyword(_) => keyword_pattern
+                    .as_ref()
+                    .map_or(false, |x| x.is_match(&pMessage.text)),
+                AlertRuleKind::Sender(x) => x
+                    .trim_start_matches(':')
+                    .eq_ignore_ascii_case(pMessage.account_name.trim_start_matches(':')),
+            })
+            .map(|(rule, _)| rule)
+    }
+
+    // Called for every incoming message. Raises an alert if the message matches a rule and returns whether it did
+    pub fn check(&mut self, pMessage: &ChatMessage) -> bool {
+        // Learn the character self is currently playing, so mentions of it are picked up as well
+        if self.is_self(&pMessage.account_name) == true
+            && self.self_character_name.as_deref() != Some(pMessage.character_name.as_str())
+        {
+            self.self_character_name = Some(pMessage.character_name.clone());
+            self.rebuild_patterns();
+        }
+
+        let rule = match self.matching_rule(pMessage) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        info!(
+            "Message from {} matched alert rule {}",
+            pMessage.account_name, rule
+        );
+        let alert = ChatAlert {
+            rule: rule.clone(),
+            account_name: pMessage.account_name.clone(),
+            character_name: pMessage.character_name.clone(),
+            text: pMessage.text.clone(),
+        };
+        self.alerts.push(alert);
+
+        true
+    }
+
+    // Alerts raised since the last call
+    pub fn drain_alerts(&mut self) -> Vec<ChatAlert> {
+        std::mem::take(&mut self.alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlertEngine, AlertRule, AlertRuleKind};
+    use crate::chat_log::ChatMessage;
+    use crate::infra::install_log_handler;
+    use chrono::DateTime;
+
+    fn check(
+        pEngine: &mut AlertEngine,
+        pAccountName: &str,
+        pCharacterName: &str,
+        pText: &str,
+    ) -> Option<String> {
+        let message = ChatMessage {
+            is_broadcast: false,
+            timestamp: DateTime::parse_from_rfc3339("2022-07-09T11:45:24.888Z").unwrap(),
+            account_name: pAccountName.to_string(),
+            character_name: pCharacterName.to_string(),
+            text: pText.to_string(),
+            sender: None,
+            is_alert: false,
+        };
+
+        let is_alert = pEngine.check(&message);
+        let mut alerts = pEngine.drain_alerts();
+        assert!(alerts.len() <= 1);
+        assert_eq!(alerts.is_empty(), is_alert == false);
+        alerts.pop().map(|x| x.rule.to_string())
+    }
+
+    // Test that each kind of rule matches what it should and nothing else
+    #[test]
+    fn rules() {
+        install_log_handler().unwrap();
+
+        let mut engine = AlertEngine::new();
+        engine.set_self_account_name(":self.1234");
+        engine.add_rule(AlertRule {
+            kind: AlertRuleKind::Keyword("ready".to_string()),
+            enabled: true,
+        });
+        engine.add_rule(AlertRule {
+            kind: AlertRuleKind::Keyword("stack on tag".to_string()),
+            enabled: true,
+        });
+        engine.add_rule(AlertRule {
+            kind: AlertRuleKind::Sender(":commander.5678".to_string()),
+            enabled: true,
+        });
+        engine.add_rule(AlertRule {
+            kind: AlertRuleKind::Keyword("+1".to_string()),
+            enabled: true,
+        });
+
+        let mention = Some("Mention".to_string());
+        let ready = Some("Keyword \"ready\"".to_string());
+
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "hi self.1234"),
+            mention
+        );
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "everyone READY?"),
+            ready
+        );
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "already done"),
+            None
+        );
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "Stack On Tag please"),
+            Some("Keyword \"stack on tag\"".to_string())
+        );
+        assert_eq!(
+            check(&mut engine, ":Commander.5678", "Commander", "go go go"),
+            Some("Sender :commander.5678".to_string())
+        );
+
+        // Keywords that start or end with punctuation only need a word boundary on their word character sides
+        let plus_one = Some("Keyword \"+1\"".to_string());
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "+1 from me"),
+            plus_one
+        );
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "me too +1"),
+            plus_one
+        );
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "+10 from me"),
+            None
+        );
+
+        // The character name is only known after self sent a message with it. Messages of self never alert
+        let call_out = "Selfish Character, come here";
+        assert_eq!(check(&mut engine, ":peer.1111", "Peer", call_out), None);
+        assert_eq!(
+            check(&mut engine, ":self.1234", "Selfish Character", "ready"),
+            None
+        );
+        assert_eq!(check(&mut engine, ":peer.1111", "Peer", call_out), mention);
+
+        engine.set_rule_enabled(1, false);
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "everyone ready?"),
+            None
+        );
+        engine.remove_rule(0);
+        assert_eq!(
+            check(&mut engine, ":peer.1111", "Peer", "hi self.1234"),
+            None
+        );
+    }
+}
@@ -1,15 +1,33 @@
 #![allow(non_snake_case)]
 
+use crate::{
+    chat_alerts::AlertEngine,
+    chat_log_storage::{ChatLogStorage, ChatLogStorageSettings},
+    chat_query::ChatQuery,
+    pinboard::Pinboard,
+};
+use arcdps::UserRole;
 use chrono::{DateTime, FixedOffset};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+};
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Channel {
     pub channel_id: u32,
     pub channel_type: arcdps::ChannelType,
     pub subgroup: u8,
 }
 
+// Squad role and subgroup of a message's sender at the time the message was sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SenderInfo {
+    pub role: UserRole,
+    pub subgroup: u8,
+}
+
 #[derive(Debug)]
 pub struct ChatMessage {
     pub is_broadcast: bool,
@@ -17,46 +35,846 @@ pub struct ChatMessage {
     pub account_name: String,
     pub character_name: String,
     pub text: String,
+    // None if the sender wasn't in the squad
+    pub sender: Option<SenderInfo>,
+    // Whether the message matched an alert rule when it was received. Messages loaded from disk never did
+    pub is_alert: bool,
+}
+
+fn split_message(
+    pChatMessage: &arcdps::ChatMessageInfo,
+    pSender: Option<SenderInfo>,
+) -> (Channel, ChatMessage) {
+    (
+        Channel {
+            channel_id: pChatMessage.channel_id,
+            channel_type: pChatMessage.channel_type,
+            subgroup: pChatMessage.subgroup,
+        },
+        ChatMessage {
+            is_broadcast: pChatMessage.is_broadcast,
+            timestamp: pChatMessage.timestamp,
+            account_name: pChatMessage.account_name.to_string(),
+            character_name: pChatMessage.character_name.to_string(),
+            text: pChatMessage.text.to_string(),
+            sender: pSender,
+            is_alert: false,
+        },
+    )
+}
+
+impl ChatMessage {
+    // Approximate memory used by the message, including its strings
+    pub fn size_in_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.account_name.len()
+            + self.character_name.len()
+            + self.text.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ChatLogLimit {
+    Messages(usize),
+    Bytes(usize),
+}
+
+// Once a limit is exceeded the oldest messages are evicted, either from the channel that exceeded its limit or from
+// whichever channel holds the oldest message for the total limit
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ChatLogLimits {
+    pub per_channel: ChatLogLimit,
+    pub total: ChatLogLimit,
+}
+
+impl Default for ChatLogLimits {
+    fn default() -> Self {
+        Self {
+            per_channel: ChatLogLimit::Messages(10_000),
+            total: ChatLogLimit::Bytes(32 * 1024 * 1024),
+        }
+    }
 }
 
-fn split_message(pChatMessage: &arcdps::ChatMessageInfo) -> (Channel, ChatMessage) {
-    (Channel {
-        channel_id: pChatMessage.channel_id,
-        channel_type: pChatMessage.channel_type,
-        subgroup: pChatMessage.subgroup,
-    },
-    ChatMessage {
-        is_broadcast: pChatMessage.is_broadcast,
-        timestamp: pChatMessage.timestamp,
-        account_name: pChatMessage.account_name.to_string(),
-        character_name: pChatMessage.character_name.to_string(),
-        text: pChatMessage.text.to_string(),
-    })
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChatLogMetrics {
+    pub channels: usize,
+    pub messages: usize,
+    pub bytes: usize,
+    pub evicted_messages: u64,
+}
+
+#[derive(Default)]
+struct ChannelLog {
+    // Messages in the order they were added, tagged with a sequence number that is increasing across all channels
+    messages: VecDeque<(u64, ChatMessage)>,
+    bytes: usize,
+}
+
+impl ChannelLog {
+    fn exceeds(&self, pLimit: ChatLogLimit) -> bool {
+        match pLimit {
+            ChatLogLimit::Messages(x) => self.messages.len() > x,
+            ChatLogLimit::Bytes(x) => self.bytes > x,
+        }
+    }
+
+    // Evicts the oldest messages until the channel is within pLimit, keeping at least the newest message. Returns the
+    // evicted messages
+    fn evict(&mut self, pLimit: ChatLogLimit) -> Vec<(u64, ChatMessage)> {
+        let mut evicted = Vec::new();
+        while self.messages.len() > 1 && self.exceeds(pLimit) == true {
+            if let Some(entry) = self.messages.pop_front() {
+                self.bytes -= entry.1.size_in_bytes();
+                evicted.push(entry);
+            }
+        }
+
+        evicted
+    }
+}
+
+// Identifies a message for as long as it hasn't been evicted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageId {
+    channel: Channel,
+    sequence: u64,
+}
+
+// Position of a message in the time ordered index over all channels. Messages with the same timestamp are ordered by
+// the order they were added in
+struct IndexEntry {
+    timestamp: DateTime<FixedOffset>,
+    sequence: u64,
+    channel: Channel,
+}
+
+impl IndexEntry {
+    fn key(&self) -> (DateTime<FixedOffset>, u64) {
+        (self.timestamp, self.sequence)
+    }
+}
+
+// Chunks are split once they grow past twice this size
+const INDEX_CHUNK_SIZE: usize = 512;
+
+// The time ordered index over all channels, split into sorted chunks. A channel at its limit evicts its oldest message,
+// which is anywhere in the index when other channels have older messages, so inserting and removing only move the
+// entries of a single chunk rather than half the index
+#[derive(Default)]
+struct TimeIndex {
+    chunks: Vec<Vec<IndexEntry>>,
+    len: usize,
+}
+
+impl TimeIndex {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // Chunk that holds (or would hold) the entry with pKey, None if the index is empty
+    fn chunk_for(&self, pKey: &(DateTime<FixedOffset>, u64)) -> Option<usize> {
+        if self.chunks.is_empty() {
+            return None;
+        }
+        let chunk = self
+            .chunks
+            .partition_point(|x| x.last().map_or(true, |y| y.key() < *pKey));
+        Some(chunk.min(self.chunks.len() - 1))
+    }
+
+    fn insert(&mut self, pEntry: IndexEntry) {
+        let key = pEntry.key();
+        let chunk_index = match self.chunk_for(&key) {
+            Some(x) => x,
+            None => {
+                self.chunks.push(Vec::new());
+                0
+            }
+        };
+
+        let chunk = &mut self.chunks[chunk_index];
+        let position = chunk.partition_point(|x| x.key() < key);
+        chunk.insert(position, pEntry);
+        if chunk.len() > 2 * INDEX_CHUNK_SIZE {
+            let tail = chunk.split_off(INDEX_CHUNK_SIZE);
+            self.chunks.insert(chunk_index + 1, tail);
+        }
+        self.len += 1;
+    }
+
+    // Returns whether the entry was found
+    fn remove(&mut self, pTimestamp: &DateTime<FixedOffset>, pSequence: u64) -> bool {
+        let key = (*pTimestamp, pSequence);
+        let chunk_index = match self.chunk_for(&key) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let chunk = &mut self.chunks[chunk_index];
+        let position = chunk.partition_point(|x| x.key() < key);
+        if chunk.get(position).map(|x| x.sequence) != Some(pSequence) {
+            return false;
+        }
+        chunk.remove(position);
+        if chunk.is_empty() {
+            self.chunks.remove(chunk_index);
+        }
+        self.len -= 1;
+
+        true
+    }
+
+    // Position of the first entry for which pPredicate is false, the entries have to be partitioned by it
+    fn partition_point(&self, mut pPredicate: impl FnMut(&IndexEntry) -> bool) -> usize {
+        let chunk_index = self
+            .chunks
+            .partition_point(|x| x.last().map_or(true, |y| pPredicate(y)));
+        let before: usize = self.chunks[..chunk_index].iter().map(|x| x.len()).sum();
+
+        before
+            + self
+                .chunks
+                .get(chunk_index)
+                .map_or(0, |x| x.partition_point(pPredicate))
+    }
+
+    // Entries at the positions in pRange, which has to be within the index
+    fn range(&self, pRange: Range<usize>) -> impl Iterator<Item = &IndexEntry> + '_ {
+        let mut first_chunk = self.chunks.len();
+        let mut offset = 0;
+        let mut skipped = 0;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if skipped + chunk.len() > pRange.start {
+                first_chunk = i;
+                offset = pRange.start - skipped;
+                break;
+            }
+            skipped += chunk.len();
+        }
+
+        self.chunks[first_chunk..]
+            .iter()
+            .flatten()
+            .skip(offset)
+            .take(pRange.end.saturating_sub(pRange.start))
+    }
 }
 
 pub struct ChatLog {
-    channels: HashMap<Channel, Vec<ChatMessage>>,
+    channels: HashMap<Channel, ChannelLog>,
+    index: TimeIndex,
+    storage: Option<ChatLogStorage>,
+    alerts: AlertEngine,
+    pinboard: Pinboard,
+    limits: ChatLogLimits,
+    next_sequence: u64,
+    // Changes every time a message is added or evicted
+    generation: u64,
+    // Number of messages received in each channel since startup, including evicted ones. Channels that only have
+    // messages loaded from disk are present with a count of 0
+    received_counts: HashMap<Channel, u64>,
+    total_bytes: usize,
+    evicted_messages: u64,
 }
 
 impl ChatLog {
-    pub fn new() -> Self { Self { channels: HashMap::new() } }
+    pub fn new() -> Self {
+        Self {
+            channels: HashMap::new(),
+            index: TimeIndex::default(),
+            storage: None,
+            alerts: AlertEngine::new(),
+            pinboard: Pinboard::new(),
+            limits: ChatLogLimits::default(),
+            next_sequence: 0,
+            generation: 0,
+            received_counts: HashMap::new(),
+            total_bytes: 0,
+            evicted_messages: 0,
+        }
+    }
 
-    pub fn add(&mut self, pChatMessage: &arcdps::ChatMessageInfo) {
-        let (channel, msg) = split_message(pChatMessage);
+    // Starts writing received messages to disk, after deleting expired files and loading back the most recent history
+    pub fn enable_storage(&mut self, pSettings: ChatLogStorageSettings) {
+        let storage = ChatLogStorage::new(pSettings);
+        storage.cleanup();
+        for (channel, msg) in storage.load_recent() {
+            self.received_counts.entry(channel).or_insert(0);
+            self.insert(channel, msg);
+        }
+
+        self.storage = Some(storage);
+    }
+
+    pub fn add(&mut self, pChatMessage: &arcdps::ChatMessageInfo, pSender: Option<SenderInfo>) {
+        let (channel, mut msg) = split_message(pChatMessage, pSender);
         debug!("Received message {:?} into {:?}", msg, channel);
 
-        let channel = self.channels.entry(channel).or_default();
-        channel.push(msg);
+        if let Some(storage) = &mut self.storage {
+            storage.append(&channel, &msg);
+        }
+
+        msg.is_alert = self.alerts.check(&msg);
+        self.pinboard.add(&channel, &msg);
+        *self.received_counts.entry(channel).or_insert(0) += 1;
+        self.insert(channel, msg);
     }
 
-    pub fn get_all_messages(&self) -> Vec<(&Channel, &ChatMessage)> {
-        let mut result: Vec<(&Channel, &ChatMessage)> = Vec::new();
-        for c in self.channels.iter() {
-            for m in c.1.iter() {
-                result.push((c.0, m));
+    fn insert(&mut self, pChannel: Channel, pMessage: ChatMessage) {
+        let size = pMessage.size_in_bytes();
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.generation += 1;
+
+        self.index.insert(IndexEntry {
+            timestamp: pMessage.timestamp,
+            sequence,
+            channel: pChannel,
+        });
+
+        let channel = self.channels.entry(pChannel).or_default();
+        channel.messages.push_back((sequence, pMessage));
+        channel.bytes += size;
+        self.total_bytes += size;
+
+        let evicted = channel.evict(self.limits.per_channel);
+        self.record_evictions(&evicted);
+        self.enforce_total_limit();
+    }
+
+    fn record_evictions(&mut self, pEvicted: &[(u64, ChatMessage)]) {
+        for (sequence, msg) in pEvicted {
+            if self.index.remove(&msg.timestamp, *sequence) == false {
+                error!("Evicted message {} is missing from the index", sequence);
             }
+
+            self.total_bytes -= msg.size_in_bytes();
+            self.evicted_messages += 1;
+            self.generation += 1;
+        }
+    }
+
+    fn exceeds_total_limit(&self) -> bool {
+        match self.limits.total {
+            ChatLogLimit::Messages(x) => self.index.len() > x,
+            ChatLogLimit::Bytes(x) => self.total_bytes > x,
         }
+    }
+
+    // Evicts the earliest received messages across all channels until the total limit is met again. For live messages
+    // that is the order of their timestamps, for messages loaded from disk not necessarily. The newest message is
+    // always kept, regardless of how small the limit is
+    fn enforce_total_limit(&mut self) {
+        while self.index.len() > 1 && self.exceeds_total_limit() == true {
+            let oldest = self
+                .channels
+                .values_mut()
+                .filter_map(|channel| Some((channel.messages.front()?.0, channel)))
+                .min_by_key(|(sequence, _)| *sequence);
+
+            let (_, channel) = match oldest {
+                Some(x) => x,
+                None => break,
+            };
+            if let Some(entry) = channel.messages.pop_front() {
+                channel.bytes -= entry.1.size_in_bytes();
+                self.record_evictions(&[entry]);
+            }
+        }
+
+        self.channels
+            .retain(|_, channel| channel.messages.is_empty() == false);
+    }
+
+    pub fn get_limits(&self) -> ChatLogLimits {
+        self.limits
+    }
+
+    // Applies the new limits right away, evicting messages if the log is over them
+    pub fn set_limits(&mut self, pLimits: ChatLogLimits) {
+        self.limits = pLimits;
+
+        let evicted = self
+            .channels
+            .values_mut()
+            .flat_map(|channel| channel.evict(pLimits.per_channel))
+            .collect::<Vec<_>>();
+        self.record_evictions(&evicted);
+        self.enforce_total_limit();
+    }
+
+    pub fn get_metrics(&self) -> ChatLogMetrics {
+        ChatLogMetrics {
+            channels: self.channels.len(),
+            messages: self.index.len(),
+            bytes: self.total_bytes,
+            evicted_messages: self.evicted_messages,
+        }
+    }
+
+    pub fn get_alerts(&self) -> &AlertEngine {
+        &self.alerts
+    }
+
+    pub fn get_alerts_mut(&mut self) -> &mut AlertEngine {
+        &mut self.alerts
+    }
+
+    pub fn get_pinboard(&self) -> &Pinboard {
+        &self.pinboard
+    }
 
-        result
+    pub fn get_pinboard_mut(&mut self) -> &mut Pinboard {
+        &mut self.pinboard
     }
-}
\ No newline at end of file
+
+    pub fn get_received_counts(&self) -> &HashMap<Channel, u64> {
+        &self.received_counts
+    }
+
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get_message(&self, pId: MessageId) -> Option<(&Channel, &ChatMessage)> {
+        let (channel, log) = self.channels.get_key_value(&pId.channel)?;
+        let position = log
+            .messages
+            .binary_search_by_key(&pId.sequence, |x| x.0)
+            .ok()?;
+
+        Some((channel, &log.messages[position].1))
+    }
+
+    fn resolve(&self, pEntry: &IndexEntry) -> Option<(&Channel, &ChatMessage)> {
+        self.get_message(MessageId {
+            channel: pEntry.channel,
+            sequence: pEntry.sequence,
+        })
+    }
+
+    // Ids of all messages matching pQuery, ordered by timestamp
+    pub fn query(&self, pQuery: &ChatQuery) -> Vec<MessageId> {
+        let start = match pQuery.since {
+            Some(since) => self.index.partition_point(|x| x.timestamp < since),
+            None => 0,
+        };
+        let end = match pQuery.until {
+            Some(until) => self.index.partition_point(|x| x.timestamp <= until),
+            None => self.index.len(),
+        };
+
+        self.index
+            .range(start..end.max(start))
+            .filter(|entry| {
+                self.resolve(entry)
+                    .map_or(false, |(channel, msg)| pQuery.matches(channel, msg))
+            })
+            .map(|entry| MessageId {
+                channel: entry.channel,
+                sequence: entry.sequence,
+            })
+            .collect()
+    }
+
+    pub fn message_count(&self) -> usize {
+        self.index.len()
+    }
+
+    // Messages ordered by timestamp, limited to the positions in pRange. Positions past the end are ignored
+    pub fn get_messages(
+        &self,
+        pRange: Range<usize>,
+    ) -> impl Iterator<Item = (&Channel, &ChatMessage)> + '_ {
+        let end = pRange.end.min(self.index.len());
+        let start = pRange.start.min(end);
+
+        self.index
+            .range(start..end)
+            .filter_map(move |entry| self.resolve(entry))
+    }
+
+    // All messages ordered by timestamp
+    pub fn get_all_messages(&self) -> Vec<(&Channel, &ChatMessage)> {
+        self.get_messages(0..self.index.len()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::{
+        Channel, ChatLog, ChatLogLimit, ChatLogLimits, ChatMessage, IndexEntry, TimeIndex,
+        INDEX_CHUNK_SIZE,
+    };
+    use crate::chat_query::{ChannelFilter, ChatQuery, TextMatcher};
+    use crate::infra::install_log_handler;
+    use arcdps::ChannelType;
+    use chrono::{DateTime, Duration};
+    use test::{black_box, Bencher};
+
+    fn make_channel(pSubgroup: u8) -> Channel {
+        Channel {
+            channel_id: 1,
+            channel_type: ChannelType::Squad,
+            subgroup: pSubgroup,
+        }
+    }
+
+    fn make_message(pText: &str) -> ChatMessage {
+        ChatMessage {
+            is_broadcast: false,
+            timestamp: DateTime::parse_from_rfc3339("2022-07-09T11:45:24.888Z").unwrap(),
+            account_name: "peer".to_string(),
+            character_name: "character_peer".to_string(),
+            text: pText.to_string(),
+            sender: None,
+            is_alert: false,
+        }
+    }
+
+    fn make_message_at(pText: &str, pSecondsOffset: i64) -> ChatMessage {
+        let mut msg = make_message(pText);
+        msg.timestamp = msg.timestamp + Duration::seconds(pSecondsOffset);
+        msg
+    }
+
+    fn texts(pChatLog: &ChatLog) -> Vec<String> {
+        pChatLog
+            .get_all_messages()
+            .into_iter()
+            .map(|(_, msg)| msg.text.clone())
+            .collect()
+    }
+
+    // Test that each channel only keeps its newest messages once it exceeds the per channel limit
+    #[test]
+    fn per_channel_limit() {
+        install_log_handler().unwrap();
+
+        let mut chatlog = ChatLog::new();
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(2),
+            total: ChatLogLimit::Messages(100),
+        });
+
+        for i in 0..4 {
+            chatlog.insert(make_channel(0), make_message(&format!("a{}", i)));
+        }
+        chatlog.insert(make_channel(1), make_message("b0"));
+
+        assert_eq!(texts(&chatlog), vec!["a2", "a3", "b0"]);
+
+        let metrics = chatlog.get_metrics();
+        assert_eq!(metrics.channels, 2);
+        assert_eq!(metrics.messages, 3);
+        assert_eq!(metrics.evicted_messages, 2);
+        assert_eq!(metrics.bytes, 3 * make_message("a0").size_in_bytes());
+    }
+
+    // Test that the total limit evicts the oldest messages regardless of which channel they are in, and that lowering
+    // the limits evicts right away
+    #[test]
+    fn total_limit() {
+        install_log_handler().unwrap();
+
+        let message_size = make_message("x0").size_in_bytes();
+        let mut chatlog = ChatLog::new();
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(100),
+            total: ChatLogLimit::Bytes(3 * message_size),
+        });
+
+        chatlog.insert(make_channel(0), make_message("a0"));
+        chatlog.insert(make_channel(1), make_message("b0"));
+        chatlog.insert(make_channel(1), make_message("b1"));
+        chatlog.insert(make_channel(0), make_message("a1"));
+        assert_eq!(texts(&chatlog), vec!["b0", "b1", "a1"]);
+
+        chatlog.insert(make_channel(2), make_message("c0"));
+        chatlog.insert(make_channel(2), make_message("c1"));
+        assert_eq!(texts(&chatlog), vec!["a1", "c0", "c1"]);
+        assert_eq!(chatlog.get_metrics().channels, 2);
+
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(1),
+            total: ChatLogLimit::Messages(1),
+        });
+        assert_eq!(texts(&chatlog), vec!["c1"]);
+
+        let metrics = chatlog.get_metrics();
+        assert_eq!(metrics.channels, 1);
+        assert_eq!(metrics.messages, 1);
+        assert_eq!(metrics.bytes, message_size);
+        assert_eq!(metrics.evicted_messages, 5);
+    }
+
+    // Test that messages from all channels are ordered by timestamp even when they arrive out of order, that messages
+    // with equal timestamps keep their arrival order, and that windows into the ordered messages are clamped
+    #[test]
+    fn time_ordered_index() {
+        install_log_handler().unwrap();
+
+        let mut chatlog = ChatLog::new();
+        chatlog.insert(make_channel(0), make_message_at("a", 10));
+        chatlog.insert(make_channel(1), make_message_at("b", 5));
+        chatlog.insert(make_channel(0), make_message_at("c", 20));
+        chatlog.insert(make_channel(2), make_message_at("d", 10));
+        chatlog.insert(make_channel(1), make_message_at("e", 0));
+
+        assert_eq!(chatlog.message_count(), 5);
+        assert_eq!(texts(&chatlog), vec!["e", "b", "a", "d", "c"]);
+
+        let window = chatlog
+            .get_messages(1..3)
+            .map(|(channel, msg)| (channel.subgroup, msg.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(window, vec![(1, "b"), (0, "a")]);
+
+        assert_eq!(chatlog.get_messages(4..100).count(), 1);
+        assert_eq!(chatlog.get_messages(7..100).count(), 0);
+
+        // Evicting from the middle of the index keeps the rest in order
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(1),
+            total: ChatLogLimit::Messages(100),
+        });
+        assert_eq!(texts(&chatlog), vec!["e", "d", "c"]);
+    }
+
+    // Test that the index stays ordered across chunk splits and removals, with inserts and removals all over it
+    #[test]
+    fn time_index_chunks() {
+        let base = make_message("x").timestamp;
+        let count = 5 * INDEX_CHUNK_SIZE as u64;
+        // Spreads the timestamps over the index in a fixed but scattered order, with some duplicates
+        let timestamp =
+            |pSequence: u64| base + Duration::seconds(((pSequence * 7919) % (count / 2)) as i64);
+
+        let mut index = TimeIndex::default();
+        let mut expected = Vec::new();
+        for sequence in 0..count {
+            index.insert(IndexEntry {
+                timestamp: timestamp(sequence),
+                sequence,
+                channel: make_channel(0),
+            });
+            expected.push((timestamp(sequence), sequence));
+        }
+        assert!(index.chunks.len() > 1);
+
+        for sequence in (0..count).filter(|x| x % 3 == 0) {
+            assert!(index.remove(&timestamp(sequence), sequence));
+        }
+        assert_eq!(index.remove(&timestamp(0), 0), false);
+        expected.retain(|(_, sequence)| sequence % 3 != 0);
+        expected.sort();
+
+        assert_eq!(index.len(), expected.len());
+        let keys = index
+            .range(0..index.len())
+            .map(|x| x.key())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+
+        let window = index.range(1000..1010).map(|x| x.key()).collect::<Vec<_>>();
+        assert_eq!(window, expected[1000..1010]);
+        assert_eq!(index.range(index.len()..index.len() + 5).count(), 0);
+
+        let middle = expected[expected.len() / 2].0;
+        assert_eq!(
+            index.partition_point(|x| x.timestamp < middle),
+            expected.partition_point(|x| x.0 < middle)
+        );
+    }
+
+    fn query_texts(pChatLog: &ChatLog, pQuery: &ChatQuery) -> Vec<String> {
+        pChatLog
+            .query(pQuery)
+            .into_iter()
+            .map(|id| pChatLog.get_message(id).unwrap().1.text.clone())
+            .collect()
+    }
+
+    // Test each query condition on its own and combined
+    #[test]
+    fn query() {
+        install_log_handler().unwrap();
+
+        let mut chatlog = ChatLog::new();
+        let party = Channel {
+            channel_id: 2,
+            channel_type: ChannelType::Party,
+            subgroup: u8::MAX,
+        };
+        let squad = make_channel(u8::MAX);
+
+        chatlog.insert(squad, make_message_at("Pull in 10", 0));
+        chatlog.insert(make_channel(1), make_message_at("need might", 10));
+        let mut broadcast = make_message_at("PULL TIMER 5", 20);
+        broadcast.is_broadcast = true;
+        broadcast.account_name = "commander".to_string();
+        broadcast.character_name = "Commander Character".to_string();
+        chatlog.insert(squad, broadcast);
+        chatlog.insert(party, make_message_at("pulling now", 30));
+
+        let mut query = ChatQuery::default();
+        assert_eq!(query_texts(&chatlog, &query).len(), 4);
+
+        query.text = Some(TextMatcher::new("pull", false).unwrap());
+        assert_eq!(
+            query_texts(&chatlog, &query),
+            vec!["Pull in 10", "PULL TIMER 5", "pulling now"]
+        );
+
+        query.text = Some(TextMatcher::new(r"^pull\b.*\d+$", true).unwrap());
+        assert_eq!(
+            query_texts(&chatlog, &query),
+            vec!["Pull in 10", "PULL TIMER 5"]
+        );
+        assert!(TextMatcher::new("pull(", true).is_err());
+
+        query.text = None;
+        query.channel = ChannelFilter::Party;
+        assert_eq!(query_texts(&chatlog, &query), vec!["pulling now"]);
+        query.channel = ChannelFilter::Squad;
+        assert_eq!(query_texts(&chatlog, &query).len(), 3);
+        query.channel = ChannelFilter::SquadWide;
+        assert_eq!(query_texts(&chatlog, &query).len(), 2);
+        query.channel = ChannelFilter::Subgroup(1);
+        assert_eq!(query_texts(&chatlog, &query), vec!["need might"]);
+
+        query.channel = ChannelFilter::Any;
+        query.is_broadcast = Some(true);
+        assert_eq!(query_texts(&chatlog, &query), vec!["PULL TIMER 5"]);
+        query.is_broadcast = None;
+
+        query.account_name = Some("COMMAND".to_string());
+        assert_eq!(query_texts(&chatlog, &query), vec!["PULL TIMER 5"]);
+        query.account_name = None;
+        query.character_name = Some("character_peer".to_string());
+        assert_eq!(query_texts(&chatlog, &query).len(), 3);
+        query.character_name = None;
+
+        let base = make_message("").timestamp;
+        query.since = Some(base + Duration::seconds(10));
+        query.until = Some(base + Duration::seconds(20));
+        assert_eq!(
+            query_texts(&chatlog, &query),
+            vec!["need might", "PULL TIMER 5"]
+        );
+
+        query.text = Some(TextMatcher::new("pull", false).unwrap());
+        assert_eq!(query_texts(&chatlog, &query), vec!["PULL TIMER 5"]);
+    }
+
+    // Test that a message keeps whether it alerted when it was received, even after the rules changed
+    #[test]
+    fn alert_is_kept_on_message() {
+        install_log_handler().unwrap();
+
+        let mut chatlog = ChatLog::new();
+        chatlog.get_alerts_mut().set_self_account_name(":self.1234");
+        let mut add = |pText: &str| {
+            chatlog.add(
+                &arcdps::ChatMessageInfo {
+                    channel_id: 1,
+                    channel_type: ChannelType::Squad,
+                    subgroup: u8::MAX,
+                    is_broadcast: false,
+                    timestamp: make_message("").timestamp,
+                    account_name: ":peer.1111",
+                    character_name: "Peer",
+                    text: pText,
+                },
+                None,
+            )
+        };
+        add("hi self.1234");
+        add("hi everyone");
+        chatlog.get_alerts_mut().set_rules(Vec::new());
+
+        let alerts = chatlog
+            .get_all_messages()
+            .into_iter()
+            .map(|(_, msg)| msg.is_alert)
+            .collect::<Vec<_>>();
+        assert_eq!(alerts, vec![true, false]);
+        assert_eq!(chatlog.get_alerts_mut().drain_alerts().len(), 1);
+    }
+
+    fn filled_chat_log(pMessageCount: usize) -> ChatLog {
+        let mut chatlog = ChatLog::new();
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(pMessageCount),
+            total: ChatLogLimit::Messages(pMessageCount),
+        });
+        for i in 0..pMessageCount {
+            chatlog.insert(
+                make_channel((i % 5) as u8),
+                make_message_at(&format!("message {}", i), i as i64),
+            );
+        }
+
+        chatlog
+    }
+
+    // Cost of what the chat log window does every frame - fetching one screen of rows at the current scroll position.
+    // Should be the same regardless of how many messages are in the log
+    fn bench_visible_window(pBencher: &mut Bencher, pMessageCount: usize) {
+        let chatlog = filled_chat_log(pMessageCount);
+        let start = pMessageCount / 2;
+
+        pBencher.iter(|| {
+            black_box(chatlog.get_messages(start..start + 50).count());
+        });
+    }
+
+    #[bench]
+    fn visible_window_1k(pBencher: &mut Bencher) {
+        bench_visible_window(pBencher, 1_000);
+    }
+
+    #[bench]
+    fn visible_window_100k(pBencher: &mut Bencher) {
+        bench_visible_window(pBencher, 100_000);
+    }
+
+    // Cost of receiving a message into a full log, which evicts the oldest message
+    #[bench]
+    fn insert_full_100k(pBencher: &mut Bencher) {
+        let mut chatlog = filled_chat_log(100_000);
+        let mut i = 100_000;
+
+        pBencher.iter(|| {
+            chatlog.insert(make_channel(0), make_message_at("new message", i));
+            i += 1;
+        });
+    }
+
+    // Cost of receiving a message into a channel that is at its per channel limit while another channel has older
+    // messages, so the evicted message is in the middle of the index
+    #[bench]
+    fn insert_channel_full_100k(pBencher: &mut Bencher) {
+        let mut chatlog = ChatLog::new();
+        chatlog.set_limits(ChatLogLimits {
+            per_channel: ChatLogLimit::Messages(50_000),
+            total: ChatLogLimit::Messages(200_000),
+        });
+        for i in 0..100_000 {
+            chatlog.insert(
+                make_channel((i / 50_000) as u8),
+                make_message_at(&format!("message {}", i), i as i64),
+            );
+        }
+        let mut i = 100_000;
+
+        pBencher.iter(|| {
+            chatlog.insert(make_channel(1), make_message_at("new message", i));
+            i += 1;
+        });
+    }
+}
@@ -0,0 +1,490 @@
+#![allow(non_snake_case)]
+
+//! On-disk storage of chat messages. Messages are appended to one file per (local) day, named
+//! `chat_<%Y-%m-%d>.log`. Once a file reaches the configured size, writing continues in `chat_<%Y-%m-%d>.1.log`,
+//! `chat_<%Y-%m-%d>.2.log` and so on. Files older than the retention period are deleted on startup.
+//!
+//! Every line is one message with tab separated fields:
+//! `<RFC 3339 timestamp> <channel type> <channel id> <subgroup> <flags> <account name> <character name> <text>
+//! <sender role> <sender subgroup>`
+//! - channel type is "party" or "squad"
+//! - subgroup is "S" for the whole squad/party, otherwise the subgroup number as shown in game (1-based)
+//! - flags is "B" for broadcasts, "-" otherwise
+//! - sender role is the squad role of the sender when the message was sent, as in the exports ("squad_leader",
+//!   "lieutenant", "member", ...), "-" if the sender wasn't in the squad
+//! - sender subgroup is the sender's subgroup number (1-based) when the message was sent, "-" if the sender wasn't in
+//!   the squad
+//!
+//! Lines written before the sender fields were added only have the first 8 fields.
+//!
+//! Backslashes, tabs and line breaks inside fields are escaped as `\\`, `\t`, `\n` and `\r`.
+
+use crate::{
+    chat_log::{Channel, ChatMessage, SenderInfo},
+    export::{role_from_str, role_to_str},
+    persistence::addon_path,
+};
+use arcdps::ChannelType;
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ChatLogStorageSettings {
+    pub directory: PathBuf,
+    // Files from days further back than this are deleted
+    pub retention_days: u32,
+    // Size in bytes after which a new file is started for the same day
+    pub max_file_size: u64,
+    // Number of most recent messages loaded back into memory on startup
+    pub history_to_load: usize,
+}
+
+impl Default for ChatLogStorageSettings {
+    fn default() -> Self {
+        Self {
+            directory: addon_path("chat_logs"),
+            retention_days: 14,
+            max_file_size: 16 * 1024 * 1024,
+            history_to_load: 1000,
+        }
+    }
+}
+
+struct OpenFile {
+    date: NaiveDate,
+    index: u32,
+    file: File,
+    size: u64,
+}
+
+pub struct ChatLogStorage {
+    settings: ChatLogStorageSettings,
+    current_file: Option<OpenFile>,
+}
+
+fn escape_field(pField: &str) -> String {
+    let mut result = String::with_capacity(pField.len());
+    for c in pField.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+fn unescape_field(pField: &str) -> String {
+    let mut result = String::with_capacity(pField.len());
+    let mut chars = pField.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(x) => result.push(x),
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+pub fn format_line(pChannel: &Channel, pMessage: &ChatMessage) -> Option<String> {
+    let channel_type = match pChannel.channel_type {
+        ChannelType::Party => "party",
+        ChannelType::Squad => "squad",
+        _ => return None,
+    };
+    let subgroup = if pChannel.subgroup == u8::MAX {
+        "S".to_string()
+    } else {
+        (pChannel.subgroup as u32 + 1).to_string()
+    };
+
+    let (sender_role, sender_subgroup) = match pMessage.sender {
+        Some(x) => (role_to_str(x.role), (x.subgroup as u32 + 1).to_string()),
+        None => ("-", "-".to_string()),
+    };
+
+    Some(format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        pMessage.timestamp.to_rfc3339(),
+        channel_type,
+        pChannel.channel_id,
+        subgroup,
+        if pMessage.is_broadcast { "B" } else { "-" },
+        escape_field(&pMessage.account_name),
+        escape_field(&pMessage.character_name),
+        escape_field(&pMessage.text),
+        sender_role,
+        sender_subgroup,
+    ))
+}
+
+pub fn parse_line(pLine: &str) -> Option<(Channel, ChatMessage)> {
+    let fields = pLine
+        .trim_end_matches(|c| c == '\r' || c == '\n')
+        .split('\t')
+        .collect::<Vec<_>>();
+    if fields.len() != 8 && fields.len() != 10 {
+        return None;
+    }
+
+    let timestamp = DateTime::parse_from_rfc3339(fields[0]).ok()?;
+    let channel_type = match fields[1] {
+        "party" => ChannelType::Party,
+        "squad" => ChannelType::Squad,
+        _ => return None,
+    };
+    let channel_id = fields[2].parse::<u32>().ok()?;
+    let subgroup = if fields[3] == "S" {
+        u8::MAX
+    } else {
+        fields[3].parse::<u8>().ok()?.checked_sub(1)?
+    };
+    let sender = match fields.get(8).zip(fields.get(9)) {
+        Some((&"-", _)) | None => None,
+        Some((role, subgroup)) => Some(SenderInfo {
+            role: role_from_str(role)?,
+            subgroup: subgroup.parse::<u8>().ok()?.checked_sub(1)?,
+        }),
+    };
+
+    Some((
+        Channel {
+            channel_id,
+            channel_type,
+            subgroup,
+        },
+        ChatMessage {
+            is_broadcast: fields[4] == "B",
+            timestamp,
+            account_name: unescape_field(fields[5]),
+            character_name: unescape_field(fields[6]),
+            text: unescape_field(fields[7]),
+            sender,
+            is_alert: false,
+        },
+    ))
+}
+
+// Returns the date and rotation index of a chat log file, or None if the file name doesn't belong to a chat log
+fn parse_file_name(pFileName: &str) -> Option<(NaiveDate, u32)> {
+    let stem = pFileName.strip_prefix("chat_")?.strip_suffix(".log")?;
+    let (date, index) = match stem.split_once('.') {
+        Some((date, index)) => (date, index.parse::<u32>().ok()?),
+        None => (stem, 0),
+    };
+
+    Some((NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?, index))
+}
+
+impl ChatLogStorage {
+    pub fn new(pSettings: ChatLogStorageSettings) -> Self {
+        Self {
+            settings: pSettings,
+            current_file: None,
+        }
+    }
+
+    fn file_path(&self, pDate: &NaiveDate, pIndex: u32) -> PathBuf {
+        let date = pDate.format("%Y-%m-%d");
+        if pIndex == 0 {
+            self.settings.directory.join(format!("chat_{}.log", date))
+        } else {
+            self.settings
+                .directory
+                .join(format!("chat_{}.{}.log", date, pIndex))
+        }
+    }
+
+    // All chat log files in the directory, ordered from oldest to newest
+    fn list_files(&self) -> Vec<(NaiveDate, u32, PathBuf)> {
+        let entries = match fs::read_dir(&self.settings.directory) {
+            Ok(x) => x,
+            Err(e) => {
+                info!("Failed to list {:?} - {:?}", self.settings.directory, e);
+                return Vec::new();
+            }
+        };
+
+        let mut files = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let (date, index) = parse_file_name(file_name.to_str()?)?;
+                Some((date, index, entry.path()))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        files
+    }
+
+    // Deletes files of days that are older than the retention period
+    pub fn cleanup(&self) {
+        let oldest_kept = Local::now().naive_local().date()
+            - chrono::Duration::days(self.settings.retention_days.into());
+
+        for (date, _, path) in self.list_files() {
+            if date >= oldest_kept {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(_) => info!("Deleted old chat log {:?}", path),
+                Err(e) => warn!("Failed to delete old chat log {:?} - {:?}", path, e),
+            }
+        }
+    }
+
+    // Reads back the most recent messages, ordered from oldest to newest
+    pub fn load_recent(&self) -> Vec<(Channel, ChatMessage)> {
+        let mut result: Vec<(Channel, ChatMessage)> = Vec::new();
+
+        for (_, _, path) in self.list_files().into_iter().rev() {
+            if result.len() >= self.settings.history_to_load {
+                break;
+            }
+
+            let file = match File::open(&path) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Failed to open {:?} - {:?}", path, e);
+                    continue;
+                }
+            };
+
+            let mut messages = BufReader::new(file)
+                .lines()
+                .filter_map(|line| line.ok())
+                .filter_map(|line| parse_line(&line))
+                .collect::<Vec<_>>();
+
+            let remaining = self.settings.history_to_load - result.len();
+            let skip = messages.len().saturating_sub(remaining);
+            let mut older = messages.split_off(skip);
+            older.append(&mut result);
+            result = older;
+        }
+
+        info!(
+            "Loaded {} chat messages from {:?}",
+            result.len(),
+            self.settings.directory
+        );
+        result
+    }
+
+    fn open_file(&self, pDate: NaiveDate) -> Option<OpenFile> {
+        if let Err(e) = fs::create_dir_all(&self.settings.directory) {
+            warn!(
+                "Failed to create directory {:?} - {:?}",
+                self.settings.directory, e
+            );
+            return None;
+        }
+
+        // Continue in the newest file of the day, or start a new one if it's full
+        let mut index = 0;
+        while self.file_path(&pDate, index + 1).exists() {
+            index += 1;
+        }
+        let mut path = self.file_path(&pDate, index);
+        let mut size = fs::metadata(&path).map(|x| x.len()).unwrap_or(0);
+        if size >= self.settings.max_file_size {
+            index += 1;
+            path = self.file_path(&pDate, index);
+            size = 0;
+        }
+
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                debug!("Opened chat log {:?}", path);
+                Some(OpenFile {
+                    date: pDate,
+                    index,
+                    file,
+                    size,
+                })
+            }
+            Err(e) => {
+                warn!("Failed to open {:?} - {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn append(&mut self, pChannel: &Channel, pMessage: &ChatMessage) {
+        let line = match format_line(pChannel, pMessage) {
+            Some(x) => x,
+            None => return,
+        };
+        let date = pMessage
+            .timestamp
+            .with_timezone(&Local)
+            .naive_local()
+            .date();
+
+        let needs_new_file = match &self.current_file {
+            Some(x) => x.date != date || x.size + line.len() as u64 > self.settings.max_file_size,
+            None => true,
+        };
+        if needs_new_file == true {
+            // Rotating within the same day moves on to the next index
+            let next_index = self
+                .current_file
+                .as_ref()
+                .filter(|x| x.date == date)
+                .map(|x| x.index + 1);
+            self.current_file = match next_index {
+                Some(index) => {
+                    let path = self.file_path(&date, index);
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .map_err(|e| warn!("Failed to open {:?} - {:?}", path, e))
+                        .ok()
+                        .map(|file| OpenFile {
+                            date,
+                            index,
+                            file,
+                            size: 0,
+                        })
+                }
+                None => self.open_file(date),
+            };
+        }
+
+        if let Some(current_file) = &mut self.current_file {
+            match current_file.file.write_all(line.as_bytes()) {
+                Ok(_) => current_file.size += line.len() as u64,
+                Err(e) => warn!("Failed to write chat message - {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_line, parse_line, ChatLogStorage, ChatLogStorageSettings};
+    use crate::chat_log::{Channel, ChatMessage, SenderInfo};
+    use crate::infra::install_log_handler;
+    use arcdps::{ChannelType, UserRole};
+    use chrono::DateTime;
+
+    fn make_message(pText: &str) -> (Channel, ChatMessage) {
+        (
+            Channel {
+                channel_id: 7,
+                channel_type: ChannelType::Squad,
+                subgroup: 2,
+            },
+            ChatMessage {
+                is_broadcast: true,
+                timestamp: DateTime::parse_from_rfc3339("2022-07-09T11:45:24.888+02:00").unwrap(),
+                account_name: "peer.1234".to_string(),
+                character_name: "Peer Character".to_string(),
+                text: pText.to_string(),
+                sender: Some(SenderInfo {
+                    role: UserRole::Lieutenant,
+                    subgroup: 4,
+                }),
+                is_alert: false,
+            },
+        )
+    }
+
+    // Test that messages survive being written and read back, including characters that need escaping
+    #[test]
+    fn line_roundtrip() {
+        install_log_handler().unwrap();
+
+        let (channel, message) = make_message("tab\there\nnewline\r\\ and a \\\\t literal");
+        let line = format_line(&channel, &message).unwrap();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(line.matches('\t').count(), 9);
+
+        let (parsed_channel, parsed_message) = parse_line(&line).unwrap();
+        assert_eq!(parsed_channel, channel);
+        assert_eq!(parsed_message.is_broadcast, message.is_broadcast);
+        assert_eq!(parsed_message.timestamp, message.timestamp);
+        assert_eq!(parsed_message.account_name, message.account_name);
+        assert_eq!(parsed_message.character_name, message.character_name);
+        assert_eq!(parsed_message.text, message.text);
+        assert_eq!(parsed_message.sender, message.sender);
+
+        // Lines from before the sender fields existed
+        let old_line = line.splitn(9, '\t').take(8).collect::<Vec<_>>().join("\t");
+        let (_, old_message) = parse_line(&old_line).unwrap();
+        assert_eq!(old_message.text, message.text);
+        assert_eq!(old_message.sender, None);
+    }
+
+    // Test that files are rotated once they reach the size limit and that only the most recent messages are loaded back
+    #[test]
+    fn rotation_and_load_recent() {
+        install_log_handler().unwrap();
+
+        let directory = std::env::temp_dir().join("arcdps_squad_manager_chat_log_storage_test");
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let line_length = format_line(&make_message("message 0").0, &make_message("message 0").1)
+            .unwrap()
+            .len() as u64;
+        let settings = ChatLogStorageSettings {
+            directory: directory.clone(),
+            retention_days: 14,
+            max_file_size: line_length * 3,
+            history_to_load: 5,
+        };
+
+        {
+            let mut storage = ChatLogStorage::new(settings.clone());
+            for i in 0..8 {
+                let (channel, message) = make_message(&format!("message {}", i));
+                storage.append(&channel, &message);
+            }
+        }
+
+        // 8 messages with 3 per file
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 3);
+
+        let storage = ChatLogStorage::new(settings);
+        let loaded = storage
+            .load_recent()
+            .into_iter()
+            .map(|(_, message)| message.text)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            loaded,
+            vec![
+                "message 3",
+                "message 4",
+                "message 5",
+                "message 6",
+                "message 7"
+            ]
+        );
+
+        // Files from 2022 are far outside the retention period
+        storage.cleanup();
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(non_snake_case)]
+
+use crate::chat_log::{Channel, ChatMessage};
+use arcdps::ChannelType;
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+
+pub enum TextMatcher {
+    // Case insensitive substring
+    Substring(String),
+    Regex(Regex),
+}
+
+impl TextMatcher {
+    // Builds a case insensitive matcher. Returns the error message of the regex crate if pPattern isn't a valid regex
+    pub fn new(pPattern: &str, pIsRegex: bool) -> Result<Self, String> {
+        if pIsRegex == true {
+            regex::RegexBuilder::new(pPattern)
+                .case_insensitive(true)
+                .build()
+                .map(TextMatcher::Regex)
+                .map_err(|e| e.to_string())
+        } else {
+            Ok(TextMatcher::Substring(pPattern.to_lowercase()))
+        }
+    }
+
+    pub fn matches(&self, pText: &str) -> bool {
+        match self {
+            TextMatcher::Substring(x) => pText.to_lowercase().contains(x),
+            TextMatcher::Regex(x) => x.is_match(pText),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelFilter {
+    Any,
+    Party,
+    // Squad chat, both squad wide and in subgroups
+    Squad,
+    // Squad chat that was sent to the whole squad
+    SquadWide,
+    // Squad chat sent to one subgroup (0-based)
+    Subgroup(u8),
+}
+
+impl ChannelFilter {
+    pub fn matches(&self, pChannel: &Channel) -> bool {
+        match self {
+            ChannelFilter::Any => true,
+            ChannelFilter::Party => pChannel.channel_type == ChannelType::Party,
+            ChannelFilter::Squad => pChannel.channel_type == ChannelType::Squad,
+            ChannelFilter::SquadWide => {
+                pChannel.channel_type == ChannelType::Squad && pChannel.subgroup == u8::MAX
+            }
+            ChannelFilter::Subgroup(x) => {
+                pChannel.channel_type == ChannelType::Squad && pChannel.subgroup == *x
+            }
+        }
+    }
+}
+
+// A set of conditions a message has to fulfill to be returned by ChatLog::query. Unset conditions match everything
+pub struct ChatQuery {
+    pub text: Option<TextMatcher>,
+    // Case insensitive substrings of the sender names
+    pub account_name: Option<String>,
+    pub character_name: Option<String>,
+    pub channel: ChannelFilter,
+    pub is_broadcast: Option<bool>,
+    // Inclusive bounds on the message timestamp
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+}
+
+impl Default for ChatQuery {
+    fn default() -> Self {
+        Self {
+            text: None,
+            account_name: None,
+            character_name: None,
+            channel: ChannelFilter::Any,
+            is_broadcast: None,
+            since: None,
+            until: None,
+        }
+    }
+}
+
+fn contains_ignore_case(pHaystack: &str, pNeedle: &Option<String>) -> bool {
+    match pNeedle {
+        Some(needle) => pHaystack.to_lowercase().contains(&needle.to_lowercase()),
+        None => true,
+    }
+}
+
+impl ChatQuery {
+    pub fn matches(&self, pChannel: &Channel, pMessage: &ChatMessage) -> bool {
+        if self.channel.matches(pChannel) == false {
+            return false;
+        }
+        if let Some(is_broadcast) = self.is_broadcast {
+            if pMessage.is_broadcast != is_broadcast {
+                return false;
+            }
+        }
+        if self.since.map_or(false, |x| pMessage.timestamp < x) == true
+            || self.until.map_or(false, |x| pMessage.timestamp > x) == true
+        {
+            return false;
+        }
+        if contains_ignore_case(&pMessage.account_name, &self.account_name) == false
+            || contains_ignore_case(&pMessage.character_name, &self.character_name) == false
+        {
+            return false;
+        }
+
+        match &self.text {
+            Some(text) => text.matches(&pMessage.text),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+#![allow(non_snake_case)]
+
+use chrono::{DateTime, Local};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Source of the current time. Monotonic time is used for measuring durations, local time for anything that is shown
+// to the user or written to disk
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn now_local(&self) -> DateTime<Local>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_local(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+// A clock that only moves when advanced, for tests and replaying recordings. Clones share the same time, so the
+// caller can keep one and hand another to the code under test
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Arc<Mutex<(Instant, DateTime<Local>)>>,
+}
+
+impl ManualClock {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::starting_at(Local::now())
+    }
+
+    pub fn starting_at(pLocalTime: DateTime<Local>) -> Self {
+        Self {
+            time: Arc::new(Mutex::new((Instant::now(), pLocalTime))),
+        }
+    }
+
+    pub fn advance(&self, pDuration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.0 += pDuration;
+        time.1 = time.1 + chrono::Duration::from_std(pDuration).unwrap();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap().0
+    }
+
+    fn now_local(&self) -> DateTime<Local> {
+        self.time.lock().unwrap().1
+    }
+}
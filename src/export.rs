@@ -0,0 +1,572 @@
+#![allow(non_snake_case)]
+
+//! Exports of the squad roster and chat history, for consumption by external tools.
+//!
+//! Every export writes the same rows to a CSV file and a JSON file in `addons/arcdps_squad_manager/exports`, named
+//! `<kind>_<local time as %Y%m%d_%H%M%S>.{csv,json}`, with a `_2`, `_3`, ... suffix for further exports of the same
+//! kind within the same second (reports that are updated over time, such as attendance, replace their file instead).
+//! The schema below is versioned through `schema_version`; fields are only ever added at the end of a row, a breaking
+//! change bumps the version.
+//!
+//! `squad_roster` (schema_version 1), one row per squad member:
+//! - `account_name` - string
+//! - `role` - one of "squad_leader", "lieutenant", "member"
+//! - `subgroup` - subgroup number as shown in game (1-based)
+//! - `join_time` - time the member joined the squad, as reported by the game (seconds since the unix epoch)
+//! - `is_ready` - bool
+//! - `total_ready_check_time_ms` - integer, total time spent unready in successful ready checks
+//! - `last_unready_duration_ms` - integer or empty/null, time spent unready in the last successful ready check
+//!
+//! `chat_log` (schema_version 1), one row per chat message ordered by timestamp:
+//! - `timestamp` - RFC 3339 timestamp
+//! - `channel_type` - one of "party", "squad", "unknown"
+//! - `channel_id` - integer
+//! - `subgroup` - integer or empty/null, subgroup number (1-based) for subgroup chat, empty for the whole squad/party
+//! - `is_broadcast` - bool
+//! - `account_name` - string
+//! - `character_name` - string
+//! - `text` - string
+//! - `sender_role` - role of the sender in the squad when the message was sent (same values as `role` in
+//!   `squad_roster`), empty/null if the sender wasn't in the squad
+//! - `sender_subgroup` - integer or empty/null, subgroup number (1-based) of the sender when the message was sent
+//!
+//! The JSON files are objects of the form `{"schema_version": 1, "exported_at": <RFC 3339>, "rows": [...]}`. The CSV
+//! files have a header row with the field names, booleans are written as "true"/"false" and strings are quoted
+//! according to RFC 4180 when needed.
+
+use crate::{
+    chat_log::ChatLog,
+    persistence::{addon_path, free_file_name, save_json},
+    squad_tracker::SquadTracker,
+};
+use arcdps::{ChannelType, UserRole};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+// What export_rows does when files of the same name already exist
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportMode {
+    // Keep them and write to the first free name with a _2, _3, ... suffix
+    New,
+    // Replace them, for reports that are updated over time
+    Replace,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportFile<T: Serialize> {
+    schema_version: u32,
+    exported_at: String,
+    rows: Vec<T>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RosterRow {
+    pub account_name: String,
+    pub role: &'static str,
+    pub subgroup: u8,
+    pub join_time: u64,
+    pub is_ready: bool,
+    pub total_ready_check_time_ms: u128,
+    pub last_unready_duration_ms: Option<u128>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatRow {
+    pub timestamp: String,
+    pub channel_type: &'static str,
+    pub channel_id: u32,
+    pub subgroup: Option<u8>,
+    pub is_broadcast: bool,
+    pub account_name: String,
+    pub character_name: String,
+    pub text: String,
+    pub sender_role: Option<&'static str>,
+    pub sender_subgroup: Option<u8>,
+}
+
+pub trait CsvRow {
+    const HEADER: &'static [&'static str];
+    fn fields(&self) -> Vec<String>;
+}
+
+impl CsvRow for RosterRow {
+    const HEADER: &'static [&'static str] = &[
+        "account_name",
+        "role",
+        "subgroup",
+        "join_time",
+        "is_ready",
+        "total_ready_check_time_ms",
+        "last_unready_duration_ms",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.account_name.clone(),
+            self.role.to_string(),
+            self.subgroup.to_string(),
+            self.join_time.to_string(),
+            self.is_ready.to_string(),
+            self.total_ready_check_time_ms.to_string(),
+            self.last_unready_duration_ms
+                .map(|x| x.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+impl CsvRow for ChatRow {
+    const HEADER: &'static [&'static str] = &[
+        "timestamp",
+        "channel_type",
+        "channel_id",
+        "subgroup",
+        "is_broadcast",
+        "account_name",
+        "character_name",
+        "text",
+        "sender_role",
+        "sender_subgroup",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.timestamp.clone(),
+            self.channel_type.to_string(),
+            self.channel_id.to_string(),
+            self.subgroup.map(|x| x.to_string()).unwrap_or_default(),
+            self.is_broadcast.to_string(),
+            self.account_name.clone(),
+            self.character_name.clone(),
+            self.text.clone(),
+            self.sender_role.unwrap_or_default().to_string(),
+            self.sender_subgroup
+                .map(|x| x.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn role_to_str(pRole: UserRole) -> &'static str {
+    match pRole {
+        UserRole::SquadLeader => "squad_leader",
+        UserRole::Lieutenant => "lieutenant",
+        UserRole::Member => "member",
+        UserRole::Invited => "invited",
+        UserRole::Applied => "applied",
+        UserRole::None => "none",
+        UserRole::Invalid => "invalid",
+    }
+}
+
+pub fn role_from_str(pRole: &str) -> Option<UserRole> {
+    match pRole {
+        "squad_leader" => Some(UserRole::SquadLeader),
+        "lieutenant" => Some(UserRole::Lieutenant),
+        "member" => Some(UserRole::Member),
+        "invited" => Some(UserRole::Invited),
+        "applied" => Some(UserRole::Applied),
+        "none" => Some(UserRole::None),
+        "invalid" => Some(UserRole::Invalid),
+        _ => None,
+    }
+}
+
+pub fn channel_type_to_str(pChannelType: ChannelType) -> &'static str {
+    match pChannelType {
+        ChannelType::Party => "party",
+        ChannelType::Squad => "squad",
+        _ => "unknown",
+    }
+}
+
+pub fn channel_type_from_str(pChannelType: &str) -> Option<ChannelType> {
+    match pChannelType {
+        "party" => Some(ChannelType::Party),
+        "squad" => Some(ChannelType::Squad),
+        _ => None,
+    }
+}
+
+pub fn export_directory() -> PathBuf {
+    addon_path("exports")
+}
+
+fn escape_csv_field(pField: &str) -> String {
+    if pField.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", pField.replace('"', "\"\""))
+    } else {
+        pField.to_string()
+    }
+}
+
+fn write_csv<T: CsvRow>(pPath: &Path, pRows: &[T], pMode: ExportMode) -> bool {
+    let mut options = OpenOptions::new();
+    match pMode {
+        ExportMode::New => options.write(true).create_new(true),
+        ExportMode::Replace => options.write(true).create(true).truncate(true),
+    };
+    let file = match options.open(pPath) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Failed to create {:?} - {:?}", pPath, e);
+            return false;
+        }
+    };
+    let mut writer = BufWriter::new(file);
+
+    let mut lines = vec![T::HEADER.join(",")];
+    for row in pRows {
+        lines.push(
+            row.fields()
+                .iter()
+                .map(|x| escape_csv_field(x))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    }
+
+    for line in lines {
+        if let Err(e) = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.write_all(b"\r\n"))
+        {
+            warn!("Failed to write to {:?} - {:?}", pPath, e);
+            return false;
+        }
+    }
+    if let Err(e) = writer.flush() {
+        warn!("Failed to write to {:?} - {:?}", pPath, e);
+        return false;
+    }
+
+    true
+}
+
+pub fn export_file_name(pKind: &str, pTime: &DateTime<Local>) -> String {
+    format!("{}_{}", pKind, pTime.format("%Y%m%d_%H%M%S"))
+}
+
+// Writes pRows as both CSV and JSON (pBaseName + ".csv" and pBaseName + ".json") and returns the path of the CSV file
+pub fn export_rows<T: CsvRow + Serialize>(
+    pDirectory: &Path,
+    pBaseName: &str,
+    pNow: &DateTime<Local>,
+    pRows: Vec<T>,
+    pMode: ExportMode,
+) -> Option<PathBuf> {
+    if let Err(e) = fs::create_dir_all(pDirectory) {
+        warn!("Failed to create directory {:?} - {:?}", pDirectory, e);
+        return None;
+    }
+
+    let name = match pMode {
+        ExportMode::Replace => pBaseName.to_string(),
+        // Never overwrites an earlier export, e.g. of the same kind within the same second
+        ExportMode::New => free_file_name(pDirectory, pBaseName, &["csv", "json"])?,
+    };
+    let csv_path = pDirectory.join(format!("{}.csv", name));
+    let json_path = pDirectory.join(format!("{}.json", name));
+
+    if write_csv(&csv_path, &pRows, pMode) == false {
+        return None;
+    }
+
+    let file = ExportFile {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        exported_at: pNow.to_rfc3339(),
+        rows: pRows,
+    };
+    if save_json(&json_path, &file) == false {
+        return None;
+    }
+
+    info!(
+        "Exported {} rows to {:?} and {:?}",
+        file.rows.len(),
+        csv_path,
+        json_path
+    );
+    Some(csv_path)
+}
+
+pub fn roster_rows(pSquadTracker: &SquadTracker) -> Vec<RosterRow> {
+    let mut rows = pSquadTracker
+        .get_squad_members()
+        .iter()
+        .map(|(account_name, state)| RosterRow {
+            account_name: account_name.clone(),
+            role: role_to_str(state.role),
+            subgroup: state.subgroup.saturating_add(1),
+            join_time: state.join_time,
+            is_ready: state.is_ready,
+            total_ready_check_time_ms: state.total_ready_check_time.as_millis(),
+            last_unready_duration_ms: state.last_unready_duration.map(|x| x.as_millis()),
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|lhs, rhs| lhs.account_name.cmp(&rhs.account_name));
+
+    rows
+}
+
+pub fn chat_rows(pChatLog: &ChatLog) -> Vec<ChatRow> {
+    pChatLog
+        .get_all_messages()
+        .into_iter()
+        .map(|(channel, msg)| ChatRow {
+            timestamp: msg.timestamp.to_rfc3339(),
+            channel_type: channel_type_to_str(channel.channel_type),
+            channel_id: channel.channel_id,
+            subgroup: if channel.subgroup == u8::MAX {
+                None
+            } else {
+                Some(channel.subgroup + 1)
+            },
+            is_broadcast: msg.is_broadcast,
+            account_name: msg.account_name.clone(),
+            character_name: msg.character_name.clone(),
+            text: msg.text.clone(),
+            sender_role: msg.sender.map(|x| role_to_str(x.role)),
+            sender_subgroup: msg.sender.map(|x| x.subgroup.saturating_add(1)),
+        })
+        .collect()
+}
+
+pub fn export_squad_roster(pSquadTracker: &SquadTracker) -> Option<PathBuf> {
+    let now = Local::now();
+    export_rows(
+        &export_directory(),
+        &export_file_name("squad_roster", &now),
+        &now,
+        roster_rows(pSquadTracker),
+        ExportMode::New,
+    )
+}
+
+pub fn export_chat_log(pChatLog: &ChatLog) -> Option<PathBuf> {
+    let now = Local::now();
+    export_rows(
+        &export_directory(),
+        &export_file_name("chat_log", &now),
+        &now,
+        chat_rows(pChatLog),
+        ExportMode::New,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_rows, ChatRow, CsvRow, ExportMode, RosterRow, EXPORT_SCHEMA_VERSION};
+    use crate::infra::install_log_handler;
+    use chrono::{Local, TimeZone};
+    use std::{ffi::OsStr, fs, path::PathBuf};
+
+    fn test_directory(pName: &str) -> PathBuf {
+        let result = std::env::temp_dir().join(format!(
+            "arcdps_squad_manager_export_test_{}_{}",
+            pName,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&result);
+        result
+    }
+
+    // Minimal RFC 4180 reader, only for checking what write_csv produced
+    fn parse_csv(pContent: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = pContent.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (in_quotes, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => in_quotes = false,
+                (false, '"') => in_quotes = true,
+                (false, ',') => row.push(std::mem::take(&mut field)),
+                (false, '\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                (_, c) => field.push(c),
+            }
+        }
+
+        rows
+    }
+
+    fn chat_row(pText: &str) -> ChatRow {
+        ChatRow {
+            timestamp: "2022-07-09T11:45:24.888+02:00".to_string(),
+            channel_type: "squad",
+            channel_id: 7,
+            subgroup: None,
+            is_broadcast: false,
+            account_name: ":peer.1234".to_string(),
+            character_name: "Peer, \"The\" Character".to_string(),
+            text: pText.to_string(),
+            sender_role: Some("member"),
+            sender_subgroup: Some(2),
+        }
+    }
+
+    // Test that fields with quotes, commas, line breaks and pipes come back unchanged out of the CSV file
+    #[test]
+    fn csv_round_trip() {
+        install_log_handler().unwrap();
+
+        let directory = test_directory("csv");
+        let texts = [
+            "plain",
+            "with, comma",
+            "\"quoted\" text",
+            "line\nbreak",
+            "windows\r\nline break",
+            "pipe | separated",
+            "",
+        ];
+        let rows = texts.iter().map(|x| chat_row(x)).collect::<Vec<_>>();
+        let mut expected = vec![ChatRow::HEADER
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()];
+        expected.extend(rows.iter().map(|x| x.fields()));
+
+        let path =
+            export_rows(&directory, "chat_log", &Local::now(), rows, ExportMode::New).unwrap();
+        assert_eq!(parse_csv(&fs::read_to_string(&path).unwrap()), expected);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    // Test the layout of the JSON file, which external tools rely on
+    #[test]
+    fn json_schema() {
+        install_log_handler().unwrap();
+
+        let directory = test_directory("json");
+        let now = Local.ymd(2022, 7, 9).and_hms(11, 45, 24);
+        let rows = vec![RosterRow {
+            account_name: ":peer.1234".to_string(),
+            role: "lieutenant",
+            subgroup: 3,
+            join_time: 12345,
+            is_ready: true,
+            total_ready_check_time_ms: 1500,
+            last_unready_duration_ms: None,
+        }];
+
+        let path = export_rows(&directory, "squad_roster", &now, rows, ExportMode::New).unwrap();
+        let content = fs::read_to_string(path.with_extension("json")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json["schema_version"], EXPORT_SCHEMA_VERSION);
+        assert_eq!(json["exported_at"], now.to_rfc3339());
+        assert_eq!(
+            json["rows"],
+            serde_json::json!([{
+                "account_name": ":peer.1234",
+                "role": "lieutenant",
+                "subgroup": 3,
+                "join_time": 12345,
+                "is_ready": true,
+                "total_ready_check_time_ms": 1500,
+                "last_unready_duration_ms": null,
+            }])
+        );
+
+        // The CSV and JSON files have the same fields
+        let mut keys = json["rows"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut header = RosterRow::HEADER
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        header.sort();
+        assert_eq!(keys, header);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    // Test that exporting twice within the same second doesn't overwrite the first export
+    #[test]
+    fn name_collision() {
+        install_log_handler().unwrap();
+
+        let directory = test_directory("collision");
+        let now = Local::now();
+        let first = export_rows(
+            &directory,
+            "chat_log",
+            &now,
+            vec![chat_row("first")],
+            ExportMode::New,
+        )
+        .unwrap();
+        let second = export_rows(
+            &directory,
+            "chat_log",
+            &now,
+            vec![chat_row("second")],
+            ExportMode::New,
+        )
+        .unwrap();
+
+        assert_eq!(first.file_name(), Some(OsStr::new("chat_log.csv")));
+        assert_eq!(second.file_name(), Some(OsStr::new("chat_log_2.csv")));
+        assert!(fs::read_to_string(&first).unwrap().contains("first"));
+        assert!(fs::read_to_string(&second).unwrap().contains("second"));
+        assert!(second.with_extension("json").exists());
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    // Test that exporting in replace mode overwrites the earlier export instead of adding a suffix
+    #[test]
+    fn replace() {
+        install_log_handler().unwrap();
+
+        let directory = test_directory("replace");
+        let now = Local::now();
+        let first = export_rows(
+            &directory,
+            "report",
+            &now,
+            vec![chat_row("first")],
+            ExportMode::Replace,
+        )
+        .unwrap();
+        let second = export_rows(
+            &directory,
+            "report",
+            &now,
+            vec![chat_row("second")],
+            ExportMode::Replace,
+        )
+        .unwrap();
+
+        assert_eq!(first, second);
+        let content = fs::read_to_string(&second).unwrap();
+        assert!(content.contains("second") && content.contains("first") == false);
+        let content = fs::read_to_string(second.with_extension("json")).unwrap();
+        assert!(content.contains("second") && content.contains("first") == false);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 2);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -1,39 +1,372 @@
 #![allow(non_snake_case)]
 
 use crate::{
-    chat_log::ChatLog,
-    imgui_ex,
-    squad_tracker::{SquadMemberState, SquadTracker},
+    chat_alerts::{AlertRule, AlertRuleKind, ChatAlert},
+    chat_log::{Channel, ChatLog, ChatLogLimit, ChatMessage, MessageId},
+    chat_query::{ChannelFilter, ChatQuery, TextMatcher},
+    clock::SystemClock,
+    export, imgui_ex,
+    infra::{set_log_levels, LogFormat, LogLevel, LogSettings, ModuleLogLevel, LOG_LEVELS},
+    pinboard::{Pinboard, PinboardSettings},
+    ready_check_history::{ReadyCheckOutcome, ReadyStatusChange},
+    recording::Recorder,
+    settings::Settings,
+    squad_events::{SquadEvent, SquadEventKind},
+    squad_timeline::{TimelineEventKind, TimelineFilter},
+    squad_tracker::{
+        PendingUserOutcome, ReadyCheckLimit, ReadyCheckLimitEvent, ReadyCheckLimits,
+        SquadMemberState, SquadTracker,
+    },
     updates::{install_update, tag_to_version_num, UpdateInfo, UpdateStatus},
-    NEW_UPDATE,
+    NEW_UPDATE, RECORDER,
 };
 use arcdps::{
-    imgui::{ImString, TableFlags, Ui, Window, TableColumnSetup, Id, TableColumnFlags},
+    imgui::{
+        Id, ImString, ListClipper, Selectable, SelectableFlags, Slider, TableBgTarget,
+        TableColumnFlags, TableColumnSetup, TableFlags, TreeNodeFlags, Ui, Window,
+    },
     ChannelType, UserRole,
 };
-use chrono::Local;
+use chrono::{Local, LocalResult, TimeZone};
 use std::{
     cmp::Ordering,
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::PathBuf,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
+const CHAT_ALERT_TOAST_DURATION: Duration = Duration::from_secs(8);
+const CHAT_ALERT_TOAST_COUNT: usize = 5;
+const ALERT_RULE_KIND_NAMES: [&str; 2] = ["Keyword", "Sender"];
+
+const BROADCAST_FILTER_NAMES: [&str; 3] = ["All messages", "Only broadcasts", "No broadcasts"];
+
+struct ChatLogFilter {
+    text: String,
+    is_regex: bool,
+    account_name: String,
+    character_name: String,
+    // Index into BROADCAST_FILTER_NAMES
+    broadcast: usize,
+    // Only show messages from the last this many minutes, 0 to show all
+    last_minutes: u32,
+
+    // Result of the last query, None if no filter is set. Re-queried when the filter, the shown channel or the chat log
+    // changes
+    results: Option<Vec<MessageId>>,
+    error: Option<String>,
+    changed: bool,
+    queried_channel: ChannelFilter,
+    queried_generation: u64,
+    queried_at: Option<Instant>,
+}
+
+impl ChatLogFilter {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            is_regex: false,
+            account_name: String::new(),
+            character_name: String::new(),
+            broadcast: 0,
+            last_minutes: 0,
+            results: None,
+            error: None,
+            changed: true,
+            queried_channel: ChannelFilter::Any,
+            queried_generation: 0,
+            queried_at: None,
+        }
+    }
+
+    // Returns None if the filter is empty, or an error if the text isn't a valid regex
+    fn to_query(&self, pChannel: ChannelFilter) -> Result<Option<ChatQuery>, String> {
+        fn non_empty(pValue: &str) -> Option<String> {
+            if pValue.is_empty() == true {
+                None
+            } else {
+                Some(pValue.to_string())
+            }
+        }
+
+        let query = ChatQuery {
+            text: if self.text.is_empty() == true {
+                None
+            } else {
+                Some(TextMatcher::new(&self.text, self.is_regex)?)
+            },
+            account_name: non_empty(&self.account_name),
+            character_name: non_empty(&self.character_name),
+            channel: pChannel,
+            is_broadcast: match self.broadcast {
+                1 => Some(true),
+                2 => Some(false),
+                _ => None,
+            },
+            since: match self.last_minutes {
+                0 => None,
+                x => Some((Local::now() - chrono::Duration::minutes(x.into())).into()),
+            },
+            until: None,
+        };
+
+        let is_empty = query.text.is_none()
+            && query.account_name.is_none()
+            && query.character_name.is_none()
+            && query.channel == ChannelFilter::Any
+            && query.is_broadcast.is_none()
+            && query.since.is_none();
+        if is_empty == true {
+            Ok(None)
+        } else {
+            Ok(Some(query))
+        }
+    }
+
+    fn refresh(&mut self, pChatLog: &ChatLog, pChannel: ChannelFilter) {
+        // A time based filter has to be re-evaluated every now and then as messages age out of it
+        let time_expired = self.last_minutes > 0
+            && self
+                .queried_at
+                .map_or(true, |x| x.elapsed() >= Duration::from_secs(1));
+        if self.changed == false
+            && self.queried_channel == pChannel
+            && self.queried_generation == pChatLog.get_generation()
+            && time_expired == false
+        {
+            return;
+        }
+
+        self.changed = false;
+        self.queried_channel = pChannel;
+        self.queried_generation = pChatLog.get_generation();
+        self.queried_at = Some(Instant::now());
+        match self.to_query(pChannel) {
+            Ok(query) => {
+                self.results = query.map(|x| pChatLog.query(&x));
+                self.error = None;
+            }
+            Err(e) => {
+                self.results = Some(Vec::new());
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+// A tab of the chat log window, showing the messages of one kind of channel
+struct ChatTab {
+    channel: ChannelFilter,
+    // Messages received in the channels of this tab, and how many of those were received while the tab was shown
+    received: u64,
+    seen: u64,
+    scroll_y: f32,
+    visible: bool,
+}
+
+impl ChatTab {
+    fn new(pChannel: ChannelFilter) -> Self {
+        Self {
+            channel: pChannel,
+            received: 0,
+            seen: 0,
+            scroll_y: 0.0,
+            visible: false,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self.channel {
+            ChannelFilter::Any => "All".to_string(),
+            ChannelFilter::Party => "Party".to_string(),
+            ChannelFilter::Squad => "Squad (all)".to_string(),
+            ChannelFilter::SquadWide => "Squad".to_string(),
+            ChannelFilter::Subgroup(x) => format!("Subgroup {}", x.saturating_add(1)),
+        }
+    }
+
+    fn order(&self) -> u16 {
+        match self.channel {
+            ChannelFilter::Any => 0,
+            ChannelFilter::Party => 1,
+            ChannelFilter::SquadWide => 2,
+            ChannelFilter::Squad => 3,
+            ChannelFilter::Subgroup(x) => 4 + x as u16,
+        }
+    }
+
+    fn unread(&self) -> u64 {
+        self.received.saturating_sub(self.seen)
+    }
+
+    // The label is built with a fixed id, so the tab stays the same tab while its unread count changes
+    fn label(&self) -> String {
+        let name = self.name();
+        match self.unread() {
+            0 => format!("{}###{}", name, name),
+            unread => format!("{} ({})###{}", name, unread, name),
+        }
+    }
+
+    // Switching back to a tab restores where it was scrolled to when it was left. Returns that position if the tab was
+    // just switched to, None while it stays shown
+    fn take_scroll_to_restore(&mut self) -> Option<f32> {
+        if self.visible == true {
+            return None;
+        }
+
+        self.visible = true;
+        Some(self.scroll_y)
+    }
+}
+
+fn chat_tab_channel(pChannel: &Channel) -> Option<ChannelFilter> {
+    match pChannel.channel_type {
+        ChannelType::Party => Some(ChannelFilter::Party),
+        ChannelType::Squad if pChannel.subgroup == u8::MAX => Some(ChannelFilter::SquadWide),
+        ChannelType::Squad => Some(ChannelFilter::Subgroup(pChannel.subgroup)),
+        _ => None,
+    }
+}
+
+// Adds tabs for channels that appeared since the last frame and updates how many messages each tab received
+fn update_chat_tabs(pTabs: &mut Vec<ChatTab>, pChatLog: &ChatLog) {
+    if pTabs.is_empty() == true {
+        pTabs.push(ChatTab::new(ChannelFilter::Any));
+    }
+
+    let counts = pChatLog.get_received_counts();
+    for channel in counts.keys() {
+        let tab_channel = match chat_tab_channel(channel) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // Once squad chat is split up into subgroups there also is a tab with all of it
+        let mut tab_channels = vec![tab_channel];
+        if let ChannelFilter::Subgroup(_) = tab_channel {
+            tab_channels.push(ChannelFilter::Squad);
+        }
+        for tab_channel in tab_channels {
+            if pTabs.iter().any(|x| x.channel == tab_channel) == false {
+                pTabs.push(ChatTab::new(tab_channel));
+            }
+        }
+    }
+
+    for tab in pTabs.iter_mut() {
+        tab.received = counts
+            .iter()
+            .filter(|(channel, _)| tab.channel.matches(channel))
+            .map(|(_, count)| count)
+            .sum();
+    }
+
+    pTabs.sort_by_key(|x| x.order());
+}
+
 pub struct GuiState {
     ready_check_window_open: bool,
+    roster_window_open: bool,
+    timeline_window_open: bool,
+    timeline_filter: TimelineFilter,
     chat_log_window_open: bool,
     chat_log_wrap_width: f32,
+    chat_log_filter: ChatLogFilter,
+    chat_tabs: Vec<ChatTab>,
+    chat_alert_toasts: VecDeque<(Instant, ChatAlert)>,
+    pinboard_window_open: bool,
+    // Index into ALERT_RULE_KIND_NAMES
+    new_alert_rule_kind: usize,
+    new_alert_rule_text: String,
+    log_settings: LogSettings,
+    new_log_module: String,
+    new_log_crate: String,
+    record_callbacks: bool,
+    selected_ready_check: Option<u64>,
+    last_export_result: Option<Option<PathBuf>>,
+    ready_check_limit_event: Option<ReadyCheckLimitEvent>,
 }
 
 impl GuiState {
     pub fn new() -> Self {
         Self {
             ready_check_window_open: false,
+            roster_window_open: false,
+            timeline_window_open: false,
+            timeline_filter: TimelineFilter::new(),
             chat_log_window_open: false,
             chat_log_wrap_width: 600.0,
+            chat_log_filter: ChatLogFilter::new(),
+            chat_tabs: Vec::new(),
+            chat_alert_toasts: VecDeque::new(),
+            pinboard_window_open: true,
+            new_alert_rule_kind: 0,
+            new_alert_rule_text: String::new(),
+            log_settings: LogSettings::default(),
+            new_log_module: String::new(),
+            new_log_crate: String::new(),
+            record_callbacks: false,
+            selected_ready_check: None,
+            last_export_result: None,
+            ready_check_limit_event: None,
         }
     }
+
+    pub fn apply_settings(&mut self, pSettings: &Settings) {
+        self.ready_check_window_open = pSettings.windows.ready_check;
+        self.roster_window_open = pSettings.windows.roster;
+        self.timeline_window_open = pSettings.windows.timeline;
+        self.chat_log_window_open = pSettings.windows.chat_log;
+        self.pinboard_window_open = pSettings.windows.pinboard;
+        self.chat_log_wrap_width = pSettings.chat_log_wrap_width;
+        self.log_settings = pSettings.log.clone();
+        self.record_callbacks = pSettings.record_callbacks;
+    }
+
+    pub fn store_settings(&self, pSettings: &mut Settings) {
+        pSettings.windows.ready_check = self.ready_check_window_open;
+        pSettings.windows.roster = self.roster_window_open;
+        pSettings.windows.timeline = self.timeline_window_open;
+        pSettings.windows.chat_log = self.chat_log_window_open;
+        pSettings.windows.pinboard = self.pinboard_window_open;
+        pSettings.chat_log_wrap_width = self.chat_log_wrap_width;
+        pSettings.log = self.log_settings.clone();
+        pSettings.record_callbacks = self.record_callbacks;
+    }
+
+    // Keeps the latest limit event of the running ready check around for display. pEvent being None means no ready
+    // check is running anymore
+    pub fn set_ready_check_limit_event(&mut self, pEvent: Option<ReadyCheckLimitEvent>) {
+        self.ready_check_limit_event = pEvent;
+    }
+
+    pub fn handle_squad_event(&mut self, pEvent: &SquadEvent) {
+        match pEvent.kind {
+            SquadEventKind::ReadyCheckStarted { .. }
+            | SquadEventKind::ReadyCheckCompleted { .. }
+            | SquadEventKind::ReadyCheckAborted { .. }
+            | SquadEventKind::SelfLeftSquad => self.set_ready_check_limit_event(None),
+            _ => {}
+        }
+    }
+
+    // Turns off the recording checkbox (and with it the setting) when the recording couldn't be started
+    pub fn set_record_callbacks(&mut self, pEnabled: bool) {
+        self.record_callbacks = pEnabled;
+    }
+
+    // Shows pAlert as a toast for a few seconds
+    pub fn add_chat_alert(&mut self, pAlert: ChatAlert) {
+        while self.chat_alert_toasts.len() >= CHAT_ALERT_TOAST_COUNT {
+            self.chat_alert_toasts.pop_front();
+        }
+        self.chat_alert_toasts.push_back((Instant::now(), pAlert));
+    }
 }
 
-pub fn draw(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker, pChatLog: &ChatLog) {
+pub fn draw(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker, pChatLog: &mut ChatLog) {
     if pState.ready_check_window_open == true {
         Window::new(&ImString::new("Squad Manager###SQUAD_MANAGER_READY_CHECK"))
             .always_auto_resize(true)
@@ -42,7 +375,39 @@ pub fn draw(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker, pChat
             .collapsible(false)
             .opened(&mut pState.ready_check_window_open)
             .build(&pUi, || {
+                draw_ready_check_countdown(
+                    pUi,
+                    pSquadTracker,
+                    pState.ready_check_limit_event.as_ref(),
+                );
                 draw_ready_check_tab(pUi, pSquadTracker);
+                draw_last_ready_check_outcome(pUi, pSquadTracker);
+                draw_ready_check_history(pUi, pSquadTracker, &mut pState.selected_ready_check);
+            });
+    }
+
+    if pState.roster_window_open == true {
+        Window::new(&ImString::new("Squad Roster###SQUAD_MANAGER_ROSTER"))
+            .always_auto_resize(true)
+            .focus_on_appearing(false)
+            .no_nav()
+            .collapsible(false)
+            .opened(&mut pState.roster_window_open)
+            .build(&pUi, || {
+                draw_roster(pUi, pSquadTracker);
+                draw_pending_user_history(pUi, pSquadTracker);
+            });
+    }
+
+    if pState.timeline_window_open == true {
+        Window::new(&ImString::new("Squad Timeline###SQUAD_MANAGER_TIMELINE"))
+            .always_auto_resize(true)
+            .focus_on_appearing(false)
+            .no_nav()
+            .collapsible(false)
+            .opened(&mut pState.timeline_window_open)
+            .build(&pUi, || {
+                draw_timeline(pUi, pSquadTracker, &mut pState.timeline_filter);
             });
     }
 
@@ -54,7 +419,44 @@ pub fn draw(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker, pChat
             .collapsible(false)
             .opened(&mut pState.chat_log_window_open)
             .build(&pUi, || {
-                draw_chat_log(pUi, pChatLog, pState.chat_log_wrap_width);
+                draw_chat_log(
+                    pUi,
+                    pChatLog,
+                    pSquadTracker,
+                    pState.chat_log_wrap_width,
+                    &mut pState.chat_log_filter,
+                    &mut pState.chat_tabs,
+                );
+            });
+    }
+
+    if pState.pinboard_window_open == true {
+        Window::new(&ImString::new("Pinboard###SQUAD_MANAGER_PINBOARD"))
+            .always_auto_resize(true)
+            .focus_on_appearing(false)
+            .no_nav()
+            .collapsible(false)
+            .opened(&mut pState.pinboard_window_open)
+            .build(&pUi, || {
+                draw_pinboard(pUi, pChatLog.get_pinboard_mut());
+            });
+    }
+
+    while let Some((time, _)) = pState.chat_alert_toasts.front() {
+        if time.elapsed() < CHAT_ALERT_TOAST_DURATION {
+            break;
+        }
+        pState.chat_alert_toasts.pop_front();
+    }
+    if pState.chat_alert_toasts.is_empty() == false {
+        Window::new(&ImString::new("Chat Alerts###SQUAD_MANAGER_CHAT_ALERTS"))
+            .title_bar(false)
+            .always_auto_resize(true)
+            .focus_on_appearing(false)
+            .no_nav()
+            .collapsible(false)
+            .build(&pUi, || {
+                draw_chat_alert_toasts(pUi, &pState.chat_alert_toasts);
             });
     }
 
@@ -77,6 +479,61 @@ pub fn draw(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker, pChat
     }
 }
 
+fn draw_ready_check_countdown(
+    pUi: &Ui,
+    pSquadTracker: &SquadTracker,
+    pLimitEvent: Option<&ReadyCheckLimitEvent>,
+) {
+    const YELLOW: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+    const RED: [f32; 4] = [0.85, 0.0, 0.0, 1.0];
+
+    let elapsed = match pSquadTracker.get_ready_check_elapsed() {
+        Some(x) => x,
+        None => return,
+    };
+    let limits = pSquadTracker.get_ready_check_limits();
+
+    if elapsed < limits.soft_limit {
+        pUi.text(format!(
+            "Ready check running for {} ({} until soft limit)",
+            format_seconds(elapsed).trim_start(),
+            format_seconds(limits.soft_limit - elapsed).trim_start()
+        ));
+    } else if elapsed < limits.hard_limit {
+        pUi.text_colored(
+            YELLOW,
+            format!(
+                "Ready check running for {} ({} until hard limit)",
+                format_seconds(elapsed).trim_start(),
+                format_seconds(limits.hard_limit - elapsed).trim_start()
+            ),
+        );
+    } else {
+        pUi.text_colored(
+            RED,
+            format!(
+                "Ready check running for {} (hard limit passed)",
+                format_seconds(elapsed).trim_start()
+            ),
+        );
+    }
+
+    if let Some(event) = pLimitEvent {
+        let (color, limit_name) = match event.limit {
+            ReadyCheckLimit::Soft => (YELLOW, "Soft"),
+            ReadyCheckLimit::Hard => (RED, "Hard"),
+        };
+        pUi.text_colored(
+            color,
+            format!(
+                "{} limit passed - still not ready: {}",
+                limit_name,
+                event.not_ready.join(", ")
+            ),
+        );
+    }
+}
+
 fn draw_ready_check_tab(pUi: &Ui, pSquadTracker: &SquadTracker) {
     let _table_ref = pUi.begin_table_with_flags(
         &ImString::new("ready_check_table"),
@@ -93,16 +550,16 @@ fn draw_ready_check_tab(pUi: &Ui, pSquadTracker: &SquadTracker) {
     pUi.table_setup_column(&ImString::new("Total Time Unready"));
     pUi.table_headers_row();
 
-    let mut users: Vec<(&String, &SquadMemberState, Option<Duration>)> = Vec::new();
-    let mut ready_check_start_time: Option<Instant> = None;
-    for (account_name, user_state) in pSquadTracker.get_squad_members() {
-        if user_state.role == UserRole::SquadLeader && user_state.is_ready == true {
-            ready_check_start_time = user_state.last_ready_time;
-        }
-        users.push((account_name, user_state, user_state.last_unready_duration));
-    }
+    let mut users: Vec<(&String, &SquadMemberState, Option<Duration>)> = pSquadTracker
+        .get_squad_members()
+        .iter()
+        .map(|(account_name, user_state)| {
+            (account_name, user_state, user_state.last_unready_duration)
+        })
+        .collect();
+    let ready_check_start_time = pSquadTracker.get_ready_check_start_time();
 
-    let now = Instant::now();
+    let now = pSquadTracker.now();
     if let Some(start_time) = ready_check_start_time {
         for (_account_name, user_state, last_unready_duration) in users.iter_mut() {
             let ready_time = if user_state.is_ready == true {
@@ -150,9 +607,18 @@ fn draw_ready_check_tab(pUi: &Ui, pSquadTracker: &SquadTracker) {
         });
     }
 
+    // Members that are still not ready once the soft limit passed are highlighted
+    let soft_limit_passed = pSquadTracker.get_ready_check_elapsed().map_or(false, |x| {
+        x >= pSquadTracker.get_ready_check_limits().soft_limit
+    });
+
     for (account_name, member_state, last_unready_duration) in users {
         pUi.table_next_column();
-        pUi.text(&ImString::new(account_name));
+        if soft_limit_passed == true && member_state.is_ready == false {
+            pUi.text_colored([0.9, 0.75, 0.0, 1.0], &ImString::new(account_name));
+        } else {
+            pUi.text(&ImString::new(account_name));
+        }
         pUi.table_next_column();
 
         const GREEN: [f32; 4] = [0.0, 0.75, 0.0, 1.0];
@@ -169,32 +635,624 @@ fn draw_ready_check_tab(pUi: &Ui, pSquadTracker: &SquadTracker) {
             } else {
                 GRAY
             };
-            imgui_ex::centered_text_colored(
+            imgui_ex::centered_text_colored(pUi, color, format_seconds(last_unready_duration));
+        }
+
+        pUi.table_next_column();
+        imgui_ex::centered_text(pUi, format_seconds(member_state.total_ready_check_time));
+    }
+}
+
+fn draw_last_ready_check_outcome(pUi: &Ui, pSquadTracker: &SquadTracker) {
+    const GREEN: [f32; 4] = [0.0, 0.75, 0.0, 1.0];
+    const RED: [f32; 4] = [0.85, 0.0, 0.0, 1.0];
+
+    match pSquadTracker.get_last_ready_check_outcome() {
+        Some(ReadyCheckOutcome::Succeeded) => {
+            pUi.text_colored(GREEN, "Last ready check succeeded");
+        }
+        Some(ReadyCheckOutcome::Aborted(reason)) => {
+            pUi.text_colored(RED, format!("Last ready check was aborted - {}", reason));
+        }
+        None => {}
+    }
+}
+
+fn format_seconds(pDuration: Duration) -> String {
+    format!(
+        "{:2}.{}s",
+        pDuration.as_secs(),
+        pDuration.subsec_millis() / 100
+    )
+}
+
+fn draw_ready_check_history(
+    pUi: &Ui,
+    pSquadTracker: &SquadTracker,
+    pSelectedReadyCheck: &mut Option<u64>,
+) {
+    const GREEN: [f32; 4] = [0.0, 0.75, 0.0, 1.0];
+    const RED: [f32; 4] = [0.85, 0.0, 0.0, 1.0];
+    const GRAY: [f32; 4] = [0.62, 0.62, 0.62, 1.0];
+
+    if pUi.collapsing_header("Ready Check History", TreeNodeFlags::empty()) == false {
+        return;
+    }
+
+    let history = pSquadTracker.get_ready_check_history();
+    if history.records().is_empty() {
+        pUi.text_colored(GRAY, "No ready checks have finished yet");
+        return;
+    }
+
+    {
+        let _table_ref = pUi.begin_table_with_flags(
+            "ready_check_history_table",
+            4,
+            TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X,
+        );
+        for name in ["Started", "Result", "Started By", "Duration"] {
+            pUi.table_setup_column(name);
+        }
+        pUi.table_headers_row();
+
+        // Newest ready check first
+        for record in history.records().iter().rev() {
+            pUi.table_next_column();
+            let is_selected = *pSelectedReadyCheck == Some(record.id);
+            let clicked = Selectable::new(format!(
+                "{}##ready_check_{}",
+                record.start_time.format("%X"),
+                record.id
+            ))
+            .selected(is_selected)
+            .flags(SelectableFlags::SPAN_ALL_COLUMNS)
+            .build(pUi);
+            if clicked == true {
+                *pSelectedReadyCheck = if is_selected { None } else { Some(record.id) };
+            }
+
+            pUi.table_next_column();
+            match &record.outcome {
+                ReadyCheckOutcome::Succeeded => {
+                    imgui_ex::centered_text_colored(pUi, GREEN, "Succeeded")
+                }
+                ReadyCheckOutcome::Aborted(reason) => {
+                    imgui_ex::centered_text_colored(pUi, RED, "Aborted");
+                    if pUi.is_item_hovered() {
+                        pUi.tooltip_text(reason.to_string());
+                    }
+                }
+            }
+
+            pUi.table_next_column();
+            imgui_ex::centered_text(pUi, &record.started_by);
+
+            pUi.table_next_column();
+            imgui_ex::centered_text(pUi, format_seconds(record.duration));
+        }
+    }
+
+    let record = match pSelectedReadyCheck.and_then(|id| history.get(id)) {
+        Some(x) => x,
+        None => return,
+    };
+
+    pUi.text(format!(
+        "Ready check started by {} at {}, ended at {}",
+        record.started_by,
+        record.start_time.format("%X"),
+        record.end_time.format("%X")
+    ));
+    if let ReadyCheckOutcome::Aborted(reason) = &record.outcome {
+        pUi.text_colored(RED, reason.to_string());
+    }
+
+    let _table_ref = pUi.begin_table_with_flags(
+        "ready_check_history_details_table",
+        3,
+        TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X,
+    );
+    for name in ["Account Name", "Reaction Time", "Ready Events"] {
+        pUi.table_setup_column(name);
+    }
+    pUi.table_headers_row();
+
+    for member in record.members.iter() {
+        pUi.table_next_column();
+        pUi.text(&member.account_name);
+
+        pUi.table_next_column();
+        match member.reaction_time {
+            Some(x) => imgui_ex::centered_text(pUi, format_seconds(x)),
+            None => imgui_ex::centered_text_colored(pUi, RED, "Not ready"),
+        }
+
+        pUi.table_next_column();
+        let events = member
+            .events
+            .iter()
+            .map(|event| {
+                let change = match event.change {
+                    ReadyStatusChange::Readied => "Ready",
+                    ReadyStatusChange::Unreadied => "Unready",
+                };
+                format!("{} {}", change, format_seconds(event.offset).trim_start())
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        pUi.text(events);
+    }
+}
+
+fn role_display_name(pRole: UserRole) -> &'static str {
+    match pRole {
+        UserRole::SquadLeader => "Commander",
+        UserRole::Lieutenant => "Lieutenant",
+        UserRole::Member => "Member",
+        UserRole::Invited => "Invited",
+        UserRole::Applied => "Applied",
+        UserRole::None | UserRole::Invalid => "?",
+    }
+}
+
+fn format_join_time(pJoinTime: u64) -> String {
+    match Local.timestamp_opt(pJoinTime as i64, 0) {
+        LocalResult::Single(x) => x.format("%X").to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+// Members by subgroup in ascending order. Within a subgroup the commander comes first, then lieutenants, then everyone
+// else in order of joining
+fn group_roster(
+    pMembers: &HashMap<String, SquadMemberState>,
+) -> BTreeMap<u8, Vec<(&String, &SquadMemberState)>> {
+    let mut subgroups: BTreeMap<u8, Vec<(&String, &SquadMemberState)>> = BTreeMap::new();
+    for (account_name, state) in pMembers {
+        subgroups
+            .entry(state.subgroup)
+            .or_default()
+            .push((account_name, state));
+    }
+
+    for members in subgroups.values_mut() {
+        members.sort_by_key(|(account_name, state)| {
+            let role_order = match state.role {
+                UserRole::SquadLeader => 0,
+                UserRole::Lieutenant => 1,
+                _ => 2,
+            };
+            (role_order, state.join_time, *account_name)
+        });
+    }
+
+    subgroups
+}
+
+fn draw_roster(pUi: &Ui, pSquadTracker: &SquadTracker) {
+    const GOLD: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+    const GRAY: [f32; 4] = [0.62, 0.62, 0.62, 1.0];
+
+    let subgroups = group_roster(pSquadTracker.get_squad_members());
+    if subgroups.is_empty() {
+        pUi.text_colored(GRAY, "Not in a squad");
+    }
+
+    for (subgroup, members) in subgroups.iter() {
+        pUi.text(format!(
+            "Subgroup {} ({} members)",
+            subgroup.saturating_add(1),
+            members.len()
+        ));
+        if let Some(_table_ref) = pUi.begin_table_with_flags(
+            format!("roster_subgroup_{}", subgroup),
+            3,
+            TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X,
+        ) {
+            for name in ["Account Name", "Role", "Joined"] {
+                pUi.table_setup_column(name);
+            }
+            pUi.table_headers_row();
+
+            for (account_name, state) in members.iter() {
+                pUi.table_next_column();
+                pUi.text(account_name);
+
+                pUi.table_next_column();
+                match state.role {
+                    UserRole::SquadLeader => {
+                        imgui_ex::centered_text_colored(pUi, GOLD, role_display_name(state.role))
+                    }
+                    UserRole::Lieutenant => {
+                        imgui_ex::centered_text_colored(pUi, BLUE, role_display_name(state.role))
+                    }
+                    _ => imgui_ex::centered_text(pUi, role_display_name(state.role)),
+                }
+
+                pUi.table_next_column();
+                imgui_ex::centered_text(pUi, format_join_time(state.join_time));
+            }
+        }
+    }
+
+    let mut pending_users = pSquadTracker.get_pending_users().iter().collect::<Vec<_>>();
+    if pending_users.is_empty() {
+        return;
+    }
+    pending_users.sort_by_key(|(account_name, state)| (state.join_time, *account_name));
+
+    pUi.separator();
+    pUi.text("Invited and applied");
+    if let Some(_table_ref) = pUi.begin_table_with_flags(
+        "roster_pending_users",
+        4,
+        TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X,
+    ) {
+        for name in ["Account Name", "Status", "Since", "Waiting"] {
+            pUi.table_setup_column(name);
+        }
+        pUi.table_headers_row();
+
+        let now = pSquadTracker.now_local();
+        for (account_name, state) in pending_users {
+            pUi.table_next_column();
+            pUi.text(account_name);
+
+            pUi.table_next_column();
+            imgui_ex::centered_text_colored(pUi, GRAY, role_display_name(state.role));
+
+            pUi.table_next_column();
+            imgui_ex::centered_text(pUi, format_join_time(state.join_time));
+
+            pUi.table_next_column();
+            let waiting = state.waiting(&now).as_secs();
+            imgui_ex::centered_text(pUi, format!("{}m {:02}s", waiting / 60, waiting % 60));
+        }
+    }
+}
+
+fn draw_pending_user_history(pUi: &Ui, pSquadTracker: &SquadTracker) {
+    const GREEN: [f32; 4] = [0.0, 0.75, 0.0, 1.0];
+    const RED: [f32; 4] = [0.85, 0.0, 0.0, 1.0];
+
+    let history = pSquadTracker.get_pending_user_history();
+    if history.is_empty() {
+        return;
+    }
+    if pUi.collapsing_header("Invite History", TreeNodeFlags::empty()) == false {
+        return;
+    }
+
+    let _table_ref = pUi.begin_table_with_flags(
+        "roster_pending_user_history",
+        4,
+        TableFlags::BORDERS | TableFlags::NO_HOST_EXTEND_X,
+    );
+    for name in ["Account Name", "Type", "Result", "Waited"] {
+        pUi.table_setup_column(name);
+    }
+    pUi.table_headers_row();
+
+    // Newest first
+    for record in history.iter().rev() {
+        pUi.table_next_column();
+        pUi.text(&record.account_name);
+
+        pUi.table_next_column();
+        imgui_ex::centered_text(pUi, role_display_name(record.role));
+
+        pUi.table_next_column();
+        match record.outcome {
+            PendingUserOutcome::Accepted => imgui_ex::centered_text_colored(
                 pUi,
-                color,
+                GREEN,
+                format!("Joined at {}", record.resolved_at.format("%X")),
+            ),
+            PendingUserOutcome::Cancelled => imgui_ex::centered_text_colored(
+                pUi,
+                RED,
+                format!("Cancelled at {}", record.resolved_at.format("%X")),
+            ),
+        }
+
+        pUi.table_next_column();
+        let waited = (record.resolved_at.timestamp() - record.join_time as i64).max(0);
+        imgui_ex::centered_text(pUi, format!("{}m {:02}s", waited / 60, waited % 60));
+    }
+}
+
+fn draw_timeline(pUi: &Ui, pSquadTracker: &SquadTracker, pFilter: &mut TimelineFilter) {
+    const GREEN: [f32; 4] = [0.0, 0.75, 0.0, 1.0];
+    const RED: [f32; 4] = [0.85, 0.0, 0.0, 1.0];
+    const GOLD: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+
+    pUi.input_text("Account", &mut pFilter.account_name).build();
+    pUi.checkbox("Joins", &mut pFilter.show_joins);
+    pUi.same_line();
+    pUi.checkbox("Leaves", &mut pFilter.show_leaves);
+    pUi.same_line();
+    pUi.checkbox("Role changes", &mut pFilter.show_role_changes);
+    pUi.same_line();
+    pUi.checkbox("Subgroup moves", &mut pFilter.show_subgroup_changes);
+
+    let timeline = pSquadTracker.get_timeline();
+    if timeline.dropped_entries() > 0 {
+        pUi.text_colored(
+            GOLD,
+            format!(
+                "{} older entries were dropped to stay within {} entries (see the options)",
+                timeline.dropped_entries(),
+                timeline.get_max_entries()
+            ),
+        );
+    }
+
+    let _table_ref = pUi.begin_table_with_sizing(
+        "squad_timeline",
+        3,
+        TableFlags::BORDERS | TableFlags::SIZING_FIXED_FIT | TableFlags::SCROLL_Y,
+        [0.0, 300.0],
+        0.0,
+    );
+    for name in ["Time", "Account", "Event"] {
+        pUi.table_setup_column(name);
+    }
+    pUi.table_headers_row();
+
+    // Newest first, so the latest changes are visible without scrolling
+    for entry in pFilter.apply(timeline) {
+        pUi.table_next_column();
+        pUi.text(entry.time.format("%X").to_string());
+
+        pUi.table_next_column();
+        pUi.text(&entry.account_name);
+
+        pUi.table_next_column();
+        match &entry.kind {
+            TimelineEventKind::Joined { role, subgroup } => pUi.text_colored(
+                GREEN,
                 format!(
-                    "{:2}.{}s",
-                    last_unready_duration.as_secs(),
-                    last_unready_duration.subsec_millis() / 100
+                    "Joined as {} in subgroup {}",
+                    role_display_name(*role),
+                    subgroup.saturating_add(1)
                 ),
-            );
+            ),
+            TimelineEventKind::Left => pUi.text_colored(RED, "Left"),
+            TimelineEventKind::SelfLeft => pUi.text_colored(RED, "Left (self), squad ended"),
+            TimelineEventKind::RoleChanged { old_role, new_role } => pUi.text_colored(
+                GOLD,
+                format!(
+                    "{} -> {}",
+                    role_display_name(*old_role),
+                    role_display_name(*new_role)
+                ),
+            ),
+            TimelineEventKind::SubgroupChanged {
+                old_subgroup,
+                new_subgroup,
+            } => pUi.text(format!(
+                "Subgroup {} -> {}",
+                old_subgroup.saturating_add(1),
+                new_subgroup.saturating_add(1)
+            )),
         }
+    }
+}
 
-        pUi.table_next_column();
-        imgui_ex::centered_text(
-            pUi,
+fn draw_chat_log_filter(pUi: &Ui, pFilter: &mut ChatLogFilter) {
+    let mut changed = false;
+    changed |= pUi.input_text("Search", &mut pFilter.text).build();
+    pUi.same_line();
+    changed |= pUi.checkbox("Regex", &mut pFilter.is_regex);
+    changed |= pUi.input_text("Account", &mut pFilter.account_name).build();
+    changed |= pUi
+        .input_text("Character", &mut pFilter.character_name)
+        .build();
+    changed |= pUi.combo_simple_string(
+        "Broadcasts",
+        &mut pFilter.broadcast,
+        &BROADCAST_FILTER_NAMES,
+    );
+    changed |= Slider::new("Last minutes (0 = all)", 0, 240).build(pUi, &mut pFilter.last_minutes);
+    pFilter.changed |= changed;
+
+    if let Some(error) = &pFilter.error {
+        pUi.text_colored([0.85, 0.0, 0.0, 1.0], format!("Invalid regex - {}", error));
+    } else if let Some(results) = &pFilter.results {
+        pUi.text(format!("{} matching messages", results.len()));
+    }
+}
+
+fn draw_pinboard(pUi: &Ui, pPinboard: &mut Pinboard) {
+    const GOLD: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+
+    const GRAY: [f32; 4] = [0.62, 0.62, 0.62, 1.0];
+
+    if pPinboard.entries().is_empty() {
+        pUi.text_colored(GRAY, "No broadcasts or commander messages yet");
+        return;
+    }
+
+    let mut toggled_pin = None;
+    let mut dismissed = None;
+
+    // Newest first
+    for entry in pPinboard.entries().iter().rev() {
+        match entry.sender_role {
+            UserRole::SquadLeader => pUi.text_colored(GOLD, "[Commander]"),
+            UserRole::Lieutenant => pUi.text_colored(BLUE, "[Lieutenant]"),
+            _ => pUi.text_colored(BLUE, "[Broadcast]"),
+        }
+        pUi.same_line();
+        pUi.text(format!(
+            "{} {} ({})",
+            entry.timestamp.with_timezone(&Local).format("%X"),
+            entry.character_name,
+            entry.account_name
+        ));
+
+        pUi.same_line();
+        let pin_label = if entry.pinned == true { "Unpin" } else { "Pin" };
+        if pUi.small_button(format!("{}##pinboard_{}", pin_label, entry.id)) == true {
+            toggled_pin = Some((entry.id, !entry.pinned));
+        }
+        pUi.same_line();
+        if pUi.small_button(format!("Dismiss##pinboard_{}", entry.id)) == true {
+            dismissed = Some(entry.id);
+        }
+
+        pUi.text_wrapped(&entry.text);
+        pUi.separator();
+    }
+
+    if let Some((id, pinned)) = toggled_pin {
+        pPinboard.set_pinned(id, pinned);
+    }
+    if let Some(id) = dismissed {
+        pPinboard.remove(id);
+    }
+}
+
+fn draw_chat_alert_toasts(pUi: &Ui, pToasts: &VecDeque<(Instant, ChatAlert)>) {
+    const GOLD: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+
+    for (_, alert) in pToasts.iter().rev() {
+        pUi.text_colored(
+            GOLD,
             format!(
-                "{:2}.{}s",
-                member_state.total_ready_check_time.as_secs(),
-                member_state.total_ready_check_time.subsec_millis() / 100
+                "{} ({}) - {}",
+                alert.character_name, alert.account_name, alert.rule
             ),
         );
+        pUi.text_wrapped(&alert.text);
+        pUi.separator();
     }
 }
 
-fn draw_chat_log(pUi: &Ui, pChatLog: &ChatLog, pChatLogWrapWidth: f32) {
-    let _table_ref = pUi.begin_table_with_sizing(
-        "chat_log",
+fn draw_chat_message_row(
+    pUi: &Ui,
+    pSquadTracker: &SquadTracker,
+    pChannel: &Channel,
+    pMessage: &ChatMessage,
+    pHighlight: bool,
+) {
+    const GOLD: [f32; 4] = [0.9, 0.75, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+    const GREY: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+    pUi.table_next_row();
+    if pHighlight == true {
+        pUi.table_set_bg_color(TableBgTarget::ROW_BG0, [0.5, 0.35, 0.0, 0.6]);
+    }
+
+    pUi.table_next_column();
+    let mut subgroup_str = match pChannel.channel_type {
+        ChannelType::Party => "P".to_string(),
+        ChannelType::Squad => {
+            if pChannel.subgroup == u8::MAX {
+                "S".to_string()
+            } else {
+                (pChannel.subgroup + 1).to_string()
+            }
+        }
+        _ => "?".to_string(),
+    };
+    if pMessage.is_broadcast {
+        subgroup_str += " (B)";
+    }
+    imgui_ex::centered_text(pUi, &subgroup_str);
+
+    pUi.table_next_column();
+    imgui_ex::centered_text(
+        pUi,
+        pMessage
+            .timestamp
+            .with_timezone(&Local)
+            .format("%X")
+            .to_string(),
+    );
+
+    pUi.table_next_column();
+    // Senders that were in the squad when sending but aren't anymore are marked as having left
+    let has_left =
+        pMessage.sender.is_some() && pSquadTracker.find_member(&pMessage.account_name).is_none();
+    let (badge, color) = match pMessage.sender.map(|x| x.role) {
+        Some(UserRole::SquadLeader) => ("[C] ", GOLD),
+        Some(UserRole::Lieutenant) => ("[L] ", BLUE),
+        _ if has_left == true => ("", GREY),
+        _ => ("", [1.0, 1.0, 1.0, 1.0]),
+    };
+    imgui_ex::centered_text_colored(
+        pUi,
+        color,
+        format!(
+            "{}{}{}",
+            badge,
+            pMessage.account_name,
+            if has_left == true { " (left)" } else { "" }
+        ),
+    );
+    if let Some(sender) = pMessage.sender {
+        if pUi.is_item_hovered() == true {
+            pUi.tooltip_text(format!(
+                "{} in subgroup {} when sent",
+                role_display_name(sender.role),
+                sender.subgroup.saturating_add(1)
+            ));
+        }
+    }
+
+    pUi.table_next_column();
+    imgui_ex::centered_text(pUi, &pMessage.character_name);
+
+    pUi.table_next_column();
+    pUi.text_wrapped(&pMessage.text);
+}
+
+fn draw_chat_log(
+    pUi: &Ui,
+    pChatLog: &ChatLog,
+    pSquadTracker: &SquadTracker,
+    pChatLogWrapWidth: f32,
+    pFilter: &mut ChatLogFilter,
+    pTabs: &mut Vec<ChatTab>,
+) {
+    draw_chat_log_filter(pUi, pFilter);
+    update_chat_tabs(pTabs, pChatLog);
+
+    if let Some(_tab_bar) = pUi.tab_bar("chat_log_tabs") {
+        for tab in pTabs.iter_mut() {
+            if let Some(_tab_item) = pUi.tab_item(tab.label()) {
+                pFilter.refresh(pChatLog, tab.channel);
+                draw_chat_log_table(
+                    pUi,
+                    pChatLog,
+                    pSquadTracker,
+                    pChatLogWrapWidth,
+                    pFilter,
+                    tab,
+                );
+                tab.seen = tab.received;
+            } else {
+                tab.visible = false;
+            }
+        }
+    }
+}
+
+fn draw_chat_log_table(
+    pUi: &Ui,
+    pChatLog: &ChatLog,
+    pSquadTracker: &SquadTracker,
+    pChatLogWrapWidth: f32,
+    pFilter: &ChatLogFilter,
+    pTab: &mut ChatTab,
+) {
+    let table_ref = pUi.begin_table_with_sizing(
+        format!("chat_log_{}", pTab.name()),
         5,
         TableFlags::NO_BORDERS_IN_BODY
         | TableFlags::HIDEABLE // TODO: Use custom context menu instead
@@ -205,54 +1263,56 @@ fn draw_chat_log(pUi: &Ui, pChatLog: &ChatLog, pChatLogWrapWidth: f32) {
         [0.0, 400.0],
         0.0,
     );
+    if table_ref.is_none() {
+        return;
+    }
+
+    if let Some(scroll_y) = pTab.take_scroll_to_restore() {
+        pUi.set_scroll_y(scroll_y);
+    }
 
     for name in ["To", "Time", "Account", "Character"] {
         pUi.table_setup_column_with(TableColumnSetup {
             name,
             flags: TableColumnFlags::empty(),
             init_width_or_weight: 0.0,
-            user_id: Id::Int(0)});
+            user_id: Id::Int(0),
+        });
     }
     pUi.table_setup_column_with(TableColumnSetup {
         name: "Message",
         flags: TableColumnFlags::empty(),
         init_width_or_weight: pChatLogWrapWidth,
-        user_id: Id::Int(0)});
+        user_id: Id::Int(0),
+    });
     pUi.table_headers_row();
 
-    let mut messages = pChatLog.get_all_messages();
-    messages.sort_unstable_by_key(|v| v.1.timestamp);
+    let row_count = match &pFilter.results {
+        Some(results) => results.len(),
+        None => pChatLog.message_count(),
+    };
 
-    for (channel, msg) in messages {
-        pUi.table_next_column();
-        let mut subgroup_str = match channel.channel_type {
-            ChannelType::Party => "P".to_string(),
-            ChannelType::Squad => {
-                if channel.subgroup == u8::MAX {
-                    "S".to_string()
-                } else {
-                    (channel.subgroup + 1).to_string()
+    // Only the visible rows are drawn. Wrapped messages make rows differ in height, which the clipper doesn't account
+    // for, but at worst that makes the scrollbar slightly inaccurate
+    let mut clipper = ListClipper::new(row_count as i32).begin(pUi);
+    while clipper.step() == true {
+        let visible_rows = clipper.display_start() as usize..clipper.display_end() as usize;
+        match &pFilter.results {
+            Some(results) => {
+                for id in &results[visible_rows] {
+                    if let Some((channel, msg)) = pChatLog.get_message(*id) {
+                        draw_chat_message_row(pUi, pSquadTracker, channel, msg, msg.is_alert);
+                    }
+                }
+            }
+            None => {
+                for (channel, msg) in pChatLog.get_messages(visible_rows) {
+                    draw_chat_message_row(pUi, pSquadTracker, channel, msg, msg.is_alert);
                 }
             }
-            _ => "?".to_string(),
-        };
-        if msg.is_broadcast {
-            subgroup_str += " (B)";
         }
-        imgui_ex::centered_text(pUi, &subgroup_str);
-
-        pUi.table_next_column();
-        imgui_ex::centered_text(pUi, msg.timestamp.with_timezone(&Local).format("%X").to_string());
-
-        pUi.table_next_column();
-        imgui_ex::centered_text(pUi, &msg.account_name);
-
-        pUi.table_next_column();
-        imgui_ex::centered_text(pUi,&msg.character_name);
-
-        pUi.table_next_column();
-        pUi.text_wrapped(&msg.text);
     }
+    pTab.scroll_y = pUi.scroll_y();
 }
 
 fn draw_update_window(pUi: &Ui, pUpdate: &mut UpdateInfo) {
@@ -288,10 +1348,499 @@ fn draw_update_window(pUi: &Ui, pUpdate: &mut UpdateInfo) {
     }
 }
 
-pub fn draw_options(pUi: &Ui, pState: &mut GuiState) {
+// Lets the user switch pLimit between a message count and a size in KiB and adjust it. Returns true if it was changed
+fn draw_chat_log_limit(pUi: &Ui, pName: &str, pLimit: &mut ChatLogLimit) -> bool {
+    let mut by_size = matches!(pLimit, ChatLogLimit::Bytes(_));
+    let mut changed = false;
+    if pUi.checkbox(format!("Limit {} chat log by size", pName), &mut by_size) == true {
+        *pLimit = if by_size == true {
+            ChatLogLimit::Bytes(16 * 1024 * 1024)
+        } else {
+            ChatLogLimit::Messages(10_000)
+        };
+        changed = true;
+    }
+
+    match pLimit {
+        ChatLogLimit::Messages(x) => {
+            let mut messages = *x as u32;
+            if Slider::new(format!("Max {} messages", pName), 100, 100_000)
+                .build(pUi, &mut messages)
+                == true
+            {
+                *x = messages as usize;
+                changed = true;
+            }
+        }
+        ChatLogLimit::Bytes(x) => {
+            let mut kibibytes = (*x / 1024) as u32;
+            if Slider::new(format!("Max {} size (KiB)", pName), 64, 262_144)
+                .build(pUi, &mut kibibytes)
+                == true
+            {
+                *x = kibibytes as usize * 1024;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+fn draw_alert_rules(pUi: &Ui, pState: &mut GuiState, pChatLog: &mut ChatLog) {
+    pUi.text("Chat alerts");
+
+    let alerts = pChatLog.get_alerts_mut();
+    let mut removed = None;
+    for (i, rule) in alerts.get_rules().to_vec().into_iter().enumerate() {
+        let mut enabled = rule.enabled;
+        if pUi.checkbox(format!("{}##alert_rule_{}", rule, i), &mut enabled) == true {
+            alerts.set_rule_enabled(i, enabled);
+        }
+        pUi.same_line();
+        if pUi.small_button(format!("Remove##alert_rule_{}", i)) == true {
+            removed = Some(i);
+        }
+    }
+    if let Some(i) = removed {
+        alerts.remove_rule(i);
+    }
+
+    pUi.combo_simple_string(
+        "##new_alert_rule_kind",
+        &mut pState.new_alert_rule_kind,
+        &ALERT_RULE_KIND_NAMES,
+    );
+    pUi.same_line();
+    pUi.input_text("##new_alert_rule_text", &mut pState.new_alert_rule_text)
+        .build();
+    pUi.same_line();
+    let has_mention = alerts
+        .get_rules()
+        .iter()
+        .any(|x| x.kind == AlertRuleKind::Mention);
+    if pUi.button("Add rule") == true && pState.new_alert_rule_text.trim().is_empty() == false {
+        let text = pState.new_alert_rule_text.trim().to_string();
+        let kind = match pState.new_alert_rule_kind {
+            0 => AlertRuleKind::Keyword(text),
+            _ => AlertRuleKind::Sender(text),
+        };
+        alerts.add_rule(AlertRule {
+            kind,
+            enabled: true,
+        });
+        pState.new_alert_rule_text.clear();
+    }
+    if has_mention == false {
+        pUi.same_line();
+        if pUi.button("Add mention rule") == true {
+            alerts.add_rule(AlertRule {
+                kind: AlertRuleKind::Mention,
+                enabled: true,
+            });
+        }
+    }
+}
+
+fn draw_log_level(pUi: &Ui, pLabel: &str, pLevel: &mut LogLevel) -> bool {
+    let mut index = LOG_LEVELS.iter().position(|x| x == pLevel).unwrap_or(0);
+    let names = LOG_LEVELS.map(|x| x.name());
+    if pUi.combo_simple_string(pLabel, &mut index, &names) == true {
+        *pLevel = LOG_LEVELS[index];
+        return true;
+    }
+
+    false
+}
+
+fn draw_log_settings(pUi: &Ui, pState: &mut GuiState) {
+    if pUi.collapsing_header("Logging", TreeNodeFlags::empty()) == false {
+        return;
+    }
+
+    let settings = &mut pState.log_settings;
+    let mut levels_changed = draw_log_level(pUi, "Log level", &mut settings.level);
+
+    let mut removed = None;
+    for (i, module_level) in settings.module_levels.iter_mut().enumerate() {
+        levels_changed |= draw_log_level(
+            pUi,
+            &format!("{}##log_module_{}", module_level.module, i),
+            &mut module_level.level,
+        );
+        pUi.same_line();
+        if pUi.small_button(format!("Remove##log_module_{}", i)) == true {
+            removed = Some(i);
+        }
+    }
+    if let Some(i) = removed {
+        settings.module_levels.remove(i);
+        levels_changed = true;
+    }
+
+    pUi.input_text("##new_log_module", &mut pState.new_log_module)
+        .hint("Module, e.g. chat_log")
+        .build();
+    pUi.same_line();
+    if pUi.button("Add module level") == true && pState.new_log_module.trim().is_empty() == false {
+        settings.module_levels.push(ModuleLogLevel {
+            module: pState.new_log_module.trim().to_string(),
+            level: settings.level,
+        });
+        pState.new_log_module.clear();
+        levels_changed = true;
+    }
+
+    if levels_changed == true {
+        set_log_levels(settings);
+    }
+
+    let mut removed = None;
+    for (i, crate_name) in settings.crates.iter().enumerate() {
+        pUi.text(crate_name);
+        pUi.same_line();
+        if pUi.small_button(format!("Remove##log_crate_{}", i)) == true {
+            removed = Some(i);
+        }
+    }
+    if let Some(i) = removed {
+        settings.crates.remove(i);
+        set_log_levels(settings);
+    }
+    pUi.input_text("##new_log_crate", &mut pState.new_log_crate)
+        .hint("Crate, e.g. arcdps")
+        .build();
+    pUi.same_line();
+    if pUi.button("Add logged crate") == true && pState.new_log_crate.trim().is_empty() == false {
+        settings
+            .crates
+            .push(pState.new_log_crate.trim().to_string());
+        pState.new_log_crate.clear();
+        set_log_levels(settings);
+    }
+
+    let mut directory = settings.directory.to_string_lossy().into_owned();
+    if pUi.input_text("Log directory", &mut directory).build() == true {
+        settings.directory = PathBuf::from(directory);
+    }
+    let mut max_file_size_mib = (settings.max_file_size / (1024 * 1024)) as u32;
+    if Slider::new("Max log file size (MiB)", 1, 1024).build(pUi, &mut max_file_size_mib) == true {
+        settings.max_file_size = u64::from(max_file_size_mib) * 1024 * 1024;
+    }
+    let mut kept_files = settings.kept_files as u32;
+    if Slider::new("Kept log files", 1, 100).build(pUi, &mut kept_files) == true {
+        settings.kept_files = kept_files as usize;
+    }
+    let mut format = match settings.format {
+        LogFormat::Text => 0,
+        LogFormat::Json => 1,
+    };
+    if pUi.combo_simple_string("Log format", &mut format, &["Text", "JSON lines"]) == true {
+        settings.format = if format == 0 {
+            LogFormat::Text
+        } else {
+            LogFormat::Json
+        };
+    }
+    pUi.text_disabled(
+        "Logged crates, directory, file size, kept files and format are applied on the next start",
+    );
+}
+
+fn draw_recording(pUi: &Ui, pState: &mut GuiState, pSquadTracker: &SquadTracker) {
+    let mut recorder = RECORDER.write();
+    if pUi.checkbox(
+        "Record squad updates and chat messages (for bug reports)",
+        &mut pState.record_callbacks,
+    ) == true
+    {
+        *recorder = if pState.record_callbacks == true {
+            Recorder::start(pSquadTracker.get_self_account_name(), Arc::new(SystemClock))
+        } else {
+            None
+        };
+        // Don't keep the setting on if the file couldn't be created, it would fail the same way on every start
+        pState.record_callbacks = recorder.is_some();
+    }
+    if let Some(recorder) = recorder.as_ref() {
+        pUi.text_disabled(format!("Recording to {}", recorder.get_path().display()));
+    }
+}
+
+pub fn draw_options(
+    pUi: &Ui,
+    pState: &mut GuiState,
+    mut pSquadTracker: Option<&mut SquadTracker>,
+    mut pChatLog: Option<&mut ChatLog>,
+) {
     pUi.checkbox(
         &ImString::new("Squad Manager"),
         &mut pState.ready_check_window_open,
     );
+    pUi.checkbox(
+        &ImString::new("Squad Roster"),
+        &mut pState.roster_window_open,
+    );
+    pUi.checkbox(
+        &ImString::new("Squad Timeline"),
+        &mut pState.timeline_window_open,
+    );
     pUi.checkbox(&ImString::new("Chat Log"), &mut pState.chat_log_window_open);
+    pUi.checkbox(&ImString::new("Pinboard"), &mut pState.pinboard_window_open);
+
+    if let Some(tracker) = pSquadTracker.as_deref_mut() {
+        let limits = tracker.get_ready_check_limits();
+        let mut soft_limit = limits.soft_limit.as_secs() as u32;
+        let mut hard_limit = limits.hard_limit.as_secs() as u32;
+
+        let mut changed = false;
+        changed |= Slider::new("Ready check soft limit (s)", 1, 300).build(pUi, &mut soft_limit);
+        changed |= Slider::new("Ready check hard limit (s)", 1, 300).build(pUi, &mut hard_limit);
+        if changed == true {
+            tracker.set_ready_check_limits(ReadyCheckLimits {
+                soft_limit: Duration::from_secs(soft_limit.into()),
+                hard_limit: Duration::from_secs(hard_limit.into()),
+            });
+        }
+
+        let mut timeline_size = tracker.get_timeline_size() as u32;
+        if Slider::new("Squad timeline entries", 1000, 100_000).build(pUi, &mut timeline_size)
+            == true
+        {
+            tracker.set_timeline_size(timeline_size as usize);
+        }
+    }
+
+    if let Some(chatlog) = pChatLog.as_deref_mut() {
+        pUi.separator();
+        draw_alert_rules(pUi, pState, chatlog);
+
+        let settings = chatlog.get_pinboard().get_settings();
+        let mut max_entries = settings.max_entries as u32;
+        let mut expiry_minutes = (settings.expiry.as_secs() / 60) as u32;
+        let mut changed = false;
+        changed |= Slider::new("Pinboard entries", 1, 50).build(pUi, &mut max_entries);
+        changed |= Slider::new("Pinboard expiry (min)", 1, 120).build(pUi, &mut expiry_minutes);
+        if changed == true {
+            chatlog.get_pinboard_mut().set_settings(PinboardSettings {
+                max_entries: max_entries as usize,
+                expiry: Duration::from_secs(u64::from(expiry_minutes) * 60),
+            });
+        }
+
+        pUi.separator();
+        let mut limits = chatlog.get_limits();
+        let mut changed = false;
+        changed |= draw_chat_log_limit(pUi, "per channel", &mut limits.per_channel);
+        changed |= draw_chat_log_limit(pUi, "total", &mut limits.total);
+        if changed == true {
+            chatlog.set_limits(limits);
+        }
+
+        let metrics = chatlog.get_metrics();
+        pUi.text(format!(
+            "Chat log: {} messages in {} channels, {} KiB ({} evicted)",
+            metrics.messages,
+            metrics.channels,
+            metrics.bytes / 1024,
+            metrics.evicted_messages
+        ));
+    }
+
+    pUi.separator();
+    draw_log_settings(pUi, pState);
+    if let Some(tracker) = pSquadTracker.as_deref() {
+        draw_recording(pUi, pState, tracker);
+    }
+
+    pUi.separator();
+    if let Some(tracker) = pSquadTracker.as_deref() {
+        if pUi.button("Export squad roster") == true {
+            pState.last_export_result = Some(export::export_squad_roster(tracker));
+        }
+        pUi.same_line();
+    }
+    if let Some(session) = pSquadTracker.as_deref().and_then(|x| x.get_attendance()) {
+        if pUi.button("Export attendance") == true {
+            pState.last_export_result = Some(session.write_report(&Local::now()));
+        }
+        pUi.same_line();
+    }
+    if let Some(chatlog) = pChatLog.as_deref() {
+        if pUi.button("Export chat log") == true {
+            pState.last_export_result = Some(export::export_chat_log(chatlog));
+        }
+    }
+
+    match &pState.last_export_result {
+        Some(Some(path)) => pUi.text(format!("Exported to {}", path.display())),
+        Some(None) => pUi.text_colored(
+            [0.85, 0.0, 0.0, 1.0],
+            "Export failed, see the log for details",
+        ),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_roster, update_chat_tabs, ChatTab};
+    use crate::chat_log::ChatLog;
+    use crate::chat_query::ChannelFilter;
+    use crate::infra::install_log_handler;
+    use crate::squad_tracker::SquadMemberState;
+    use arcdps::{ChannelType, ChatMessageInfo, UserRole};
+    use chrono::DateTime;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn add_message(pChatLog: &mut ChatLog, pChannelType: ChannelType, pSubgroup: u8) {
+        pChatLog.add(
+            &ChatMessageInfo {
+                channel_id: 1,
+                channel_type: pChannelType,
+                subgroup: pSubgroup,
+                is_broadcast: false,
+                timestamp: DateTime::parse_from_rfc3339("2022-07-09T11:45:24.888Z").unwrap(),
+                account_name: ":peer.1234",
+                character_name: "Peer",
+                text: "hello",
+            },
+            None,
+        );
+    }
+
+    fn labels(pTabs: &[ChatTab]) -> Vec<String> {
+        pTabs.iter().map(|x| x.label()).collect()
+    }
+
+    // Test that a tab appears for every kind of channel that received messages, with the messages not seen yet in its
+    // label
+    #[test]
+    fn chat_tabs() {
+        install_log_handler().unwrap();
+
+        let mut chatlog = ChatLog::new();
+        let mut tabs = Vec::new();
+        update_chat_tabs(&mut tabs, &chatlog);
+        assert_eq!(labels(&tabs), vec!["All###All"]);
+
+        add_message(&mut chatlog, ChannelType::Squad, u8::MAX);
+        add_message(&mut chatlog, ChannelType::Party, u8::MAX);
+        add_message(&mut chatlog, ChannelType::Party, u8::MAX);
+        update_chat_tabs(&mut tabs, &chatlog);
+        assert_eq!(
+            labels(&tabs),
+            vec!["All (3)###All", "Party (2)###Party", "Squad (1)###Squad"]
+        );
+
+        // Subgroup chat brings in a tab for all of squad chat as well
+        add_message(&mut chatlog, ChannelType::Squad, 1);
+        update_chat_tabs(&mut tabs, &chatlog);
+        assert_eq!(
+            labels(&tabs),
+            vec![
+                "All (4)###All",
+                "Party (2)###Party",
+                "Squad (1)###Squad",
+                "Squad (all) (2)###Squad (all)",
+                "Subgroup 2 (1)###Subgroup 2",
+            ]
+        );
+
+        // A tab that was shown has no unread messages until the next one arrives
+        let party = tabs
+            .iter_mut()
+            .find(|x| x.channel == ChannelFilter::Party)
+            .unwrap();
+        party.seen = party.received;
+        update_chat_tabs(&mut tabs, &chatlog);
+        let party = tabs
+            .iter()
+            .find(|x| x.channel == ChannelFilter::Party)
+            .unwrap();
+        assert_eq!(party.label(), "Party###Party");
+
+        add_message(&mut chatlog, ChannelType::Party, u8::MAX);
+        update_chat_tabs(&mut tabs, &chatlog);
+        let party = tabs
+            .iter()
+            .find(|x| x.channel == ChannelFilter::Party)
+            .unwrap();
+        assert_eq!(party.unread(), 1);
+    }
+
+    // Test that switching back to a tab restores its scroll position once, and not again while it stays shown
+    #[test]
+    fn chat_tab_scroll_restore() {
+        let mut tab = ChatTab::new(ChannelFilter::Any);
+        assert_eq!(tab.take_scroll_to_restore(), Some(0.0));
+        tab.scroll_y = 120.0;
+        assert_eq!(tab.take_scroll_to_restore(), None);
+
+        // Switched to another tab and back
+        tab.visible = false;
+        assert_eq!(tab.take_scroll_to_restore(), Some(120.0));
+        assert_eq!(tab.take_scroll_to_restore(), None);
+    }
+
+    fn member(pJoinTime: u64, pRole: UserRole, pSubgroup: u8) -> SquadMemberState {
+        SquadMemberState {
+            join_time: pJoinTime,
+            role: pRole,
+            subgroup: pSubgroup,
+            is_ready: false,
+            last_ready_time: None,
+            last_unready_time: None,
+            last_unready_duration: None,
+            total_ready_check_time: Duration::new(0, 0),
+        }
+    }
+
+    // Test that the roster lists subgroups in ascending order with the commander, then lieutenants, then everyone else
+    // by join time (and account name on equal join times) in each
+    #[test]
+    fn roster_subgroups() {
+        assert!(group_roster(&HashMap::new()).is_empty());
+
+        let members: HashMap<String, SquadMemberState> = [
+            (":member_late.1", member(30, UserRole::Member, 0)),
+            (":member_early.2", member(10, UserRole::Member, 0)),
+            (":lieutenant.3", member(40, UserRole::Lieutenant, 0)),
+            (":commander.4", member(50, UserRole::SquadLeader, 0)),
+            (":member_b.5", member(20, UserRole::Member, 4)),
+            (":member_a.6", member(20, UserRole::Member, 4)),
+            (":lieutenant.7", member(60, UserRole::Lieutenant, 4)),
+            (":member.8", member(5, UserRole::Member, 2)),
+        ]
+        .into_iter()
+        .map(|(name, state)| (name.to_string(), state))
+        .collect();
+
+        let subgroups: Vec<(u8, Vec<&str>)> = group_roster(&members)
+            .into_iter()
+            .map(|(subgroup, members)| {
+                (
+                    subgroup,
+                    members.iter().map(|(name, _)| name.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            subgroups,
+            vec![
+                (
+                    0,
+                    vec![
+                        ":commander.4",
+                        ":lieutenant.3",
+                        ":member_early.2",
+                        ":member_late.1"
+                    ]
+                ),
+                (2, vec![":member.8"]),
+                (4, vec![":lieutenant.7", ":member_a.6", ":member_b.5"]),
+            ]
+        );
+    }
 }
@@ -2,18 +2,30 @@
 
 use backtrace::Backtrace;
 use flexi_logger::filter::{LogLineFilter, LogLineWriter};
-use flexi_logger::DeferredNow;
+use flexi_logger::{DeferredNow, LogSpecification};
+use serde::{Deserialize, Serialize};
 use static_init::dynamic;
+#[cfg(windows)]
 use std::ffi::CString;
+#[cfg(windows)]
 use std::mem::size_of;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(windows)]
 use winapi::shared::ntdef::TRUE;
+#[cfg(windows)]
 use winapi::um::dbghelp;
+#[cfg(windows)]
 use winapi::um::errhandlingapi::GetLastError;
+#[cfg(windows)]
 use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+#[cfg(windows)]
 use winapi::um::memoryapi::{MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS};
+#[cfg(windows)]
 use winapi::um::processthreadsapi::{GetCurrentProcess, GetCurrentThreadId};
+#[cfg(windows)]
 use winapi::um::winbase::CreateFileMappingA;
+#[cfg(windows)]
 use winapi::um::winnt::PAGE_READWRITE;
 
 #[macro_export]
@@ -89,12 +101,25 @@ macro_rules! error {
     )
 }
 
+#[cfg(windows)]
 fn get_current_thread_id() -> u32 {
     unsafe {
         return GetCurrentThreadId();
     }
 }
 
+// Only the addon itself runs on Windows, other platforms (the scenario runner, tests) log without a thread id
+#[cfg(not(windows))]
+fn get_current_thread_id() -> u32 {
+    0
+}
+
+#[cfg(not(windows))]
+fn get_global_sequence() -> Option<u64> {
+    None
+}
+
+#[cfg(windows)]
 fn get_global_sequence() -> Option<u64> {
     let shared_file_name = CString::new(r"Local\arcdps_squad_manager_ProcessSeq").unwrap();
 
@@ -141,7 +166,23 @@ fn get_global_sequence() -> Option<u64> {
 #[dynamic]
 static mut LOGGER: Option<flexi_logger::LoggerHandle> = None;
 
-pub struct LogFilter;
+// Only writes records of the crates in LogSettings::crates, so dependencies don't flood the log
+pub struct LogFilter {
+    crates: Vec<String>,
+}
+
+impl LogFilter {
+    fn new(pCrates: &[String]) -> Self {
+        Self {
+            crates: pCrates.to_vec(),
+        }
+    }
+
+    fn is_logged(&self, pModulePath: &str) -> bool {
+        is_in_crates(pModulePath, &self.crates)
+    }
+}
+
 impl LogLineFilter for LogFilter {
     fn write(
         &self,
@@ -150,7 +191,7 @@ impl LogLineFilter for LogFilter {
         log_line_writer: &dyn LogLineWriter,
     ) -> std::io::Result<()> {
         if let Some(module_path) = record.module_path() {
-            if module_path.starts_with("arcdps_squad_manager") {
+            if self.is_logged(module_path) == true {
                 log_line_writer.write(now, record)?;
             }
         }
@@ -158,7 +199,222 @@ impl LogLineFilter for LogFilter {
     }
 }
 
+// Whether pModulePath is one of pCrates or a module within one of them
+fn is_in_crates(pModulePath: &str, pCrates: &[String]) -> bool {
+    let crate_name = pModulePath.split("::").next().unwrap_or(pModulePath);
+    pCrates.iter().any(|x| x.trim() == crate_name)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+pub const LOG_LEVELS: [LogLevel; 6] = [
+    LogLevel::Off,
+    LogLevel::Error,
+    LogLevel::Warn,
+    LogLevel::Info,
+    LogLevel::Debug,
+    LogLevel::Trace,
+];
+
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    fn to_level_filter(self) -> log::LevelFilter {
+        match self {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // "<time> <thread id> <level> <function>|<message>", meant for reading
+    Text,
+    // One JsonLogLine object per line, meant for log analysis tools
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ModuleLogLevel {
+    // Module path within the addon, e.g. "chat_log" or "squad_tracker", or a full path starting with one of the logged
+    // crates, e.g. "arcdps::exports"
+    pub module: String,
+    pub level: LogLevel,
+}
+
+// The level and module levels can be changed at runtime through set_log_levels, everything else is only read when
+// the log handler is installed
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub level: LogLevel,
+    // Overrides of level for single modules and everything below them
+    pub module_levels: Vec<ModuleLogLevel>,
+    // Crates whose records are written, everything else (mostly dependencies) is dropped
+    pub crates: Vec<String>,
+    pub directory: PathBuf,
+    // Size in bytes after which a new file is started, a new file is also started every day
+    pub max_file_size: u64,
+    // Number of rotated files kept (compressed) before the oldest is deleted
+    pub kept_files: usize,
+    pub format: LogFormat,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Debug,
+            module_levels: Vec::new(),
+            crates: vec![CRATE_NAME.to_string()],
+            directory: PathBuf::from("addons/logs/arcdps_squad_manager"),
+            max_file_size: 128 * 1024 * 1024,
+            kept_files: 16,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+const CRATE_NAME: &str = "arcdps_squad_manager";
+
+fn log_specification(pSettings: &LogSettings) -> LogSpecification {
+    let mut builder = LogSpecification::builder();
+    builder.default(pSettings.level.to_level_filter());
+    for module_level in pSettings.module_levels.iter() {
+        let module = module_level.module.trim();
+        if module.is_empty() == true {
+            continue;
+        }
+        let module = if is_in_crates(module, &pSettings.crates) == true {
+            module.to_string()
+        } else {
+            format!("{}::{}", CRATE_NAME, module)
+        };
+        builder.module(module, module_level.level.to_level_filter());
+    }
+
+    builder.build()
+}
+
+// Applies the level and module levels of pSettings to the running logger
+pub fn set_log_levels(pSettings: &LogSettings) {
+    if let Some(logger) = &mut *LOGGER.write() {
+        logger.set_new_spec(log_specification(pSettings));
+    }
+}
+
+// Sequence number of this process as returned by get_global_sequence when the log handler was installed, u64::MAX if
+// unknown
+static PROCESS_SEQUENCE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+fn text_format(
+    pWrite: &mut dyn std::io::Write,
+    pNow: &mut DeferredNow,
+    pRecord: &log::Record,
+) -> std::io::Result<()> {
+    let format = time::macros::format_description!(
+        "[month repr:short] [day] [hour repr:24]:[minute]:[second].[subsecond digits:6]"
+    );
+    pWrite.write_fmt(format_args!(
+        "{time} {thread_id} {level:.1} {message}",
+        time = pNow
+            .now()
+            .format(&format)
+            .unwrap_or("Unknown time".to_string()),
+        thread_id = get_current_thread_id(),
+        level = pRecord.level(),
+        message = &pRecord.args()
+    ))
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonLogLine {
+    // RFC 3339 with the local offset
+    pub timestamp: String,
+    pub thread: u32,
+    pub level: String,
+    // As given by function_name_no_crate!, empty for messages not logged through the macros of this module
+    pub function: String,
+    // Sequence number of the process, None if it couldn't be determined
+    pub sequence: Option<u64>,
+    pub message: String,
+}
+
+impl JsonLogLine {
+    // pArgs is the formatted message as produced by the logging macros of this module, "<function>|<message>"
+    fn new(
+        pTimestamp: String,
+        pThread: u32,
+        pLevel: log::Level,
+        pSequence: u64,
+        pArgs: &str,
+    ) -> Self {
+        let (function, message) = pArgs.split_once('|').unwrap_or(("", pArgs));
+
+        Self {
+            timestamp: pTimestamp,
+            thread: pThread,
+            level: pLevel.to_string(),
+            function: function.to_string(),
+            sequence: if pSequence == u64::MAX {
+                None
+            } else {
+                Some(pSequence)
+            },
+            message: message.to_string(),
+        }
+    }
+}
+
+fn json_format(
+    pWrite: &mut dyn std::io::Write,
+    pNow: &mut DeferredNow,
+    pRecord: &log::Record,
+) -> std::io::Result<()> {
+    let line = JsonLogLine::new(
+        pNow.now()
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or("Unknown time".to_string()),
+        get_current_thread_id(),
+        pRecord.level(),
+        PROCESS_SEQUENCE.load(Ordering::Relaxed),
+        &pRecord.args().to_string(),
+    );
+
+    // serde_json escapes newlines in the message, so every record stays on a single line
+    serde_json::to_writer(pWrite, &line).map_err(std::io::Error::from)
+}
+
 pub fn install_log_handler() -> Result<(), flexi_logger::FlexiLoggerError> {
+    install_log_handler_with_settings(&LogSettings::default())
+}
+
+pub fn install_log_handler_with_settings(
+    pSettings: &LogSettings,
+) -> Result<(), flexi_logger::FlexiLoggerError> {
     use flexi_logger::*;
 
     let mut logger = LOGGER.write();
@@ -166,35 +422,28 @@ pub fn install_log_handler() -> Result<(), flexi_logger::FlexiLoggerError> {
         return Ok(()); // Return OK value in case logger is already initialized
     }
 
+    let sequence = get_global_sequence().unwrap_or(u64::MAX);
+    PROCESS_SEQUENCE.store(sequence, Ordering::Relaxed);
+    let format: FormatFunction = match pSettings.format {
+        LogFormat::Text => text_format,
+        LogFormat::Json => json_format,
+    };
+
     *logger = Some(
-        Logger::try_with_str("debug")?
+        Logger::with(log_specification(pSettings))
             .log_to_file(
                 FileSpec::default()
-                    .directory("addons/logs/arcdps_squad_manager")
+                    .directory(pSettings.directory.clone())
                     .basename("arcdps_squad_manager")
-                    .discriminant(get_global_sequence().unwrap_or(u64::MAX).to_string()),
+                    .discriminant(sequence.to_string()),
             )
             .rotate(
-                Criterion::AgeOrSize(Age::Day, 128 * 1024 * 1024),
+                Criterion::AgeOrSize(Age::Day, pSettings.max_file_size),
                 Naming::Numbers,
-                Cleanup::KeepCompressedFiles(16),
+                Cleanup::KeepCompressedFiles(pSettings.kept_files),
             )
-            .format(|write, now, record| {
-                let format = time::macros::format_description!(
-                    "[month repr:short] [day] [hour repr:24]:[minute]:[second].[subsecond digits:6]"
-                );
-                write.write_fmt(format_args!(
-                    "{time} {thread_id} {level:.1} {message}",
-                    time = now
-                        .now()
-                        .format(&format)
-                        .unwrap_or("Unknown time".to_string()),
-                    thread_id = get_current_thread_id(),
-                    level = record.level(),
-                    message = &record.args()
-                ))
-            })
-            .filter(Box::new(LogFilter))
+            .format(format)
+            .filter(Box::new(LogFilter::new(&pSettings.crates)))
             .write_mode(WriteMode::Direct)
             .start()?,
     );
@@ -206,25 +455,55 @@ pub fn install_panic_handler() {
     std::panic::set_hook(Box::new(panic_handler));
 }
 
-fn panic_handler(pPanicInfo: &std::panic::PanicInfo) {
+#[cfg(windows)]
+fn initialize_symbols() {
     unsafe {
         let result = dbghelp::SymCleanup(GetCurrentProcess());
         info!("SymCleanup returned {}", result);
         let result = dbghelp::SymInitializeW(GetCurrentProcess(), std::ptr::null(), TRUE.into());
         info!("SymInitializeW returned {}", result);
     }
+}
+
+#[cfg(not(windows))]
+fn initialize_symbols() {}
+
+// Returns the path of the module containing pAddress and the address the module is loaded at (0 if unknown)
+#[cfg(windows)]
+fn resolve_module(pAddress: u64) -> (PathBuf, u64) {
+    let mut mod_info: dbghelp::IMAGEHLP_MODULEW64;
+    unsafe {
+        mod_info = std::mem::zeroed::<dbghelp::IMAGEHLP_MODULEW64>();
+        mod_info.SizeOfStruct = std::mem::size_of_val(&mod_info) as u32;
+        dbghelp::SymGetModuleInfoW64(GetCurrentProcess(), pAddress, &mut mod_info);
+    }
+
+    let module_path_buf = PathBuf::from(String::from_utf16_lossy(
+        mod_info
+            .ImageName
+            .iter()
+            .take_while(|c| **c != 0) // truncate string to null termination
+            .map(|c| *c)
+            .collect::<Vec<_>>()
+            .as_slice(),
+    ));
+
+    (module_path_buf, mod_info.BaseOfImage)
+}
+
+#[cfg(not(windows))]
+fn resolve_module(_pAddress: u64) -> (PathBuf, u64) {
+    (PathBuf::new(), 0)
+}
+
+fn panic_handler(pPanicInfo: &std::panic::PanicInfo) {
+    initialize_symbols();
 
     let bt = Backtrace::new();
     error!("Caught panic \"{}\"", pPanicInfo);
 
     for (i, frame) in bt.frames().into_iter().enumerate() {
-        // Resolve module name
-        let mut mod_info: dbghelp::IMAGEHLP_MODULEW64;
-        unsafe {
-            mod_info = std::mem::zeroed::<dbghelp::IMAGEHLP_MODULEW64>();
-            mod_info.SizeOfStruct = std::mem::size_of_val(&mod_info) as u32;
-            dbghelp::SymGetModuleInfoW64(GetCurrentProcess(), frame.ip() as u64, &mut mod_info);
-        }
+        let (module_path_buf, module_base) = resolve_module(frame.ip() as u64);
 
         let symbol_name = frame
             .symbols()
@@ -250,22 +529,13 @@ fn panic_handler(pPanicInfo: &std::panic::PanicInfo) {
             .and_then(|x| x.lineno())
             .unwrap_or(0);
 
-        let module_path_buf = std::path::PathBuf::from(String::from_utf16_lossy(
-            mod_info
-                .ImageName
-                .iter()
-                .take_while(|c| **c != 0) // truncate string to null termination
-                .map(|c| *c)
-                .collect::<Vec<_>>()
-                .as_slice(),
-        ));
         let module_name = module_path_buf
             .file_name()
             .and_then(|y| y.to_str())
             .unwrap_or("<unknown module>");
 
-        let module_offset = if mod_info.BaseOfImage > 0 {
-            frame.ip() as u64 - mod_info.BaseOfImage
+        let module_offset = if module_base > 0 {
+            frame.ip() as u64 - module_base
         } else {
             0x0
         };
@@ -283,3 +553,142 @@ fn panic_handler(pPanicInfo: &std::panic::PanicInfo) {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        json_format, log_specification, JsonLogLine, LogFilter, LogLevel, LogSettings,
+        ModuleLogLevel,
+    };
+    use flexi_logger::DeferredNow;
+    use log::{Level, LevelFilter, Record};
+
+    // Test that module levels are scoped to modules of the addon unless they start with one of the logged crates
+    #[test]
+    fn module_levels() {
+        let settings = LogSettings {
+            level: LogLevel::Info,
+            crates: vec!["arcdps_squad_manager".to_string(), "arcdps".to_string()],
+            module_levels: vec![
+                ModuleLogLevel {
+                    module: "chat_log".to_string(),
+                    level: LogLevel::Trace,
+                },
+                ModuleLogLevel {
+                    module: "arcdps_squad_manager::squad_tracker".to_string(),
+                    level: LogLevel::Warn,
+                },
+                ModuleLogLevel {
+                    module: "arcdps::exports".to_string(),
+                    level: LogLevel::Debug,
+                },
+                ModuleLogLevel {
+                    module: "serde::de".to_string(),
+                    level: LogLevel::Error,
+                },
+                ModuleLogLevel {
+                    module: " ".to_string(),
+                    level: LogLevel::Off,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let spec = log_specification(&settings);
+        let mut filters: Vec<(Option<String>, LevelFilter)> = spec
+            .module_filters()
+            .iter()
+            .map(|x| (x.module_name.clone(), x.level_filter))
+            .collect();
+        filters.sort();
+        assert_eq!(
+            filters,
+            vec![
+                (None, LevelFilter::Info),
+                (Some("arcdps::exports".to_string()), LevelFilter::Debug),
+                (
+                    Some("arcdps_squad_manager::chat_log".to_string()),
+                    LevelFilter::Trace
+                ),
+                (
+                    Some("arcdps_squad_manager::serde::de".to_string()),
+                    LevelFilter::Error
+                ),
+                (
+                    Some("arcdps_squad_manager::squad_tracker".to_string()),
+                    LevelFilter::Warn
+                ),
+            ]
+        );
+    }
+
+    // Test that only records of the configured crates are logged, by whole crate name
+    #[test]
+    fn log_filter() {
+        let filter = LogFilter::new(&LogSettings::default().crates);
+        assert!(filter.is_logged("arcdps_squad_manager") == true);
+        assert!(filter.is_logged("arcdps_squad_manager::chat_log") == true);
+        assert!(filter.is_logged("arcdps") == false);
+        assert!(filter.is_logged("arcdps::exports") == false);
+        assert!(filter.is_logged("arcdps_squad_manager_extra::module") == false);
+
+        let filter = LogFilter::new(&["arcdps_squad_manager".to_string(), " arcdps ".to_string()]);
+        assert!(filter.is_logged("arcdps_squad_manager::squad_tracker") == true);
+        assert!(filter.is_logged("arcdps::exports") == true);
+        assert!(filter.is_logged("flexi_logger::writers") == false);
+    }
+
+    fn format_json(pLevel: Level, pArgs: std::fmt::Arguments) -> JsonLogLine {
+        let mut buffer = Vec::new();
+        json_format(
+            &mut buffer,
+            &mut DeferredNow::new(),
+            &Record::builder().args(pArgs).level(pLevel).build(),
+        )
+        .unwrap();
+
+        let line = String::from_utf8(buffer).unwrap();
+        assert_eq!(line.lines().count(), 1, "{}", line);
+        serde_json::from_str(&line).unwrap()
+    }
+
+    // Test that JSON log lines parse back into their fields, including messages that need escaping
+    #[test]
+    fn json_format_roundtrip() {
+        let line = format_json(
+            Level::Warn,
+            format_args!("{}|{}", function_name_no_crate!(), "plain message"),
+        );
+        assert_eq!(line.level, "WARN");
+        assert_eq!(line.function, "infra::tests::json_format_roundtrip");
+        assert_eq!(line.message, "plain message");
+        assert!(chrono::DateTime::parse_from_rfc3339(&line.timestamp).is_ok());
+
+        let tricky = "quote \" backslash \\ pipe | newline \n tab \t unicode \u{e9}";
+        let line = format_json(
+            Level::Debug,
+            format_args!("{}|{}", "some::function", tricky),
+        );
+        assert_eq!(line.level, "DEBUG");
+        assert_eq!(line.function, "some::function");
+        assert_eq!(line.message, tricky);
+
+        // Records that didn't go through the logging macros have no function
+        let line = format_json(Level::Info, format_args!("no function"));
+        assert_eq!(line.function, "");
+        assert_eq!(line.message, "no function");
+    }
+
+    // Test that the sequence is omitted rather than logged as u64::MAX when it isn't known
+    #[test]
+    fn json_line_sequence() {
+        let line = JsonLogLine::new("t".to_string(), 1, Level::Error, 7, "f|m");
+        assert_eq!(line.sequence, Some(7));
+        let line = JsonLogLine::new("t".to_string(), 1, Level::Error, u64::MAX, "f|m");
+        assert_eq!(line.sequence, None);
+
+        let parsed: JsonLogLine =
+            serde_json::from_str(&serde_json::to_string(&line).unwrap()).unwrap();
+        assert_eq!(parsed, line);
+    }
+}
@@ -1,11 +1,26 @@
 #![feature(atomic_from_mut)]
+#![cfg_attr(test, feature(test))]
 #![allow(non_snake_case)]
 
 #[macro_use]
 mod infra;
+mod attendance;
+mod chat_alerts;
 mod chat_log;
+mod chat_log_storage;
+mod chat_query;
+mod clock;
+mod export;
 mod gui;
 mod imgui_ex;
+mod persistence;
+mod pinboard;
+mod ready_check_history;
+mod recording;
+pub mod scenario;
+mod settings;
+mod squad_events;
+mod squad_timeline;
 mod squad_tracker;
 mod updates;
 
@@ -14,10 +29,17 @@ use arcdps::imgui;
 use arcdps::ChatMessageInfo;
 use arcdps::UserInfoIter;
 use chat_log::ChatLog;
+use clock::SystemClock;
 use gui::GuiState;
 use infra::*;
+use recording::Recorder;
+use settings::Settings;
 use squad_tracker::SquadTracker;
 use static_init::dynamic;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use updates::{find_potential_update, UpdateInfo};
 
 arcdps_export! {
@@ -41,6 +63,26 @@ static mut CHAT_LOG: Option<ChatLog> = None;
 #[dynamic]
 static mut GUI_STATE: Option<GuiState> = None;
 
+// The settings as last loaded or saved, compared against the current state to detect changes
+#[dynamic]
+static mut SETTINGS: Option<Settings> = None;
+
+// Set while the unofficial extras callbacks are being recorded
+#[dynamic]
+static mut RECORDER: Option<Recorder> = None;
+
+// When the settings were last compared against SETTINGS, see save_settings_if_changed
+#[dynamic]
+static mut LAST_SETTINGS_CHECK: Option<Instant> = None;
+
+const SETTINGS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// When the ready check stats were last saved, see save_ready_check_stats_if_changed
+#[dynamic]
+static mut LAST_STATS_SAVE: Option<Instant> = None;
+
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
 #[dynamic]
 static mut NEW_UPDATE: Option<UpdateInfo> = None;
 
@@ -49,13 +91,35 @@ fn unofficial_extras_init(
     pUnofficialExtrasVersion: Option<&'static str>,
 ) {
     if let Some(name) = pSelfAccountName {
+        let settings = SETTINGS.read().as_ref().cloned().unwrap_or_default();
         {
             let mut tracker = SQUAD_TRACKER.write();
-            tracker.get_or_insert(SquadTracker::new(name));
+            let tracker = tracker.get_or_insert(SquadTracker::new(name));
+            tracker.load_ready_check_stats(&persistence::addon_path("ready_check_stats.json"));
+            tracker.set_attendance_reports_enabled(true);
+            tracker.set_ready_check_limits(settings.ready_check_limits);
+            tracker.set_timeline_size(settings.timeline_size);
         }
         {
             let mut chatlog = CHAT_LOG.write();
-            chatlog.get_or_insert(ChatLog::new());
+            let chatlog = chatlog.get_or_insert(ChatLog::new());
+            chatlog.set_limits(settings.chat_log_limits);
+            chatlog.enable_storage(settings.chat_log_storage.clone());
+            chatlog.get_pinboard_mut().set_settings(settings.pinboard);
+
+            let alerts = chatlog.get_alerts_mut();
+            alerts.set_self_account_name(name);
+            alerts.set_rules(settings.alert_rules);
+        }
+        if settings.record_callbacks == true {
+            let recorder = Recorder::start(name, Arc::new(SystemClock));
+            // Don't keep the setting on if the file couldn't be created, it would fail the same way on every start
+            if recorder.is_none() {
+                if let Some(state) = &mut *GUI_STATE.write() {
+                    state.set_record_callbacks(false);
+                }
+            }
+            *RECORDER.write() = recorder;
         }
 
         info!(
@@ -71,8 +135,17 @@ fn unofficial_extras_init(
 }
 
 fn unofficial_extras_chat_message(pChatMessage: &ChatMessageInfo) {
+    if let Some(recorder) = &mut *RECORDER.write() {
+        recorder.record_chat_message(pChatMessage);
+    }
+
+    let sender = SQUAD_TRACKER
+        .read()
+        .as_ref()
+        .and_then(|x| x.sender_info(pChatMessage.account_name));
+
     if let Some(chatlog) = &mut *CHAT_LOG.write() {
-        chatlog.add(pChatMessage);
+        chatlog.add(pChatMessage, sender);
     }
 }
 
@@ -118,20 +191,50 @@ fn mock_unofficial_extras_init() {
 }
 
 fn unofficial_extras_squad_update(pUsers: UserInfoIter) {
+    // The users are only collected when they have to be recorded as well
+    let is_recording = RECORDER.read().is_some();
+    if is_recording == false {
+        if let Some(tracker) = &mut *SQUAD_TRACKER.write() {
+            tracker.squad_update(pUsers);
+        }
+        return;
+    }
+
+    let users = pUsers.collect::<Vec<_>>();
+    if let Some(recorder) = &mut *RECORDER.write() {
+        recorder.record_squad_update(&users);
+    }
+
     if let Some(tracker) = &mut *SQUAD_TRACKER.write() {
-        tracker.squad_update(pUsers);
+        tracker.squad_update(users.into_iter());
     }
 }
 
 fn init() -> Result<(), Box<dyn std::error::Error>> {
-    if let Err(e) = install_log_handler() {
+    // Loaded before the logger is started since the log settings are part of it. Anything logged while loading is lost
+    let mut settings = settings::load_settings(&persistence::addon_path("settings.json"));
+
+    if let Err(e) = install_log_handler_with_settings(&settings.log) {
         println!("Starting log failed {}", e);
     }
-    info!("{}", "Started logger");
+    info!("Started logger - {:?}", settings.log);
 
     install_panic_handler();
     info!("{}", "Started panic handler");
 
+    if settings::import_legacy_alert_rules(
+        &mut settings,
+        &persistence::addon_path("chat_alert_rules.json"),
+    ) == true
+    {
+        settings::save_settings(&persistence::addon_path("settings.json"), &settings);
+    }
+
+    let mut state = GuiState::new();
+    state.apply_settings(&settings);
+    *GUI_STATE.write() = Some(state);
+    *SETTINGS.write() = Some(settings);
+
     find_potential_update();
 
     if arcdps::arcdps_version().contains("ARCDPS_MOCK") {
@@ -147,6 +250,84 @@ fn init() -> Result<(), Box<dyn std::error::Error>> {
 
 fn release() {
     info!("Release");
+
+    if let Some(tracker) = &mut *SQUAD_TRACKER.write() {
+        tracker.save_ready_check_stats();
+    }
+    if let Some(state) = &*GUI_STATE.read() {
+        save_settings_if_changed(
+            state,
+            SQUAD_TRACKER.read().as_ref(),
+            CHAT_LOG.read().as_ref(),
+            true,
+        );
+    }
+}
+
+// Collects the current settings from everything that owns one and writes them to disk if they differ from what was
+// last saved. Parts that aren't initialized yet keep their previous value. Unless pForce is set this is done at most
+// once per SETTINGS_CHECK_INTERVAL, so dragging a slider doesn't write the file every frame
+fn save_settings_if_changed(
+    pState: &GuiState,
+    pSquadTracker: Option<&SquadTracker>,
+    pChatLog: Option<&ChatLog>,
+    pForce: bool,
+) {
+    {
+        let mut last_check = LAST_SETTINGS_CHECK.write();
+        let now = Instant::now();
+        let is_due = last_check.map_or(true, |x| now.duration_since(x) >= SETTINGS_CHECK_INTERVAL);
+        if pForce == false && is_due == false {
+            return;
+        }
+        *last_check = Some(now);
+    }
+
+    let mut saved_settings = SETTINGS.write();
+    let mut settings = saved_settings.clone().unwrap_or_default();
+
+    pState.store_settings(&mut settings);
+    if let Some(tracker) = pSquadTracker {
+        settings.ready_check_limits = tracker.get_ready_check_limits();
+        settings.timeline_size = tracker.get_timeline_size();
+    }
+    if let Some(chatlog) = pChatLog {
+        settings.chat_log_limits = chatlog.get_limits();
+        settings.pinboard = chatlog.get_pinboard().get_settings();
+        settings.alert_rules = chatlog.get_alerts().get_rules().to_vec();
+    }
+
+    if saved_settings.as_ref() == Some(&settings) {
+        return;
+    }
+    // A failed save is remembered like a successful one, so it's only retried once the settings change again
+    if settings::save_settings(&persistence::addon_path("settings.json"), &settings) == false {
+        warn!("Failed to save settings, retrying on the next change");
+    }
+    *saved_settings = Some(settings);
+}
+
+// Saves the ready check stats if they changed, at most once per STATS_SAVE_INTERVAL. Squad updates only mark them as
+// changed, so the file isn't written from the unofficial extras callback while it holds the tracker lock
+fn save_ready_check_stats_if_changed() {
+    let is_due = LAST_STATS_SAVE
+        .read()
+        .map_or(true, |x| x.elapsed() >= STATS_SAVE_INTERVAL);
+    if is_due == false {
+        return;
+    }
+    let has_unsaved_stats = SQUAD_TRACKER
+        .read()
+        .as_ref()
+        .map_or(false, |x| x.has_unsaved_stats());
+    if has_unsaved_stats == false {
+        return;
+    }
+
+    if let Some(tracker) = &mut *SQUAD_TRACKER.write() {
+        tracker.save_ready_check_stats();
+    }
+    *LAST_STATS_SAVE.write() = Some(Instant::now());
 }
 
 fn imgui(pUi: &imgui::Ui, pNotChararacterSelectOrLoading: bool) {
@@ -157,13 +338,39 @@ fn imgui(pUi: &imgui::Ui, pNotChararacterSelectOrLoading: bool) {
     let mut state = GUI_STATE.write();
     let state = state.get_or_insert(GuiState::new());
 
+    // Most frames have nothing to pick up from the tracker, which is found out without locking out squad updates
+    let needs_update = SQUAD_TRACKER
+        .read()
+        .as_ref()
+        .map_or(false, |x| x.needs_frame_update());
+    if needs_update == true {
+        if let Some(tracker) = &mut *SQUAD_TRACKER.write() {
+            for event in tracker.drain_events() {
+                state.handle_squad_event(&event);
+            }
+            tracker.check_ready_check_limits();
+            for event in tracker.drain_ready_check_limit_events() {
+                state.set_ready_check_limit_event(Some(event));
+            }
+        }
+    }
+    save_ready_check_stats_if_changed();
+    if let Some(chatlog) = &mut *CHAT_LOG.write() {
+        for alert in chatlog.get_alerts_mut().drain_alerts() {
+            state.add_chat_alert(alert);
+        }
+        chatlog.get_pinboard_mut().expire(&chrono::Local::now());
+    }
+
     let tracker = SQUAD_TRACKER.read();
-    let chatlog = CHAT_LOG.read();
-    if let Some((tracker, chatlog)) = tracker.as_ref().zip(chatlog.as_ref()) {
+    let mut chatlog = CHAT_LOG.write();
+    if let Some((tracker, chatlog)) = tracker.as_ref().zip(chatlog.as_mut()) {
         gui::draw(pUi, state, tracker, chatlog);
     } else {
         debug!("Tried to render frame before initialization");
     }
+
+    save_settings_if_changed(state, tracker.as_ref(), chatlog.as_ref(), false);
 }
 
 fn options_windows(pUi: &imgui::Ui, pWindowName: Option<&str>) -> bool {
@@ -171,7 +378,11 @@ fn options_windows(pUi: &imgui::Ui, pWindowName: Option<&str>) -> bool {
         let mut state = GUI_STATE.write();
         let state = state.get_or_insert(GuiState::new());
 
-        gui::draw_options(pUi, state);
+        let mut tracker = SQUAD_TRACKER.write();
+        let mut chatlog = CHAT_LOG.write();
+        gui::draw_options(pUi, state, tracker.as_mut(), chatlog.as_mut());
+
+        save_settings_if_changed(state, tracker.as_ref(), chatlog.as_ref(), false);
     }
 
     return false;
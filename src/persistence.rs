@@ -0,0 +1,116 @@
+#![allow(non_snake_case)]
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+pub const ADDON_DIRECTORY: &str = "addons/arcdps_squad_manager";
+
+pub fn addon_path(pFileName: &str) -> PathBuf {
+    PathBuf::from(ADDON_DIRECTORY).join(pFileName)
+}
+
+// Returns the first of pBaseName, pBaseName_2, pBaseName_3, ... for which no file with any of pExtensions exists in
+// pDirectory, so files written together (e.g. the CSV and JSON file of an export) share their name. None once 999 is
+// taken as well
+pub fn free_file_name(pDirectory: &Path, pBaseName: &str, pExtensions: &[&str]) -> Option<String> {
+    let result = (1..1000)
+        .map(|i| match i {
+            1 => pBaseName.to_string(),
+            _ => format!("{}_{}", pBaseName, i),
+        })
+        .find(|name| {
+            pExtensions.iter().all(|extension| {
+                pDirectory.join(format!("{}.{}", name, extension)).exists() == false
+            })
+        });
+    if result.is_none() {
+        warn!(
+            "Failed to find a free file name for {} in {:?}",
+            pBaseName, pDirectory
+        );
+    }
+
+    result
+}
+
+pub fn load_json<T: DeserializeOwned>(pPath: &Path) -> Option<T> {
+    let file = match File::open(pPath) {
+        Ok(x) => x,
+        Err(e) => {
+            info!("Failed to open {:?} - {:?}", pPath, e);
+            return None;
+        }
+    };
+
+    match serde_json::from_reader(BufReader::new(file)) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            warn!("Failed to parse {:?} - {}", pPath, e);
+            None
+        }
+    }
+}
+
+// Writes to a temporary file first and then renames it over the target, so a crash halfway through writing never
+// leaves a truncated file behind
+pub fn save_json<T: Serialize>(pPath: &Path, pValue: &T) -> bool {
+    if let Some(parent) = pPath.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!("Failed to create directory {:?} - {:?}", parent, e);
+            return false;
+        }
+    }
+
+    let mut tmp_path = pPath.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    {
+        let file = match File::create(&tmp_path) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to create {:?} - {:?}", tmp_path, e);
+                return false;
+            }
+        };
+
+        let mut writer = BufWriter::new(file);
+        if let Err(e) = serde_json::to_writer_pretty(&mut writer, pValue) {
+            warn!("Failed to serialize into {:?} - {}", tmp_path, e);
+            return false;
+        }
+        if let Err(e) = writer.flush() {
+            warn!("Failed to write to {:?} - {:?}", tmp_path, e);
+            return false;
+        }
+    }
+
+    match fs::rename(&tmp_path, pPath) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Failed to rename {:?} to {:?} - {:?}", tmp_path, pPath, e);
+            false
+        }
+    }
+}
+
+// Serializes a Duration as whole seconds, which is easier to edit by hand than serde's default representation
+pub mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        pDuration: &Duration,
+        pSerializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        pSerializer.serialize_u64(pDuration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(pDeserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(pDeserializer)?))
+    }
+}
@@ -0,0 +1,302 @@
+#![allow(non_snake_case)]
+
+use crate::chat_log::{Channel, ChatMessage};
+use arcdps::{ChannelType, UserRole};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::Duration};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PinboardSettings {
+    // Number of unpinned entries kept, older ones are dropped first
+    pub max_entries: usize,
+    // Unpinned entries are dropped once they are older than this
+    #[serde(with = "crate::persistence::duration_secs")]
+    pub expiry: Duration,
+}
+
+impl Default for PinboardSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 10,
+            expiry: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PinboardEntry {
+    pub id: u64,
+    pub timestamp: DateTime<FixedOffset>,
+    pub account_name: String,
+    pub character_name: String,
+    pub text: String,
+    pub sender_role: UserRole,
+    pub is_broadcast: bool,
+    pub pinned: bool,
+}
+
+// Instructions from the commander and lieutenants, collected from squad chat so they don't get lost between other
+// messages
+pub struct Pinboard {
+    entries: VecDeque<PinboardEntry>,
+    settings: PinboardSettings,
+    next_id: u64,
+}
+
+impl Pinboard {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            settings: PinboardSettings::default(),
+            next_id: 0,
+        }
+    }
+
+    pub fn get_settings(&self) -> PinboardSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, pSettings: PinboardSettings) {
+        self.settings = pSettings;
+        self.enforce_max_entries();
+    }
+
+    // Ordered from oldest to newest
+    pub fn entries(&self) -> &VecDeque<PinboardEntry> {
+        &self.entries
+    }
+
+    // Adds pMessage to the board if it's a squad broadcast or was sent by the commander or a lieutenant
+    pub fn add(&mut self, pChannel: &Channel, pMessage: &ChatMessage) {
+        if pChannel.channel_type != ChannelType::Squad {
+            return;
+        }
+        let sender_role = pMessage.sender.map_or(UserRole::None, |x| x.role);
+        let is_instruction = pMessage.is_broadcast == true
+            || sender_role == UserRole::SquadLeader
+            || sender_role == UserRole::Lieutenant;
+        if is_instruction == false {
+            return;
+        }
+
+        debug!(
+            "Pinning message from {} ({:?})",
+            pMessage.account_name, sender_role
+        );
+        self.entries.push_back(PinboardEntry {
+            id: self.next_id,
+            timestamp: pMessage.timestamp,
+            account_name: pMessage.account_name.clone(),
+            character_name: pMessage.character_name.clone(),
+            text: pMessage.text.clone(),
+            sender_role,
+            is_broadcast: pMessage.is_broadcast,
+            pinned: false,
+        });
+        self.next_id += 1;
+
+        self.enforce_max_entries();
+    }
+
+    fn enforce_max_entries(&mut self) {
+        let mut unpinned = self.entries.iter().filter(|x| x.pinned == false).count();
+        self.entries.retain(|x| {
+            if x.pinned == true || unpinned <= self.settings.max_entries {
+                return true;
+            }
+
+            unpinned -= 1;
+            false
+        });
+    }
+
+    // Drops unpinned entries that are older than the configured expiry
+    pub fn expire(&mut self, pNow: &DateTime<Local>) {
+        let expiry = match chrono::Duration::from_std(self.settings.expiry) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        self.entries
+            .retain(|x| x.pinned == true || *pNow - x.timestamp.with_timezone(&Local) < expiry);
+    }
+
+    pub fn set_pinned(&mut self, pId: u64, pPinned: bool) {
+        if let Some(entry) = self.entries.iter_mut().find(|x| x.id == pId) {
+            entry.pinned = pPinned;
+        }
+        self.enforce_max_entries();
+    }
+
+    pub fn remove(&mut self, pId: u64) {
+        self.entries.retain(|x| x.id != pId);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pinboard, PinboardSettings};
+    use crate::chat_log::{Channel, ChatMessage, SenderInfo};
+    use crate::infra::install_log_handler;
+    use arcdps::{ChannelType, UserRole};
+    use chrono::{DateTime, Duration, Local};
+
+    fn add(
+        pPinboard: &mut Pinboard,
+        pChannelType: ChannelType,
+        pIsBroadcast: bool,
+        pSenderRole: UserRole,
+        pText: &str,
+        pAge: Duration,
+    ) {
+        let channel = Channel {
+            channel_id: 1,
+            channel_type: pChannelType,
+            subgroup: u8::MAX,
+        };
+        let message = ChatMessage {
+            is_broadcast: pIsBroadcast,
+            timestamp: DateTime::from(Local::now() - pAge),
+            account_name: "sender".to_string(),
+            character_name: "character_sender".to_string(),
+            text: pText.to_string(),
+            sender: match pSenderRole {
+                UserRole::None => None,
+                role => Some(SenderInfo { role, subgroup: 0 }),
+            },
+            is_alert: false,
+        };
+
+        pPinboard.add(&channel, &message);
+    }
+
+    fn texts(pPinboard: &Pinboard) -> Vec<String> {
+        pPinboard.entries().iter().map(|x| x.text.clone()).collect()
+    }
+
+    // Test that only broadcasts and messages from the commander and lieutenants in squad chat end up on the board
+    #[test]
+    fn collects_instructions() {
+        install_log_handler().unwrap();
+
+        let mut pinboard = Pinboard::new();
+        let now = Duration::zero();
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            true,
+            UserRole::Member,
+            "broadcast",
+            now,
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            UserRole::SquadLeader,
+            "commander",
+            now,
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            UserRole::Lieutenant,
+            "lieutenant",
+            now,
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            UserRole::Member,
+            "member",
+            now,
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            UserRole::None,
+            "unknown",
+            now,
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Party,
+            false,
+            UserRole::SquadLeader,
+            "party",
+            now,
+        );
+
+        assert_eq!(
+            texts(&pinboard),
+            vec!["broadcast", "commander", "lieutenant"]
+        );
+    }
+
+    // Test that pinned entries survive both the entry limit and the expiry
+    #[test]
+    fn limit_and_expiry() {
+        install_log_handler().unwrap();
+
+        let mut pinboard = Pinboard::new();
+        pinboard.set_settings(PinboardSettings {
+            max_entries: 2,
+            expiry: std::time::Duration::from_secs(60),
+        });
+
+        let leader = UserRole::SquadLeader;
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            leader,
+            "a",
+            Duration::seconds(100),
+        );
+        let pinned_id = pinboard.entries().back().unwrap().id;
+        pinboard.set_pinned(pinned_id, true);
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            leader,
+            "b",
+            Duration::seconds(90),
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            leader,
+            "c",
+            Duration::seconds(30),
+        );
+        add(
+            &mut pinboard,
+            ChannelType::Squad,
+            false,
+            leader,
+            "d",
+            Duration::seconds(10),
+        );
+        assert_eq!(texts(&pinboard), vec!["a", "c", "d"]);
+
+        pinboard.expire(&Local::now());
+        assert_eq!(texts(&pinboard), vec!["a", "c", "d"]);
+        pinboard.expire(&(Local::now() + Duration::seconds(40)));
+        assert_eq!(texts(&pinboard), vec!["a", "d"]);
+
+        pinboard.set_pinned(pinned_id, false);
+        pinboard.expire(&Local::now());
+        assert_eq!(texts(&pinboard), vec!["d"]);
+
+        let remaining_id = pinboard.entries().back().unwrap().id;
+        pinboard.remove(remaining_id);
+        assert!(pinboard.entries().is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+#![allow(non_snake_case)]
+
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadyStatusChange {
+    Readied,
+    Unreadied,
+}
+
+// A single ready or unready action of a squad member, relative to the start of the ready check
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadyStatusEvent {
+    pub offset: Duration,
+    pub change: ReadyStatusChange,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadyCheckMemberRecord {
+    pub account_name: String,
+    // Time from the start of the ready check until the member readied for the last time. None if the member wasn't
+    // ready when the ready check ended
+    pub reaction_time: Option<Duration>,
+    pub events: Vec<ReadyStatusEvent>,
+}
+
+// Why a ready check didn't succeed. Every reason carries the accounts that caused it
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadyCheckAbortReason {
+    // The squad leader ended the ready check before everyone readied
+    LeaderCancelled {
+        leader: String,
+        not_ready: Vec<String>,
+    },
+    // Members readied and then unreadied again while the ready check was running
+    MemberUnreadied {
+        accounts: Vec<String>,
+    },
+    // Members joined or left the squad while the ready check was running
+    RosterChanged {
+        joined: Vec<String>,
+        left: Vec<String>,
+    },
+    // The ready check ran until the time limit without everyone readying
+    TimedOut {
+        not_ready: Vec<String>,
+    },
+    // Someone who wasn't ready became squad leader, or a new squad leader started another ready check. new_leader is
+    // None if the leader of the ready check left the squad
+    LeaderChanged {
+        old_leader: String,
+        new_leader: Option<String>,
+        not_ready: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for ReadyCheckAbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadyCheckAbortReason::LeaderCancelled { leader, not_ready } => write!(
+                f,
+                "{} cancelled the ready check (not ready: {})",
+                leader,
+                not_ready.join(", ")
+            ),
+            ReadyCheckAbortReason::MemberUnreadied { accounts } => {
+                write!(
+                    f,
+                    "Unreadied during the ready check: {}",
+                    accounts.join(", ")
+                )
+            }
+            ReadyCheckAbortReason::RosterChanged { joined, left } => write!(
+                f,
+                "Squad changed during the ready check (joined: {}; left: {})",
+                joined.join(", "),
+                left.join(", ")
+            ),
+            ReadyCheckAbortReason::TimedOut { not_ready } => write!(
+                f,
+                "Ready check ran out of time (not ready: {})",
+                not_ready.join(", ")
+            ),
+            ReadyCheckAbortReason::LeaderChanged {
+                old_leader,
+                new_leader: Some(new_leader),
+                not_ready,
+            } => write!(
+                f,
+                "Squad leader changed from {} to {} (not ready: {})",
+                old_leader,
+                new_leader,
+                not_ready.join(", ")
+            ),
+            ReadyCheckAbortReason::LeaderChanged {
+                old_leader,
+                new_leader: None,
+                not_ready,
+            } => write!(
+                f,
+                "{} left the squad (not ready: {})",
+                old_leader,
+                not_ready.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadyCheckOutcome {
+    Succeeded,
+    Aborted(ReadyCheckAbortReason),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadyCheckRecord {
+    pub id: u64,
+    pub start_time: DateTime<Local>,
+    pub end_time: DateTime<Local>,
+    pub duration: Duration,
+    pub outcome: ReadyCheckOutcome,
+    pub started_by: String,
+    pub members: Vec<ReadyCheckMemberRecord>,
+}
+
+pub struct ReadyCheckHistory {
+    records: VecDeque<ReadyCheckRecord>,
+    max_records: usize,
+    next_id: u64,
+}
+
+impl ReadyCheckHistory {
+    pub fn new(max_records: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            max_records,
+            next_id: 0,
+        }
+    }
+
+    // Assigns an id to the record and stores it, dropping the oldest record if the history is full. Returns the id of
+    // the added record
+    pub fn add(&mut self, mut pRecord: ReadyCheckRecord) -> u64 {
+        pRecord.id = self.next_id;
+        self.next_id += 1;
+
+        while self.records.len() >= self.max_records.max(1) {
+            self.records.pop_front();
+        }
+        self.records.push_back(pRecord);
+
+        self.next_id - 1
+    }
+
+    // Records ordered from oldest to newest
+    pub fn records(&self) -> &VecDeque<ReadyCheckRecord> {
+        &self.records
+    }
+
+    pub fn get(&self, pId: u64) -> Option<&ReadyCheckRecord> {
+        self.records.iter().find(|x| x.id == pId)
+    }
+
+    pub fn last(&self) -> Option<&ReadyCheckRecord> {
+        self.records.back()
+    }
+}
@@ -0,0 +1,491 @@
+#![allow(non_snake_case)]
+
+//! Recordings of the unofficial extras callbacks, for reproducing bugs that depend on the exact order and timing of
+//! squad updates and chat messages. A recording is a JSON lines file, `recording_<local time as %Y%m%d_%H%M%S>.jsonl`
+//! in the `recordings` directory of the addon. The first line is the header
+//! `{"version": 1, "self_account_name": "...", "started_at": <RFC 3339>}`, every following line is one callback:
+//!
+//! - `{"type": "squad_update", "offset_us": <u64>, "users": [...]}`, with one object per user holding
+//!   `account_name` (string or null), `join_time`, `role` (see `export::role_to_str`), `subgroup` and `ready_status`
+//! - `{"type": "chat_message", "offset_us": <u64>, "message": {...}}`, with the fields of the message. `channel_type`
+//!   is "party" or "squad" and `timestamp` is RFC 3339
+//!
+//! `offset_us` is the time since the recording was started in microseconds. Lines are flushed as they are written, so
+//! a recording is usable up to the last callback even if the game crashed.
+
+use crate::{
+    chat_log::ChatLog,
+    clock::{Clock, ManualClock},
+    export::{channel_type_from_str, channel_type_to_str, role_from_str, role_to_str},
+    persistence::{addon_path, free_file_name},
+    squad_tracker::SquadTracker,
+};
+use arcdps::{ChatMessageInfo, UserInfo};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub const RECORDING_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordingHeader {
+    pub version: u32,
+    pub self_account_name: String,
+    pub started_at: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordedUser {
+    pub account_name: Option<String>,
+    pub join_time: u64,
+    pub role: String,
+    pub subgroup: u8,
+    pub ready_status: bool,
+}
+
+impl RecordedUser {
+    fn new(pUser: &UserInfo) -> Self {
+        Self {
+            account_name: pUser.account_name.map(|x| x.to_string()),
+            join_time: pUser.join_time,
+            role: role_to_str(pUser.role).to_string(),
+            subgroup: pUser.subgroup,
+            ready_status: pUser.ready_status,
+        }
+    }
+
+    fn to_user_info(&self) -> Option<UserInfo> {
+        Some(UserInfo {
+            account_name: self.account_name.as_deref(),
+            join_time: self.join_time,
+            role: role_from_str(&self.role)?,
+            subgroup: self.subgroup,
+            ready_status: self.ready_status,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordedChatMessage {
+    pub channel_id: u32,
+    pub channel_type: String,
+    pub subgroup: u8,
+    pub is_broadcast: bool,
+    pub timestamp: String,
+    pub account_name: String,
+    pub character_name: String,
+    pub text: String,
+}
+
+impl RecordedChatMessage {
+    fn new(pMessage: &ChatMessageInfo) -> Self {
+        Self {
+            channel_id: pMessage.channel_id,
+            channel_type: channel_type_to_str(pMessage.channel_type).to_string(),
+            subgroup: pMessage.subgroup,
+            is_broadcast: pMessage.is_broadcast,
+            timestamp: pMessage.timestamp.to_rfc3339(),
+            account_name: pMessage.account_name.to_string(),
+            character_name: pMessage.character_name.to_string(),
+            text: pMessage.text.to_string(),
+        }
+    }
+
+    fn to_chat_message_info(&self) -> Option<ChatMessageInfo> {
+        Some(ChatMessageInfo {
+            channel_id: self.channel_id,
+            channel_type: channel_type_from_str(&self.channel_type)?,
+            subgroup: self.subgroup,
+            is_broadcast: self.is_broadcast,
+            timestamp: DateTime::parse_from_rfc3339(&self.timestamp).ok()?,
+            account_name: &self.account_name,
+            character_name: &self.character_name,
+            text: &self.text,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordedCall {
+    SquadUpdate {
+        offset_us: u64,
+        users: Vec<RecordedUser>,
+    },
+    ChatMessage {
+        offset_us: u64,
+        message: RecordedChatMessage,
+    },
+}
+
+impl RecordedCall {
+    pub fn offset(&self) -> Duration {
+        match self {
+            RecordedCall::SquadUpdate { offset_us, .. } => Duration::from_micros(*offset_us),
+            RecordedCall::ChatMessage { offset_us, .. } => Duration::from_micros(*offset_us),
+        }
+    }
+}
+
+pub fn recording_directory() -> PathBuf {
+    addon_path("recordings")
+}
+
+// Writes every callback it is given to a recording file
+pub struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    clock: Arc<dyn Clock>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new(pPath: &Path, pSelfAccountName: &str, pClock: Arc<dyn Clock>) -> Option<Self> {
+        if let Some(parent) = pPath.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                warn!("Failed to create directory {:?} - {:?}", parent, e);
+                return None;
+            }
+        }
+        // Never overwrites an existing recording
+        let file = match OpenOptions::new().write(true).create_new(true).open(pPath) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to create recording {:?} - {:?}", pPath, e);
+                return None;
+            }
+        };
+
+        let mut result = Self {
+            path: pPath.to_path_buf(),
+            writer: BufWriter::new(file),
+            start: pClock.now(),
+            clock: pClock,
+        };
+        let header = RecordingHeader {
+            version: RECORDING_VERSION,
+            self_account_name: pSelfAccountName.to_string(),
+            started_at: result.clock.now_local().to_rfc3339(),
+        };
+        if result.write_line(&header) == false {
+            return None;
+        }
+
+        info!("Started recording to {:?}", pPath);
+        Some(result)
+    }
+
+    // Starts a recording in recording_directory, named after the current time
+    pub fn start(pSelfAccountName: &str, pClock: Arc<dyn Clock>) -> Option<Self> {
+        Self::start_in(&recording_directory(), pSelfAccountName, pClock)
+    }
+
+    // Recordings started within the same second get a counter suffix, e.g. recording_20220709_114524_2.jsonl
+    fn start_in(pDirectory: &Path, pSelfAccountName: &str, pClock: Arc<dyn Clock>) -> Option<Self> {
+        let name = format!("recording_{}", pClock.now_local().format("%Y%m%d_%H%M%S"));
+        let name = free_file_name(pDirectory, &name, &["jsonl"])?;
+        let path = pDirectory.join(format!("{}.jsonl", name));
+
+        Self::new(&path, pSelfAccountName, pClock)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn offset_us(&self) -> u64 {
+        self.clock
+            .now()
+            .saturating_duration_since(self.start)
+            .as_micros() as u64
+    }
+
+    fn write_line<T: Serialize>(&mut self, pValue: &T) -> bool {
+        let mut line = match serde_json::to_vec(pValue) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to serialize into recording {:?} - {}", self.path, e);
+                return false;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.writer.write_all(&line) {
+            warn!("Failed to write to recording {:?} - {:?}", self.path, e);
+            return false;
+        }
+        if let Err(e) = self.writer.flush() {
+            warn!("Failed to write to recording {:?} - {:?}", self.path, e);
+            return false;
+        }
+
+        true
+    }
+
+    pub fn record_squad_update(&mut self, pUsers: &[UserInfo]) {
+        let call = RecordedCall::SquadUpdate {
+            offset_us: self.offset_us(),
+            users: pUsers.iter().map(RecordedUser::new).collect(),
+        };
+        self.write_line(&call);
+    }
+
+    pub fn record_chat_message(&mut self, pMessage: &ChatMessageInfo) {
+        let call = RecordedCall::ChatMessage {
+            offset_us: self.offset_us(),
+            message: RecordedChatMessage::new(pMessage),
+        };
+        self.write_line(&call);
+    }
+}
+
+pub struct Recording {
+    pub header: RecordingHeader,
+    pub calls: Vec<RecordedCall>,
+}
+
+// Reads a recording. Lines that can't be parsed are skipped with a warning, so a recording that was cut off while
+// writing can still be replayed. Only used offline, by tests replaying recordings attached to bug reports
+#[allow(dead_code)]
+pub fn load_recording(pPath: &Path) -> Option<Recording> {
+    let file = match File::open(pPath) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Failed to open recording {:?} - {:?}", pPath, e);
+            return None;
+        }
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let header: RecordingHeader = match lines.next().map(|x| x.map(|y| serde_json::from_str(&y))) {
+        Some(Ok(Ok(x))) => x,
+        x => {
+            warn!(
+                "Failed to read the header of recording {:?} - {:?}",
+                pPath, x
+            );
+            return None;
+        }
+    };
+    if header.version != RECORDING_VERSION {
+        warn!(
+            "Ignoring recording {:?} with unsupported version {} (expected {})",
+            pPath, header.version, RECORDING_VERSION
+        );
+        return None;
+    }
+
+    let mut calls = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = match line {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to read recording {:?} - {:?}", pPath, e);
+                break;
+            }
+        };
+        match serde_json::from_str(&line) {
+            Ok(x) => calls.push(x),
+            Err(e) => warn!("Skipping line {} of recording {:?} - {}", i + 2, pPath, e),
+        }
+    }
+
+    info!("Loaded recording {:?} with {} calls", pPath, calls.len());
+    Some(Recording { header, calls })
+}
+
+// Feeds a recording into a fresh SquadTracker and ChatLog, with the clock of the tracker following the offsets of
+// the recording. Calls can be stepped through one at a time to inspect the state in between
+#[allow(dead_code)]
+pub struct Replay {
+    pub clock: ManualClock,
+    pub tracker: SquadTracker,
+    pub chat_log: ChatLog,
+    offset: Duration,
+}
+
+#[allow(dead_code)]
+impl Replay {
+    pub fn new(pHeader: &RecordingHeader) -> Self {
+        let start_time = DateTime::parse_from_rfc3339(&pHeader.started_at)
+            .map(|x| x.with_timezone(&Local))
+            .unwrap_or_else(|_| Local::now());
+        let clock = ManualClock::starting_at(start_time);
+
+        let mut chat_log = ChatLog::new();
+        chat_log
+            .get_alerts_mut()
+            .set_self_account_name(&pHeader.self_account_name);
+
+        Self {
+            tracker: SquadTracker::with_clock(&pHeader.self_account_name, Arc::new(clock.clone())),
+            clock,
+            chat_log,
+            offset: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&mut self, pCall: &RecordedCall) {
+        // Offsets never go backwards, but a hand edited recording might not follow that
+        let offset = pCall.offset();
+        self.clock.advance(offset.saturating_sub(self.offset));
+        self.offset = self.offset.max(offset);
+
+        match pCall {
+            RecordedCall::SquadUpdate { users, .. } => {
+                let users = users
+                    .iter()
+                    .filter_map(|x| {
+                        let result = x.to_user_info();
+                        if result.is_none() {
+                            warn!("Skipping invalid user {:?}", x);
+                        }
+                        result
+                    })
+                    .collect::<Vec<_>>();
+                self.tracker.squad_update(users.into_iter());
+            }
+            RecordedCall::ChatMessage { message, .. } => match message.to_chat_message_info() {
+                Some(info) => {
+                    let sender = self.tracker.sender_info(info.account_name);
+                    self.chat_log.add(&info, sender);
+                }
+                None => warn!("Skipping invalid chat message {:?}", message),
+            },
+        }
+    }
+
+    pub fn run(pRecording: &Recording) -> Self {
+        let mut result = Self::new(&pRecording.header);
+        for call in pRecording.calls.iter() {
+            result.step(call);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_recording, Recorder, Replay};
+    use crate::clock::ManualClock;
+    use crate::infra::install_log_handler;
+    use crate::ready_check_history::ReadyCheckOutcome;
+    use arcdps::{ChannelType, ChatMessageInfo, UserInfo, UserRole};
+    use chrono::DateTime;
+    use std::{fs, io::Write, sync::Arc, time::Duration};
+
+    fn user(pAccountName: &str, pRole: UserRole, pReady: bool) -> UserInfo {
+        UserInfo {
+            account_name: Some(pAccountName),
+            join_time: 12345,
+            role: pRole,
+            subgroup: 0,
+            ready_status: pReady,
+        }
+    }
+
+    // Test that a recorded session replays into the same tracker and chat log state, with the original timing
+    #[test]
+    fn record_and_replay() {
+        install_log_handler().unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "arcdps_squad_manager_recording_test_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let clock = ManualClock::new();
+        let mut recorder = Recorder::new(&path, "self", Arc::new(clock.clone())).unwrap();
+
+        let leader = |pReady: bool| user("squad_leader", UserRole::SquadLeader, pReady);
+        recorder.record_squad_update(&[leader(false), user("self", UserRole::Member, false)]);
+        clock.advance(Duration::from_millis(1500));
+        recorder.record_squad_update(&[leader(true)]);
+        clock.advance(Duration::from_millis(2250));
+        recorder.record_chat_message(&ChatMessageInfo {
+            channel_id: 1,
+            channel_type: ChannelType::Squad,
+            subgroup: u8::MAX,
+            is_broadcast: false,
+            timestamp: DateTime::parse_from_rfc3339("2022-07-09T11:45:24.888+02:00").unwrap(),
+            account_name: "squad_leader",
+            character_name: "Leader | \"Quoted\"",
+            text: "ready up\nplease",
+        });
+        recorder.record_squad_update(&[user("self", UserRole::Member, true)]);
+        clock.advance(Duration::from_millis(250));
+        recorder.record_squad_update(&[leader(false), user("self", UserRole::Member, false)]);
+        drop(recorder);
+
+        // A line cut off by a crash is skipped
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"type": "squad_update", "offset"#)
+            .unwrap();
+
+        let recording = load_recording(&path).unwrap();
+        assert_eq!(recording.header.self_account_name, "self");
+        assert_eq!(recording.calls.len(), 5);
+        assert_eq!(recording.calls[4].offset(), Duration::from_millis(4000));
+
+        let replay = Replay::run(&recording);
+        let record = replay.tracker.get_ready_check_history().last().unwrap();
+        assert_eq!(record.outcome, ReadyCheckOutcome::Succeeded);
+        assert_eq!(record.duration, Duration::from_millis(2500));
+        let self_record = record
+            .members
+            .iter()
+            .find(|x| x.account_name == "self")
+            .unwrap();
+        assert_eq!(self_record.reaction_time, Some(Duration::from_millis(2250)));
+
+        let messages = replay.chat_log.get_all_messages();
+        assert_eq!(messages.len(), 1);
+        let (channel, message) = messages[0];
+        assert_eq!(channel.channel_type, ChannelType::Squad);
+        assert_eq!(message.character_name, "Leader | \"Quoted\"");
+        assert_eq!(message.text, "ready up\nplease");
+        assert_eq!(
+            message.timestamp.to_rfc3339(),
+            "2022-07-09T11:45:24.888+02:00"
+        );
+        assert_eq!(message.sender.map(|x| x.role), Some(UserRole::SquadLeader));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    // Test that recordings started within the same second don't overwrite each other
+    #[test]
+    fn recording_name_collision() {
+        install_log_handler().unwrap();
+
+        let directory = std::env::temp_dir().join(format!(
+            "arcdps_squad_manager_recording_collision_test_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        let clock = Arc::new(ManualClock::new());
+
+        let first = Recorder::start_in(&directory, "first", clock.clone()).unwrap();
+        let second = Recorder::start_in(&directory, "second", clock.clone()).unwrap();
+        assert_ne!(first.get_path(), second.get_path());
+        assert!(second.get_path().to_string_lossy().ends_with("_2.jsonl"));
+        assert!(Recorder::new(first.get_path(), "third", clock).is_none());
+        drop(second);
+
+        let recording = load_recording(first.get_path()).unwrap();
+        assert_eq!(recording.header.self_account_name, "first");
+
+        drop(first);
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,690 @@
+#![allow(non_snake_case)]
+
+//! Scenarios drive a SquadTracker and ChatLog without the game, see src/bin/scenario_runner.rs. A scenario is a text
+//! file with one step per line. Empty lines and lines starting with `#` are ignored. The steps are:
+//!
+//! - `self <account>`: the account the addon runs as, defaults to `self`. Only allowed before any other step
+//! - `join <account> [<role> [<subgroup>]]`: role is as in `export::role_from_str` and defaults to `member`, subgroup
+//!   defaults to 0
+//! - `leave <account>`
+//! - `role <account> <role>` and `subgroup <account> <subgroup>`
+//! - `ready <account>...` and `unready <account>...`: every listed account changes in the same squad update
+//! - `chat <squad|party> <account> <text>`: a chat message, the rest of the line is the text
+//! - `advance <duration>`: moves the clock forward, e.g. `1.5s`, `250ms` or `2m`
+//!
+//! Every step except `advance` happens at the current time of the clock, which starts at 0.
+
+use crate::{
+    chat_log::ChatLog,
+    clock::{Clock, ManualClock},
+    export::{channel_type_from_str, channel_type_to_str, role_from_str, role_to_str},
+    squad_events::SquadEvent,
+    squad_tracker::{ReadyCheckLimitEvent, SquadTracker},
+};
+use arcdps::{ChannelType, ChatMessageInfo, UserInfo, UserRole};
+use chrono::{DateTime, Local};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioStep {
+    Join {
+        account_name: String,
+        role: UserRole,
+        subgroup: u8,
+    },
+    Leave {
+        account_name: String,
+    },
+    Role {
+        account_name: String,
+        role: UserRole,
+    },
+    Subgroup {
+        account_name: String,
+        subgroup: u8,
+    },
+    Ready {
+        account_names: Vec<String>,
+        is_ready: bool,
+    },
+    Chat {
+        channel_type: ChannelType,
+        account_name: String,
+        text: String,
+    },
+    Advance(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub self_account_name: String,
+    pub steps: Vec<ScenarioStep>,
+}
+
+// Splits off the next whitespace separated token, leaving the remainder in pLine
+fn next_token<'a>(pLine: &mut &'a str) -> Option<&'a str> {
+    let line = pLine.trim_start();
+    if line.is_empty() == true {
+        return None;
+    }
+
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    let (token, rest) = line.split_at(end);
+    *pLine = rest;
+    Some(token)
+}
+
+fn parse_duration(pDuration: &str) -> Option<Duration> {
+    let (number, to_secs): (&str, fn(f64) -> f64) = if let Some(x) = pDuration.strip_suffix("ms") {
+        (x, |y| y / 1000.0)
+    } else if let Some(x) = pDuration.strip_suffix('s') {
+        (x, |y| y)
+    } else if let Some(x) = pDuration.strip_suffix('m') {
+        (x, |y| y * 60.0)
+    } else {
+        return None;
+    };
+
+    let number: f64 = number.parse().ok()?;
+    if number.is_finite() == false || number < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(to_secs(number)))
+}
+
+// Parses one non-empty line, with the command already split off
+fn parse_step(pCommand: &str, mut pRest: &str) -> Option<ScenarioStep> {
+    let rest = &mut pRest;
+    let step = match pCommand {
+        "join" => {
+            let account_name = next_token(rest)?.to_string();
+            let role = match next_token(rest) {
+                Some(x) => role_from_str(x)?,
+                None => UserRole::Member,
+            };
+            let subgroup = match next_token(rest) {
+                Some(x) => x.parse().ok()?,
+                None => 0,
+            };
+            ScenarioStep::Join {
+                account_name,
+                role,
+                subgroup,
+            }
+        }
+        "leave" => ScenarioStep::Leave {
+            account_name: next_token(rest)?.to_string(),
+        },
+        "role" => ScenarioStep::Role {
+            account_name: next_token(rest)?.to_string(),
+            role: role_from_str(next_token(rest)?)?,
+        },
+        "subgroup" => ScenarioStep::Subgroup {
+            account_name: next_token(rest)?.to_string(),
+            subgroup: next_token(rest)?.parse().ok()?,
+        },
+        "ready" | "unready" => {
+            let mut account_names = Vec::new();
+            while let Some(x) = next_token(rest) {
+                account_names.push(x.to_string());
+            }
+            if account_names.is_empty() == true {
+                return None;
+            }
+            ScenarioStep::Ready {
+                account_names,
+                is_ready: pCommand == "ready",
+            }
+        }
+        "chat" => {
+            let channel_type = channel_type_from_str(next_token(rest)?)?;
+            let account_name = next_token(rest)?.to_string();
+            // The text is the rest of the line, so there is nothing left to check
+            return Some(ScenarioStep::Chat {
+                channel_type,
+                account_name,
+                text: rest.trim().to_string(),
+            });
+        }
+        "advance" => ScenarioStep::Advance(parse_duration(next_token(rest)?)?),
+        _ => return None,
+    };
+
+    // Anything left over is a mistake, such as a misspelled argument that was meant to be part of the step
+    if next_token(rest).is_some() {
+        return None;
+    }
+
+    Some(step)
+}
+
+pub fn parse_scenario(pScenario: &str) -> Option<Scenario> {
+    let mut result = Scenario {
+        self_account_name: "self".to_string(),
+        steps: Vec::new(),
+    };
+
+    for (i, line) in pScenario.lines().enumerate() {
+        let mut rest = line.trim();
+        if rest.is_empty() == true || rest.starts_with('#') == true {
+            continue;
+        }
+
+        let command = next_token(&mut rest).unwrap();
+        if command == "self" {
+            match next_token(&mut rest) {
+                Some(x) if result.steps.is_empty() == true => {
+                    result.self_account_name = x.to_string()
+                }
+                _ => {
+                    warn!("Line {}: invalid self step {:?}", i + 1, line);
+                    return None;
+                }
+            }
+            continue;
+        }
+
+        match parse_step(command, rest) {
+            Some(x) => result.steps.push(x),
+            None => {
+                warn!("Line {}: invalid step {:?}", i + 1, line);
+                return None;
+            }
+        }
+    }
+
+    Some(result)
+}
+
+pub fn load_scenario(pPath: &Path) -> Option<Scenario> {
+    match fs::read_to_string(pPath) {
+        Ok(x) => parse_scenario(&x),
+        Err(e) => {
+            warn!("Failed to read scenario {:?} - {:?}", pPath, e);
+            None
+        }
+    }
+}
+
+// State of a user as last sent to the tracker. Squad updates carry the full state of every user that changed, so
+// each step sends the previous state with one field changed
+#[derive(Clone, Copy, Debug)]
+struct ScenarioUser {
+    join_time: u64,
+    role: UserRole,
+    subgroup: u8,
+    is_ready: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioOutput {
+    Squad(SquadEvent),
+    ReadyCheckLimit(ReadyCheckLimitEvent),
+}
+
+// Runs the steps of a scenario against a fresh SquadTracker and ChatLog, collecting everything they produced
+pub struct ScenarioRunner {
+    clock: ManualClock,
+    start: Instant,
+    start_local: DateTime<Local>,
+    users: BTreeMap<String, ScenarioUser>,
+    pub tracker: SquadTracker,
+    pub chat_log: ChatLog,
+    // Time since the start of the scenario for every output, in the order they were produced
+    pub outputs: Vec<(Duration, ScenarioOutput)>,
+}
+
+impl ScenarioRunner {
+    pub fn new(pSelfAccountName: &str) -> Self {
+        let clock = ManualClock::starting_at(Local::now());
+
+        let mut chat_log = ChatLog::new();
+        chat_log
+            .get_alerts_mut()
+            .set_self_account_name(pSelfAccountName);
+
+        Self {
+            start: clock.now(),
+            start_local: clock.now_local(),
+            users: BTreeMap::new(),
+            tracker: SquadTracker::with_clock(pSelfAccountName, Arc::new(clock.clone())),
+            chat_log,
+            outputs: Vec::new(),
+            clock,
+        }
+    }
+
+    pub fn run(pScenario: &Scenario) -> Self {
+        let mut result = Self::new(&pScenario.self_account_name);
+        for step in pScenario.steps.iter() {
+            result.step(step);
+        }
+
+        result
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.start)
+    }
+
+    pub fn step(&mut self, pStep: &ScenarioStep) {
+        match pStep {
+            ScenarioStep::Join {
+                account_name,
+                role,
+                subgroup,
+            } => {
+                let user = ScenarioUser {
+                    join_time: self.clock.now_local().timestamp() as u64,
+                    role: *role,
+                    subgroup: *subgroup,
+                    is_ready: false,
+                };
+                self.users.insert(account_name.clone(), user);
+                self.send_update(&[account_name]);
+            }
+            ScenarioStep::Leave { account_name } => {
+                if self.modify_user(account_name, |x| x.role = UserRole::None) == true {
+                    self.send_update(&[account_name]);
+                    self.users.remove(account_name);
+                }
+            }
+            ScenarioStep::Role { account_name, role } => {
+                if self.modify_user(account_name, |x| x.role = *role) == true {
+                    self.send_update(&[account_name]);
+                }
+            }
+            ScenarioStep::Subgroup {
+                account_name,
+                subgroup,
+            } => {
+                if self.modify_user(account_name, |x| x.subgroup = *subgroup) == true {
+                    self.send_update(&[account_name]);
+                }
+            }
+            ScenarioStep::Ready {
+                account_names,
+                is_ready,
+            } => {
+                let mut changed = Vec::new();
+                for account_name in account_names.iter() {
+                    if self.modify_user(account_name, |x| x.is_ready = *is_ready) == true {
+                        changed.push(account_name);
+                    }
+                }
+                self.send_update(&changed);
+            }
+            ScenarioStep::Chat {
+                channel_type,
+                account_name,
+                text,
+            } => {
+                let now = self.clock.now_local();
+                let message = ChatMessageInfo {
+                    channel_id: 0,
+                    channel_type: *channel_type,
+                    subgroup: u8::MAX,
+                    is_broadcast: false,
+                    timestamp: now.with_timezone(now.offset()),
+                    account_name,
+                    character_name: account_name,
+                    text,
+                };
+                let sender = self.tracker.sender_info(account_name);
+                self.chat_log.add(&message, sender);
+            }
+            ScenarioStep::Advance(duration) => {
+                self.clock.advance(*duration);
+            }
+        }
+
+        self.collect_outputs();
+    }
+
+    fn modify_user(&mut self, pAccountName: &str, pModify: impl FnOnce(&mut ScenarioUser)) -> bool {
+        match self.users.get_mut(pAccountName) {
+            Some(x) => {
+                pModify(x);
+                true
+            }
+            None => {
+                warn!("{} is not in the squad", pAccountName);
+                false
+            }
+        }
+    }
+
+    fn send_update(&mut self, pAccountNames: &[&String]) {
+        if pAccountNames.is_empty() == true {
+            return;
+        }
+
+        let users = pAccountNames
+            .iter()
+            .map(|x| {
+                let user = &self.users[x.as_str()];
+                UserInfo {
+                    account_name: Some(x.as_str()),
+                    join_time: user.join_time,
+                    role: user.role,
+                    subgroup: user.subgroup,
+                    ready_status: user.is_ready,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.tracker.squad_update(users.into_iter());
+    }
+
+    fn collect_outputs(&mut self) {
+        let elapsed = self.elapsed();
+
+        self.tracker.check_ready_check_limits();
+        for event in self.tracker.drain_events() {
+            self.outputs.push((elapsed, ScenarioOutput::Squad(event)));
+        }
+        for event in self.tracker.drain_ready_check_limit_events() {
+            self.outputs
+                .push((elapsed, ScenarioOutput::ReadyCheckLimit(event)));
+        }
+    }
+
+    // Time of pTime relative to the start of the scenario
+    fn offset_of(&self, pTime: DateTime<Local>) -> Duration {
+        (pTime - self.start_local)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub fn write_report(&self, pOutput: &mut dyn Write) -> io::Result<()> {
+        writeln!(pOutput, "Events:")?;
+        for (elapsed, output) in self.outputs.iter() {
+            match output {
+                ScenarioOutput::Squad(event) => writeln!(
+                    pOutput,
+                    "  [{:>9.3}s] {:?}",
+                    elapsed.as_secs_f64(),
+                    event.kind
+                )?,
+                ScenarioOutput::ReadyCheckLimit(event) => {
+                    writeln!(pOutput, "  [{:>9.3}s] {:?}", elapsed.as_secs_f64(), event)?
+                }
+            }
+        }
+
+        writeln!(pOutput, "Squad members:")?;
+        let members = self
+            .tracker
+            .get_squad_members()
+            .iter()
+            .collect::<BTreeMap<_, _>>();
+        for (account_name, member) in members {
+            writeln!(
+                pOutput,
+                "  {} role={} subgroup={} ready={} total_ready_check_time={:.3}s",
+                account_name,
+                role_to_str(member.role),
+                member.subgroup,
+                member.is_ready,
+                member.total_ready_check_time.as_secs_f64()
+            )?;
+        }
+
+        writeln!(pOutput, "Ready checks:")?;
+        for record in self.tracker.get_ready_check_history().records().iter() {
+            writeln!(
+                pOutput,
+                "  #{} started_by={} start={:.3}s duration={:.3}s outcome={:?}",
+                record.id,
+                record.started_by,
+                self.offset_of(record.start_time).as_secs_f64(),
+                record.duration.as_secs_f64(),
+                record.outcome
+            )?;
+            for member in record.members.iter() {
+                match member.reaction_time {
+                    Some(x) => writeln!(
+                        pOutput,
+                        "    {} reaction_time={:.3}s",
+                        member.account_name,
+                        x.as_secs_f64()
+                    )?,
+                    None => writeln!(pOutput, "    {} not ready", member.account_name)?,
+                }
+            }
+        }
+
+        writeln!(pOutput, "Chat log:")?;
+        for (channel, message) in self.chat_log.get_all_messages() {
+            let offset = self.offset_of(message.timestamp.with_timezone(&Local));
+            let sender_role = message
+                .sender
+                .map(|x| role_to_str(x.role))
+                .unwrap_or("not in squad");
+            writeln!(
+                pOutput,
+                "  [{:>9.3}s] {} {} ({}): {}",
+                offset.as_secs_f64(),
+                channel_type_to_str(channel.channel_type),
+                message.account_name,
+                sender_role,
+                message.text
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_scenario, ScenarioOutput, ScenarioRunner, ScenarioStep};
+    use crate::infra::install_log_handler;
+    use crate::ready_check_history::ReadyCheckOutcome;
+    use crate::squad_events::SquadEventKind;
+    use arcdps::{ChannelType, UserRole};
+    use rstest::rstest;
+    use std::time::Duration;
+
+    const READY_CHECK_SCENARIO: &str = "
+        # Leader starts a ready check, peer is slow to ready
+        self me
+        join lead squad_leader 1
+        join me
+        join peer member 2
+        advance 1s
+        ready lead
+        advance 500ms
+        ready me
+        chat squad lead  ready up   please
+        advance 2.25s
+        ready peer
+        advance 0.25s
+        unready lead me peer
+        leave peer
+    ";
+
+    // Test that the steps of a scenario are parsed into the expected values
+    #[test]
+    fn parse() {
+        install_log_handler().unwrap();
+
+        let scenario = parse_scenario(READY_CHECK_SCENARIO).unwrap();
+        assert_eq!(scenario.self_account_name, "me");
+        assert_eq!(scenario.steps.len(), 13);
+        assert_eq!(
+            scenario.steps[0],
+            ScenarioStep::Join {
+                account_name: "lead".to_string(),
+                role: UserRole::SquadLeader,
+                subgroup: 1
+            }
+        );
+        assert_eq!(
+            scenario.steps[1],
+            ScenarioStep::Join {
+                account_name: "me".to_string(),
+                role: UserRole::Member,
+                subgroup: 0
+            }
+        );
+        assert_eq!(
+            scenario.steps[5],
+            ScenarioStep::Advance(Duration::from_millis(500))
+        );
+        assert_eq!(
+            scenario.steps[7],
+            ScenarioStep::Chat {
+                channel_type: ChannelType::Squad,
+                account_name: "lead".to_string(),
+                text: "ready up   please".to_string()
+            }
+        );
+        assert_eq!(
+            scenario.steps[11],
+            ScenarioStep::Ready {
+                account_names: vec!["lead".to_string(), "me".to_string(), "peer".to_string()],
+                is_ready: false
+            }
+        );
+    }
+
+    // Test that a scenario with any malformed line is rejected as a whole
+    #[rstest]
+    #[case::unknown_step("dance lead")]
+    #[case::unknown_role("join lead boss")]
+    #[case::bad_subgroup("join lead member 300")]
+    #[case::extra_argument("leave lead now")]
+    #[case::missing_account("ready")]
+    #[case::bad_channel("chat guild lead hello")]
+    #[case::no_unit("advance 5")]
+    #[case::negative_duration("advance -1s")]
+    #[case::late_self("join lead\nself me")]
+    fn parse_invalid(#[case] pScenario: &str) {
+        install_log_handler().unwrap();
+
+        assert_eq!(parse_scenario(pScenario), None);
+    }
+
+    // Test that running a scenario produces the ready check, events and chat messages it describes, with exact timing
+    #[test]
+    fn run() {
+        install_log_handler().unwrap();
+
+        let scenario = parse_scenario(READY_CHECK_SCENARIO).unwrap();
+        let runner = ScenarioRunner::run(&scenario);
+        assert_eq!(runner.elapsed(), Duration::from_secs(4));
+
+        let record = runner.tracker.get_ready_check_history().last().unwrap();
+        assert_eq!(record.outcome, ReadyCheckOutcome::Succeeded);
+        assert_eq!(record.started_by, "lead");
+        assert_eq!(record.duration, Duration::from_millis(3000));
+
+        let join_time = runner.start_local.timestamp() as u64;
+        let events = runner
+            .outputs
+            .iter()
+            .filter_map(|(elapsed, output)| match output {
+                ScenarioOutput::Squad(x) => Some((*elapsed, x.kind.clone())),
+                ScenarioOutput::ReadyCheckLimit(_) => None,
+            })
+            .filter(|(_, kind)| matches!(kind, SquadEventKind::ReadyStatusChanged { .. }) == false)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    Duration::ZERO,
+                    SquadEventKind::MemberJoined {
+                        account_name: "lead".to_string(),
+                        role: UserRole::SquadLeader,
+                        subgroup: 1,
+                        join_time
+                    }
+                ),
+                (
+                    Duration::ZERO,
+                    SquadEventKind::SquadLeaderChanged {
+                        old_leader: None,
+                        new_leader: Some("lead".to_string())
+                    }
+                ),
+                (
+                    Duration::ZERO,
+                    SquadEventKind::MemberJoined {
+                        account_name: "me".to_string(),
+                        role: UserRole::Member,
+                        subgroup: 0,
+                        join_time
+                    }
+                ),
+                (
+                    Duration::ZERO,
+                    SquadEventKind::MemberJoined {
+                        account_name: "peer".to_string(),
+                        role: UserRole::Member,
+                        subgroup: 2,
+                        join_time
+                    }
+                ),
+                (
+                    Duration::from_secs(1),
+                    SquadEventKind::ReadyCheckStarted {
+                        started_by: "lead".to_string()
+                    }
+                ),
+                (
+                    Duration::from_secs(4),
+                    SquadEventKind::ReadyCheckCompleted {
+                        id: record.id,
+                        duration: Duration::from_millis(3000)
+                    }
+                ),
+                (
+                    Duration::from_secs(4),
+                    SquadEventKind::MemberLeft {
+                        account_name: "peer".to_string()
+                    }
+                ),
+            ]
+        );
+
+        let messages = runner.chat_log.get_all_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.text, "ready up   please");
+        assert_eq!(
+            messages[0].1.sender.map(|x| x.role),
+            Some(UserRole::SquadLeader)
+        );
+
+        let mut report = Vec::new();
+        runner.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(
+            report.contains("  lead role=squad_leader subgroup=1 ready=false"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("duration=3.000s outcome=Succeeded"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("    peer reaction_time=2.750s"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("[    1.500s] squad lead (squad_leader): ready up   please"),
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,361 @@
+#![allow(non_snake_case)]
+
+//! User settings are stored in `settings.json` in the addon directory as a single JSON object with a `version` field.
+//! Fields missing from the file take their default value, so adding a setting doesn't need a new version. Renaming or
+//! restructuring a setting bumps `SETTINGS_VERSION` and adds an entry to `MIGRATIONS` that rewrites the previous
+//! version's JSON into the new shape before it's deserialized. A file that can't be parsed is moved aside to
+//! `settings.json.corrupt` and the defaults are used instead.
+//!
+//! Alert rules used to be stored on their own in `chat_alert_rules.json` as `{"version": 1, "rules": [...]}`. That
+//! file is imported into the settings once and then renamed to `chat_alert_rules.json.imported`.
+
+use crate::{
+    chat_alerts::{default_rules, AlertRule},
+    chat_log::ChatLogLimits,
+    chat_log_storage::ChatLogStorageSettings,
+    infra::LogSettings,
+    persistence::{load_json, save_json},
+    pinboard::PinboardSettings,
+    squad_tracker::{ReadyCheckLimits, DEFAULT_TIMELINE_SIZE},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fs, path::Path};
+
+pub const SETTINGS_VERSION: u32 = 1;
+
+const LEGACY_ALERT_RULES_VERSION: u32 = 1;
+
+// Entry i upgrades the JSON of a version i + 1 file to version i + 2
+const MIGRATIONS: &[fn(&mut Value)] = &[];
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub ready_check: bool,
+    pub roster: bool,
+    pub timeline: bool,
+    pub chat_log: bool,
+    pub pinboard: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            ready_check: false,
+            roster: false,
+            timeline: false,
+            chat_log: false,
+            pinboard: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub windows: WindowSettings,
+    pub chat_log_wrap_width: f32,
+    pub ready_check_limits: ReadyCheckLimits,
+    // Entries kept in the squad timeline, the oldest are dropped beyond that
+    pub timeline_size: usize,
+    pub chat_log_limits: ChatLogLimits,
+    pub chat_log_storage: ChatLogStorageSettings,
+    pub pinboard: PinboardSettings,
+    pub alert_rules: Vec<AlertRule>,
+    pub log: LogSettings,
+    // Record the unofficial extras callbacks to a file, see recording.rs
+    pub record_callbacks: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            windows: WindowSettings::default(),
+            chat_log_wrap_width: 600.0,
+            ready_check_limits: ReadyCheckLimits::default(),
+            timeline_size: DEFAULT_TIMELINE_SIZE,
+            chat_log_limits: ChatLogLimits::default(),
+            chat_log_storage: ChatLogStorageSettings::default(),
+            pinboard: PinboardSettings::default(),
+            alert_rules: default_rules(),
+            log: LogSettings::default(),
+            record_callbacks: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyAlertRules {
+    version: u32,
+    rules: Vec<AlertRule>,
+}
+
+// Moves an unreadable settings file out of the way, so it isn't overwritten by the next save and can still be fixed
+// by hand
+fn backup_corrupt_file(pPath: &Path) {
+    let mut backup_path = pPath.as_os_str().to_owned();
+    backup_path.push(".corrupt");
+
+    match fs::rename(pPath, &backup_path) {
+        Ok(_) => warn!(
+            "Moved unreadable settings file {:?} to {:?}",
+            pPath, backup_path
+        ),
+        Err(e) => warn!(
+            "Failed to move unreadable settings file {:?} - {:?}",
+            pPath, e
+        ),
+    }
+}
+
+fn parse_settings(pContents: &str) -> Result<Settings, String> {
+    let mut value: Value = serde_json::from_str(pContents).map_err(|e| e.to_string())?;
+    if value.is_object() == false {
+        return Err("Settings are not a JSON object".to_string());
+    }
+
+    let version = match value.get("version") {
+        Some(x) => x.as_u64().ok_or_else(|| format!("Invalid version {}", x))? as u32,
+        None => SETTINGS_VERSION,
+    };
+    if version == 0 || version > SETTINGS_VERSION {
+        return Err(format!(
+            "Unsupported version {} (expected at most {})",
+            version, SETTINGS_VERSION
+        ));
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(&mut value);
+    }
+    if version != SETTINGS_VERSION {
+        info!(
+            "Migrated settings from version {} to {}",
+            version, SETTINGS_VERSION
+        );
+    }
+
+    let mut settings: Settings = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    settings.version = SETTINGS_VERSION;
+    Ok(settings)
+}
+
+// Loads the settings from pPath. Never fails, a missing or unreadable file results in the default settings
+pub fn load_settings(pPath: &Path) -> Settings {
+    let contents = match fs::read_to_string(pPath) {
+        Ok(x) => x,
+        Err(e) => {
+            info!("No settings loaded from {:?} - {:?}", pPath, e);
+            return Settings::default();
+        }
+    };
+
+    match parse_settings(&contents) {
+        Ok(x) => {
+            info!("Loaded settings from {:?}", pPath);
+            x
+        }
+        Err(e) => {
+            warn!("Failed to parse settings in {:?} - {}", pPath, e);
+            backup_corrupt_file(pPath);
+            Settings::default()
+        }
+    }
+}
+
+// Replaces the alert rules in pSettings with the ones in the legacy alert rules file at pPath, if there is one, and
+// renames the file so it's only imported once. Returns whether pSettings was changed
+pub fn import_legacy_alert_rules(pSettings: &mut Settings, pPath: &Path) -> bool {
+    if pPath.exists() == false {
+        return false;
+    }
+
+    let mut imported_path = pPath.as_os_str().to_owned();
+    imported_path.push(".imported");
+    if let Err(e) = fs::rename(pPath, &imported_path) {
+        warn!(
+            "Failed to move legacy alert rules file {:?} - {:?}",
+            pPath, e
+        );
+        return false;
+    }
+
+    let legacy: LegacyAlertRules = match load_json(Path::new(&imported_path)) {
+        Some(x) => x,
+        None => return false,
+    };
+    if legacy.version != LEGACY_ALERT_RULES_VERSION {
+        warn!(
+            "Ignoring legacy alert rules in {:?} with unsupported version {} (expected {})",
+            pPath, legacy.version, LEGACY_ALERT_RULES_VERSION
+        );
+        return false;
+    }
+
+    info!(
+        "Imported {} alert rules from {:?}",
+        legacy.rules.len(),
+        pPath
+    );
+    pSettings.alert_rules = legacy.rules;
+    true
+}
+
+pub fn save_settings(pPath: &Path, pSettings: &Settings) -> bool {
+    let result = save_json(pPath, pSettings);
+    if result == true {
+        debug!("Saved settings to {:?}", pPath);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        import_legacy_alert_rules, load_settings, save_settings, Settings, SETTINGS_VERSION,
+    };
+    use crate::chat_alerts::{AlertRule, AlertRuleKind};
+    use crate::chat_log::ChatLogLimit;
+    use crate::infra::{install_log_handler, LogLevel, ModuleLogLevel};
+    use std::{fs, path::PathBuf, time::Duration};
+
+    fn test_path(pName: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "arcdps_squad_manager_settings_test_{}_{}.json",
+            pName,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("json.corrupt"));
+
+        path
+    }
+
+    // Test that changed settings are saved and loaded back unchanged
+    #[test]
+    fn roundtrip() {
+        install_log_handler().unwrap();
+
+        let path = test_path("roundtrip");
+        assert_eq!(load_settings(&path), Settings::default());
+
+        let mut settings = Settings::default();
+        settings.windows.chat_log = true;
+        settings.windows.pinboard = false;
+        settings.chat_log_wrap_width = 450.0;
+        settings.ready_check_limits.soft_limit = Duration::from_secs(20);
+        settings.timeline_size = 500;
+        settings.chat_log_limits.total = ChatLogLimit::Messages(500);
+        settings.pinboard.expiry = Duration::from_secs(120);
+        settings.alert_rules.push(AlertRule {
+            kind: AlertRuleKind::Keyword("ready".to_string()),
+            enabled: false,
+        });
+        settings.log.level = LogLevel::Info;
+        settings.log.module_levels.push(ModuleLogLevel {
+            module: "chat_log".to_string(),
+            level: LogLevel::Trace,
+        });
+        settings.log.crates.push("arcdps".to_string());
+        assert!(save_settings(&path, &settings));
+
+        assert_eq!(load_settings(&path), settings);
+        let _ = fs::remove_file(&path);
+    }
+
+    // Test that settings missing from the file take their default value
+    #[test]
+    fn missing_fields() {
+        install_log_handler().unwrap();
+
+        let path = test_path("missing_fields");
+        let contents = r#"{"version": 1, "windows": {"roster": true}, "ready_check_limits": {"hard_limit": 90}}"#;
+        fs::write(&path, contents).unwrap();
+
+        let mut expected = Settings::default();
+        expected.windows.roster = true;
+        expected.ready_check_limits.hard_limit = Duration::from_secs(90);
+        assert_eq!(load_settings(&path), expected);
+        let _ = fs::remove_file(&path);
+    }
+
+    // Test that unreadable files and files from newer versions fall back to the defaults and are kept as a backup
+    #[test]
+    fn corrupt_file() {
+        install_log_handler().unwrap();
+
+        for (name, contents) in [
+            ("truncated", r#"{"version": 1, "windows": {"#.to_string()),
+            ("not_an_object", "[1, 2, 3]".to_string()),
+            (
+                "wrong_type",
+                r#"{"chat_log_wrap_width": "wide"}"#.to_string(),
+            ),
+            (
+                "newer",
+                format!(r#"{{"version": {}}}"#, SETTINGS_VERSION + 1),
+            ),
+        ] {
+            let path = test_path(name);
+            fs::write(&path, &contents).unwrap();
+
+            assert_eq!(load_settings(&path), Settings::default(), "{}", name);
+            assert!(path.exists() == false, "{}", name);
+            let backup_path = path.with_extension("json.corrupt");
+            assert_eq!(
+                fs::read_to_string(&backup_path).unwrap(),
+                contents,
+                "{}",
+                name
+            );
+            let _ = fs::remove_file(&backup_path);
+        }
+    }
+
+    // Test that the alert rules of the legacy file replace the settings' rules once and the file is kept renamed
+    #[test]
+    fn legacy_alert_rules() {
+        install_log_handler().unwrap();
+
+        let path = test_path("legacy_alert_rules");
+        let imported_path = path.with_extension("json.imported");
+        let _ = fs::remove_file(&imported_path);
+
+        let mut settings = Settings::default();
+        assert_eq!(import_legacy_alert_rules(&mut settings, &path), false);
+        assert_eq!(settings, Settings::default());
+
+        let contents = r#"{"version": 1, "rules": [{"kind": "Mention", "enabled": false}, {"kind": {"Keyword": "ready"}, "enabled": true}]}"#;
+        fs::write(&path, contents).unwrap();
+        assert_eq!(import_legacy_alert_rules(&mut settings, &path), true);
+        assert_eq!(
+            settings.alert_rules,
+            vec![
+                AlertRule {
+                    kind: AlertRuleKind::Mention,
+                    enabled: false,
+                },
+                AlertRule {
+                    kind: AlertRuleKind::Keyword("ready".to_string()),
+                    enabled: true,
+                },
+            ]
+        );
+        assert!(path.exists() == false);
+        assert_eq!(fs::read_to_string(&imported_path).unwrap(), contents);
+
+        let mut settings = Settings::default();
+        assert_eq!(import_legacy_alert_rules(&mut settings, &path), false);
+        assert_eq!(settings, Settings::default());
+
+        fs::write(&path, r#"{"version": 2, "rules": []}"#).unwrap();
+        assert_eq!(import_legacy_alert_rules(&mut settings, &path), false);
+        assert_eq!(settings, Settings::default());
+        assert!(path.exists() == false);
+        let _ = fs::remove_file(&imported_path);
+    }
+}
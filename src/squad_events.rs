@@ -0,0 +1,121 @@
+#![allow(non_snake_case)]
+
+use crate::ready_check_history::ReadyCheckAbortReason;
+use arcdps::UserRole;
+use chrono::{DateTime, Local};
+use std::{collections::VecDeque, time::Duration};
+
+// Events that weren't drained are dropped oldest first past this many
+const MAX_QUEUED_EVENTS: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SquadEventKind {
+    // join_time is the time the member joined according to the game (seconds since the unix epoch), which is earlier
+    // than the event for everyone already in the squad when self joined it
+    MemberJoined {
+        account_name: String,
+        role: UserRole,
+        subgroup: u8,
+        join_time: u64,
+    },
+    MemberLeft {
+        account_name: String,
+    },
+    RoleChanged {
+        account_name: String,
+        old_role: UserRole,
+        new_role: UserRole,
+    },
+    SubgroupChanged {
+        account_name: String,
+        old_subgroup: u8,
+        new_subgroup: u8,
+    },
+    ReadyStatusChanged {
+        account_name: String,
+        is_ready: bool,
+    },
+    // None if nobody is squad leader, e.g. between the old squad leader losing the role and the new one getting it
+    SquadLeaderChanged {
+        old_leader: Option<String>,
+        new_leader: Option<String>,
+    },
+    ReadyCheckStarted {
+        started_by: String,
+    },
+    // id is the id of the ReadyCheckRecord in the ready check history
+    ReadyCheckCompleted {
+        id: u64,
+        duration: Duration,
+    },
+    ReadyCheckAborted {
+        id: u64,
+        duration: Duration,
+        reason: ReadyCheckAbortReason,
+    },
+    // Self left the squad, which implicitly removes everyone else as well. No MemberLeft events are produced for them
+    SelfLeftSquad,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SquadEvent {
+    pub time: DateTime<Local>,
+    pub kind: SquadEventKind,
+}
+
+pub type SquadEventCallback = Box<dyn FnMut(&SquadEvent) + Send + Sync>;
+
+// Events produced by the squad tracker. They are handed to every registered callback as soon as they happen, and are
+// queued until drained for consumers that poll (such as the GUI, once per frame)
+pub struct SquadEventQueue {
+    events: VecDeque<SquadEvent>,
+    // Events since the last take_recent, for the parts of the tracker that are kept up to date from its own events
+    recent: Vec<SquadEvent>,
+    callbacks: Vec<SquadEventCallback>,
+}
+
+impl SquadEventQueue {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            recent: Vec::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, pTime: DateTime<Local>, pKind: SquadEventKind) {
+        let event = SquadEvent {
+            time: pTime,
+            kind: pKind,
+        };
+        debug!("{:?}", event);
+
+        for callback in self.callbacks.iter_mut() {
+            callback(&event);
+        }
+
+        self.recent.push(event.clone());
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // Events pushed since the last call, regardless of whether they were drained
+    pub fn take_recent(&mut self) -> Vec<SquadEvent> {
+        std::mem::take(&mut self.recent)
+    }
+
+    pub fn subscribe(&mut self, pCallback: SquadEventCallback) {
+        self.callbacks.push(pCallback);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Events since the last call, ordered from oldest to newest
+    pub fn drain(&mut self) -> Vec<SquadEvent> {
+        self.events.drain(..).collect()
+    }
+}
@@ -0,0 +1,306 @@
+#![allow(non_snake_case)]
+
+use crate::squad_events::{SquadEvent, SquadEventKind};
+use arcdps::UserRole;
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimelineEventKind {
+    Joined {
+        role: UserRole,
+        subgroup: u8,
+    },
+    Left,
+    RoleChanged {
+        old_role: UserRole,
+        new_role: UserRole,
+    },
+    SubgroupChanged {
+        old_subgroup: u8,
+        new_subgroup: u8,
+    },
+    // Self left the squad, which implicitly removes everyone else as well
+    SelfLeft,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimelineEntry {
+    pub time: DateTime<Local>,
+    pub account_name: String,
+    pub kind: TimelineEventKind,
+}
+
+// Every change to the squad roster, in the order it was observed, built from the squad events. The timeline is kept
+// after self leaves the squad so it can still be inspected, and is only reset once a new squad is joined. Once it is
+// full the oldest entries are dropped, which is counted so it can be shown
+pub struct SquadTimeline {
+    entries: VecDeque<TimelineEntry>,
+    max_entries: usize,
+    // Entries dropped from the current squad's timeline to stay within max_entries
+    dropped_entries: usize,
+    // Self left the squad, so the next member joining starts a new timeline
+    squad_ended: bool,
+}
+
+impl SquadTimeline {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries,
+            dropped_entries: 0,
+            squad_ended: false,
+        }
+    }
+
+    pub fn get_max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    // Applies the new limit right away, dropping the oldest entries if the timeline is over it
+    pub fn set_max_entries(&mut self, pMaxEntries: usize) {
+        self.max_entries = pMaxEntries;
+        while self.entries.len() > self.max_entries.max(1) {
+            self.entries.pop_front();
+            self.dropped_entries += 1;
+        }
+    }
+
+    pub fn dropped_entries(&self) -> usize {
+        self.dropped_entries
+    }
+
+    // Adds the entry for pEvent if it is a roster change. pSelfAccountName is who SelfLeftSquad is about
+    pub fn handle_event(&mut self, pEvent: &SquadEvent, pSelfAccountName: &str) {
+        let (account_name, kind) = match &pEvent.kind {
+            SquadEventKind::MemberJoined {
+                account_name,
+                role,
+                subgroup,
+                ..
+            } => {
+                if self.squad_ended == true {
+                    self.clear();
+                    self.squad_ended = false;
+                }
+                let kind = TimelineEventKind::Joined {
+                    role: *role,
+                    subgroup: *subgroup,
+                };
+                (account_name.as_str(), kind)
+            }
+            SquadEventKind::MemberLeft { account_name } => {
+                (account_name.as_str(), TimelineEventKind::Left)
+            }
+            SquadEventKind::RoleChanged {
+                account_name,
+                old_role,
+                new_role,
+            } => {
+                let kind = TimelineEventKind::RoleChanged {
+                    old_role: *old_role,
+                    new_role: *new_role,
+                };
+                (account_name.as_str(), kind)
+            }
+            SquadEventKind::SubgroupChanged {
+                account_name,
+                old_subgroup,
+                new_subgroup,
+            } => {
+                let kind = TimelineEventKind::SubgroupChanged {
+                    old_subgroup: *old_subgroup,
+                    new_subgroup: *new_subgroup,
+                };
+                (account_name.as_str(), kind)
+            }
+            SquadEventKind::SelfLeftSquad => {
+                self.squad_ended = true;
+                (pSelfAccountName, TimelineEventKind::SelfLeft)
+            }
+            _ => return,
+        };
+
+        self.add(pEvent.time, account_name, kind);
+    }
+
+    fn add(&mut self, pTime: DateTime<Local>, pAccountName: &str, pKind: TimelineEventKind) {
+        debug!("{} {:?} at {}", pAccountName, pKind, pTime);
+        while self.entries.len() >= self.max_entries.max(1) {
+            self.entries.pop_front();
+            self.dropped_entries += 1;
+        }
+        self.entries.push_back(TimelineEntry {
+            time: pTime,
+            account_name: pAccountName.to_string(),
+            kind: pKind,
+        });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped_entries = 0;
+    }
+
+    // Entries ordered from oldest to newest
+    pub fn entries(&self) -> &VecDeque<TimelineEntry> {
+        &self.entries
+    }
+}
+
+pub struct TimelineFilter {
+    // Part of the account name, case insensitive
+    pub account_name: String,
+    pub show_joins: bool,
+    pub show_leaves: bool,
+    pub show_role_changes: bool,
+    pub show_subgroup_changes: bool,
+}
+
+impl TimelineFilter {
+    pub fn new() -> Self {
+        Self {
+            account_name: String::new(),
+            show_joins: true,
+            show_leaves: true,
+            show_role_changes: true,
+            show_subgroup_changes: true,
+        }
+    }
+
+    fn kind_matches(&self, pKind: &TimelineEventKind) -> bool {
+        match pKind {
+            TimelineEventKind::Joined { .. } => self.show_joins,
+            TimelineEventKind::Left | TimelineEventKind::SelfLeft => self.show_leaves,
+            TimelineEventKind::RoleChanged { .. } => self.show_role_changes,
+            TimelineEventKind::SubgroupChanged { .. } => self.show_subgroup_changes,
+        }
+    }
+
+    // The entries of pTimeline that pass the filter, newest first
+    pub fn apply<'a>(
+        &'a self,
+        pTimeline: &'a SquadTimeline,
+    ) -> impl Iterator<Item = &'a TimelineEntry> + 'a {
+        let account_name = self.account_name.to_lowercase();
+        pTimeline.entries().iter().rev().filter(move |x| {
+            self.kind_matches(&x.kind) == true
+                && (account_name.is_empty() == true
+                    || x.account_name.to_lowercase().contains(&account_name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SquadTimeline, TimelineEventKind, TimelineFilter};
+    use arcdps::UserRole;
+    use chrono::Local;
+
+    fn timeline() -> SquadTimeline {
+        let mut result = SquadTimeline::new(10);
+        let now = Local::now();
+        let joined = TimelineEventKind::Joined {
+            role: UserRole::Member,
+            subgroup: 0,
+        };
+        result.add(now, ":Peer.1234", joined.clone());
+        result.add(now, ":other.5678", joined);
+        result.add(
+            now,
+            ":peer.1234",
+            TimelineEventKind::RoleChanged {
+                old_role: UserRole::Member,
+                new_role: UserRole::Lieutenant,
+            },
+        );
+        result.add(
+            now,
+            ":other.5678",
+            TimelineEventKind::SubgroupChanged {
+                old_subgroup: 0,
+                new_subgroup: 1,
+            },
+        );
+        result.add(now, ":other.5678", TimelineEventKind::Left);
+        result.add(now, ":self.1111", TimelineEventKind::SelfLeft);
+
+        result
+    }
+
+    fn filtered(pFilter: &TimelineFilter, pTimeline: &SquadTimeline) -> Vec<String> {
+        pFilter
+            .apply(pTimeline)
+            .map(|x| format!("{} {:?}", x.account_name, x.kind))
+            .collect()
+    }
+
+    // Test that the filter matches account names case insensitively and hides the unchecked kinds of entries
+    #[test]
+    fn filter() {
+        let timeline = timeline();
+        let mut filter = TimelineFilter::new();
+        assert_eq!(filtered(&filter, &timeline).len(), 6);
+
+        filter.account_name = "PEER".to_string();
+        assert_eq!(
+            filtered(&filter, &timeline),
+            vec![
+                ":peer.1234 RoleChanged { old_role: Member, new_role: Lieutenant }",
+                ":Peer.1234 Joined { role: Member, subgroup: 0 }",
+            ]
+        );
+
+        filter.account_name.clear();
+        filter.show_joins = false;
+        filter.show_role_changes = false;
+        filter.show_subgroup_changes = false;
+        assert_eq!(
+            filtered(&filter, &timeline),
+            vec![":self.1111 SelfLeft", ":other.5678 Left"]
+        );
+
+        filter.show_leaves = false;
+        filter.show_subgroup_changes = true;
+        assert_eq!(
+            filtered(&filter, &timeline),
+            vec![":other.5678 SubgroupChanged { old_subgroup: 0, new_subgroup: 1 }"]
+        );
+    }
+
+    // Test that a full timeline drops its oldest entries and counts them, also when the limit is lowered, until the
+    // timeline is reset
+    #[test]
+    fn max_entries() {
+        let mut timeline = SquadTimeline::new(3);
+        for i in 0..5 {
+            timeline.add(
+                Local::now(),
+                &format!("member{}", i),
+                TimelineEventKind::Left,
+            );
+        }
+
+        let names = |pTimeline: &SquadTimeline| {
+            pTimeline
+                .entries()
+                .iter()
+                .map(|x| x.account_name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&timeline), vec!["member2", "member3", "member4"]);
+        assert_eq!(timeline.dropped_entries(), 2);
+
+        timeline.set_max_entries(2);
+        assert_eq!(names(&timeline), vec!["member3", "member4"]);
+        assert_eq!(timeline.dropped_entries(), 3);
+
+        timeline.set_max_entries(10);
+        timeline.add(Local::now(), "member5", TimelineEventKind::Left);
+        assert_eq!(names(&timeline), vec!["member3", "member4", "member5"]);
+        assert_eq!(timeline.dropped_entries(), 3);
+
+        timeline.clear();
+        assert_eq!(timeline.dropped_entries(), 0);
+    }
+}
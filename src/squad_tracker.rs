@@ -1,10 +1,29 @@
 #![allow(non_snake_case)]
 
-use arcdps::{UserInfo, UserInfoIter, UserRole};
+use crate::attendance::{AttendanceSession, AttendanceTracker};
+use crate::chat_log::SenderInfo;
+use crate::clock::{Clock, SystemClock};
+use crate::persistence::{load_json, save_json};
+use crate::ready_check_history::{
+    ReadyCheckAbortReason, ReadyCheckHistory, ReadyCheckMemberRecord, ReadyCheckOutcome,
+    ReadyCheckRecord, ReadyStatusChange, ReadyStatusEvent,
+};
+use crate::squad_events::{SquadEvent, SquadEventCallback, SquadEventKind, SquadEventQueue};
+use crate::squad_timeline::SquadTimeline;
+use arcdps::{UserInfo, UserRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+const READY_CHECK_STATS_VERSION: u32 = 1;
+const READY_CHECK_HISTORY_SIZE: usize = 100;
+const PENDING_USER_HISTORY_SIZE: usize = 100;
+pub const DEFAULT_TIMELINE_SIZE: usize = 10_000;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SquadMemberState {
     pub join_time: u64,
@@ -39,26 +58,59 @@ impl SquadMemberState {
     }
 }
 
-// Returns true if ready check was aborted
-fn handle_ready_status_changed(
-    pExistingUser: (&str, &mut SquadMemberState),
-    pNow: &Instant,
-) -> Option<Instant> {
-    let mut ready_check_start_time: Option<Instant> = None;
-
-    if pExistingUser.1.role == UserRole::SquadLeader {
-        if pExistingUser.1.is_ready == true {
-            info!("Ready check started at {:?}", pNow);
-        } else {
-            info!(
-                "Ready check which was started at {:?} was finished at {:?}",
-                pExistingUser.1.last_ready_time, pNow
-            );
+// A user that was invited to the squad or applied to join it, but isn't a squad member yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingUserState {
+    // Time of the invite or application, as reported by the game
+    pub join_time: u64,
+    pub role: UserRole,
+}
 
-            ready_check_start_time = pExistingUser.1.last_ready_time;
-        }
+impl PendingUserState {
+    // Time since the invite or application, zero if the game reported a time after pNow
+    pub fn waiting(&self, pNow: &DateTime<Local>) -> Duration {
+        Duration::from_secs((pNow.timestamp() - self.join_time as i64).max(0) as u64)
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PendingUserOutcome {
+    // The user joined the squad
+    Accepted,
+    // The user stopped being pending without joining the squad - the invite was cancelled or declined, or the
+    // application was withdrawn or denied
+    Cancelled,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingUserRecord {
+    pub account_name: String,
+    pub role: UserRole,
+    pub join_time: u64,
+    pub resolved_at: DateTime<Local>,
+    pub outcome: PendingUserOutcome,
+}
+
+// Ready check statistics of a single squad member as they are stored on disk. join_time is kept so that the statistics
+// are only restored if the member is still part of the same squad session (rejoining or joining a different squad
+// changes the join time)
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PersistedMemberStats {
+    pub join_time: u64,
+    pub total_ready_check_time: Duration,
+    pub last_unready_duration: Option<Duration>,
+}
 
+#[derive(Debug, Deserialize, Serialize)]
+struct PersistedReadyCheckStats {
+    version: u32,
+    self_account_name: String,
+    members: HashMap<String, PersistedMemberStats>,
+}
+
+// Records when a member readied or unreadied. Whether that starts or ends a ready check is up to the caller, since it
+// depends on who the running ready check belongs to rather than only on the role of the member
+fn handle_ready_status_changed(pExistingUser: (&str, &mut SquadMemberState), pNow: &Instant) {
     if pExistingUser.1.is_ready == true {
         pExistingUser.1.last_ready_time = Some(*pNow);
         info!("User readied up - {:?}", pExistingUser);
@@ -66,27 +118,72 @@ fn handle_ready_status_changed(
         pExistingUser.1.last_unready_time = Some(*pNow);
         info!("User unreadied - {:?}", pExistingUser);
     }
+}
 
-    ready_check_start_time
+// Time limits for a running ready check. Passing the soft limit is only a warning, aborted ready checks that ran
+// past the hard limit are assumed to have run out of time rather than being cancelled
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ReadyCheckLimits {
+    #[serde(with = "crate::persistence::duration_secs")]
+    pub soft_limit: Duration,
+    #[serde(with = "crate::persistence::duration_secs")]
+    pub hard_limit: Duration,
 }
 
-// pSuccessful indicates whether the ready check was finished because everyone readied up (true) or because it was
-// aborted (false)
-fn handle_ready_check_finished(
-    pSquadMembers: &mut HashMap<String, SquadMemberState>,
+impl Default for ReadyCheckLimits {
+    fn default() -> Self {
+        Self {
+            soft_limit: Duration::from_secs(15),
+            hard_limit: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadyCheckLimit {
+    Soft,
+    Hard,
+}
+
+// Emitted once per ready check and limit when the limit passes while the ready check is still running
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadyCheckLimitEvent {
+    pub limit: ReadyCheckLimit,
+    pub elapsed: Duration,
+    pub not_ready: Vec<String>,
+}
+
+// Result of evaluating a ready check once the squad leader unreadied
+struct ReadyCheckResult {
+    // true if the ready check was finished because everyone readied up, false if it was aborted
+    successful: bool,
+    // Time until readying for every squad member that was counted as ready
+    reaction_times: HashMap<String, Duration>,
+    // Members that readied during the ready check but unreadied again before it finished
+    unreadied: Vec<String>,
+    // Members that didn't ready during the ready check
+    not_ready: Vec<String>,
+}
+
+// Determines who was ready at the end of a ready check, without changing any statistics
+fn evaluate_ready_check(
+    pSquadMembers: &HashMap<String, SquadMemberState>,
     pReadyCheckStartTime: &Instant,
     pNow: &Instant,
-) {
-    let mut users: Vec<(&String, &mut SquadMemberState, Duration)> = Vec::new();
+) -> ReadyCheckResult {
+    let mut reaction_times: HashMap<String, Duration> = HashMap::new();
+    let mut unreadied: Vec<String> = Vec::new();
+    let mut not_ready: Vec<String> = Vec::new();
 
-    let squad_member_count = pSquadMembers.len();
-    for (account_name, state) in pSquadMembers.iter_mut() {
+    for (account_name, state) in pSquadMembers.iter() {
         if let Some(ready_time) = state.last_ready_time {
             if ready_time < *pReadyCheckStartTime {
                 info!(
                     "User readied before ready check started - {:?} {:?} {:?}",
                     pReadyCheckStartTime, account_name, state
                 );
+                not_ready.push(account_name.clone());
                 continue;
             }
 
@@ -97,52 +194,440 @@ fn handle_ready_check_finished(
                     "User unreadied during ready check - {:?} {:?} {:?}",
                     pReadyCheckStartTime, account_name, state
                 );
+                unreadied.push(account_name.clone());
                 continue;
             }
 
             let time_spent_unready = ready_time - *pReadyCheckStartTime;
-            users.push((account_name, state, time_spent_unready));
+            reaction_times.insert(account_name.clone(), time_spent_unready);
+        } else {
+            not_ready.push(account_name.clone());
         }
     }
+    unreadied.sort();
+    not_ready.sort();
+
+    ReadyCheckResult {
+        successful: reaction_times.len() == pSquadMembers.len(),
+        reaction_times,
+        unreadied,
+        not_ready,
+    }
+}
+
+fn handle_ready_check_finished(
+    pSquadMembers: &mut HashMap<String, SquadMemberState>,
+    pReadyCheckStartTime: &Instant,
+    pNow: &Instant,
+) -> ReadyCheckResult {
+    let result = evaluate_ready_check(pSquadMembers, pReadyCheckStartTime, pNow);
 
-    // if successful
-    if users.len() == squad_member_count {
+    if result.successful == true {
         info!(
             "Ready check was successful ({} players readied)",
-            users.len()
+            result.reaction_times.len()
         );
-        for (account_name, state, time_spent_unready) in users {
+        for (account_name, time_spent_unready) in result.reaction_times.iter() {
             debug!(
                 "{:?} spent {:?} in ready check",
                 account_name, time_spent_unready
             );
-            state.last_unready_duration = Some(time_spent_unready);
-            state.total_ready_check_time += time_spent_unready;
+            let state = pSquadMembers.get_mut(account_name).unwrap();
+            state.last_unready_duration = Some(*time_spent_unready);
+            state.total_ready_check_time += *time_spent_unready;
         }
-    } else {
-        info!("Ready check was aborted ({} players readied)", users.len());
     }
+
+    result
+}
+
+// Bookkeeping for the ready check that is currently in progress, used to build a ReadyCheckRecord once it finishes
+struct ActiveReadyCheck {
+    start_time: Instant,
+    started_by: String,
+    // The member whose unready ends the ready check. This is started_by unless the ready check was carried over to a
+    // new squad leader, and stays the same if the leader loses the squad leader role without anyone else getting it
+    leader: String,
+    events: HashMap<String, Vec<ReadyStatusEvent>>,
+    joined: Vec<String>,
+    left: Vec<String>,
+    soft_limit_passed: bool,
+    hard_limit_passed: bool,
+}
+
+impl ActiveReadyCheck {
+    fn new(pStartedBy: &str, pStartTime: Instant) -> Self {
+        Self {
+            start_time: pStartTime,
+            started_by: pStartedBy.to_string(),
+            leader: pStartedBy.to_string(),
+            events: HashMap::new(),
+            joined: Vec::new(),
+            left: Vec::new(),
+            soft_limit_passed: false,
+            hard_limit_passed: false,
+        }
+    }
+
+    fn add_joined(&mut self, pAccountName: &str, pNow: &Instant) {
+        // Members that are added in the same squad update which started the ready check were already in the squad,
+        // they just weren't known to us yet
+        if *pNow > self.start_time {
+            self.joined.push(pAccountName.to_string());
+        }
+    }
+
+    fn add_left(&mut self, pAccountName: &str) {
+        self.left.push(pAccountName.to_string());
+    }
+
+    fn classify(
+        &self,
+        pResult: &ReadyCheckResult,
+        pSquadLeader: &str,
+        pHardLimit: Duration,
+        pNow: &Instant,
+    ) -> ReadyCheckOutcome {
+        if pResult.successful == true {
+            return ReadyCheckOutcome::Succeeded;
+        }
+
+        let reason = if pResult.unreadied.is_empty() == false {
+            ReadyCheckAbortReason::MemberUnreadied {
+                accounts: pResult.unreadied.clone(),
+            }
+        } else if self.joined.is_empty() == false || self.left.is_empty() == false {
+            let mut joined = self.joined.clone();
+            let mut left = self.left.clone();
+            joined.sort();
+            left.sort();
+            ReadyCheckAbortReason::RosterChanged { joined, left }
+        } else if pNow.saturating_duration_since(self.start_time) >= pHardLimit {
+            ReadyCheckAbortReason::TimedOut {
+                not_ready: pResult.not_ready.clone(),
+            }
+        } else {
+            ReadyCheckAbortReason::LeaderCancelled {
+                leader: pSquadLeader.to_string(),
+                not_ready: pResult.not_ready.clone(),
+            }
+        };
+
+        info!("Ready check was aborted - {:?}", reason);
+        ReadyCheckOutcome::Aborted(reason)
+    }
+
+    // Limits which pElapsed is past but that weren't reported yet
+    fn due_limits(&self, pElapsed: Duration, pLimits: &ReadyCheckLimits) -> Vec<ReadyCheckLimit> {
+        let mut result = Vec::new();
+        if self.soft_limit_passed == false && pElapsed >= pLimits.soft_limit {
+            result.push(ReadyCheckLimit::Soft);
+        }
+        if self.hard_limit_passed == false && pElapsed >= pLimits.hard_limit {
+            result.push(ReadyCheckLimit::Hard);
+        }
+
+        result
+    }
+
+    fn add_event(&mut self, pAccountName: &str, pNow: &Instant, pIsReady: bool) {
+        let change = if pIsReady == true {
+            ReadyStatusChange::Readied
+        } else {
+            ReadyStatusChange::Unreadied
+        };
+
+        self.events
+            .entry(pAccountName.to_string())
+            .or_default()
+            .push(ReadyStatusEvent {
+                offset: pNow.saturating_duration_since(self.start_time),
+                change,
+            });
+    }
+
+    fn into_record(
+        mut self,
+        pSquadMembers: &HashMap<String, SquadMemberState>,
+        pOutcome: ReadyCheckOutcome,
+        pReactionTimes: &HashMap<String, Duration>,
+        pNow: &Instant,
+        pNowLocal: DateTime<Local>,
+    ) -> ReadyCheckRecord {
+        let duration = pNow.saturating_duration_since(self.start_time);
+        let end_time = pNowLocal;
+        let start_time =
+            end_time - chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::zero());
+
+        let mut members = pSquadMembers
+            .keys()
+            .map(|account_name| ReadyCheckMemberRecord {
+                account_name: account_name.clone(),
+                reaction_time: pReactionTimes.get(account_name).copied(),
+                events: self.events.remove(account_name).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        members.sort_by(|lhs, rhs| lhs.account_name.cmp(&rhs.account_name));
+
+        ReadyCheckRecord {
+            id: 0, // assigned by ReadyCheckHistory
+            start_time,
+            end_time,
+            duration,
+            outcome: pOutcome,
+            started_by: self.started_by,
+            members,
+        }
+    }
+}
+
+// How a ready check came to an end
+enum ReadyCheckEnd<'a> {
+    // The leader of the ready check unreadied
+    LeaderUnreadied,
+    // Someone else took over as squad leader without carrying over the ready check, or the leader of the ready check
+    // left the squad (None)
+    LeaderChanged { new_leader: Option<&'a str> },
+}
+
+// Records pReadyCheck in the history and produces the matching event. Returns true if the statistics of the squad
+// members changed, which only happens when the ready check succeeded
+#[allow(clippy::too_many_arguments)]
+fn end_ready_check(
+    pReadyCheck: ActiveReadyCheck,
+    pEnd: ReadyCheckEnd,
+    pSquadMembers: &mut HashMap<String, SquadMemberState>,
+    pHardLimit: Duration,
+    pHistory: &mut ReadyCheckHistory,
+    pEvents: &mut SquadEventQueue,
+    pNow: &Instant,
+    pNowLocal: DateTime<Local>,
+) -> bool {
+    info!(
+        "Ready check which was started at {:?} by {} was ended at {:?}",
+        pReadyCheck.start_time, pReadyCheck.started_by, pNow
+    );
+
+    let (result, outcome) = match pEnd {
+        ReadyCheckEnd::LeaderUnreadied => {
+            let result = handle_ready_check_finished(pSquadMembers, &pReadyCheck.start_time, pNow);
+            let outcome = pReadyCheck.classify(&result, &pReadyCheck.leader, pHardLimit, pNow);
+            (result, outcome)
+        }
+        ReadyCheckEnd::LeaderChanged { new_leader } => {
+            let result = evaluate_ready_check(pSquadMembers, &pReadyCheck.start_time, pNow);
+            let reason = ReadyCheckAbortReason::LeaderChanged {
+                old_leader: pReadyCheck.leader.clone(),
+                new_leader: new_leader.map(|x| x.to_string()),
+                not_ready: result.not_ready.clone(),
+            };
+            info!("Ready check was aborted - {:?}", reason);
+            (result, ReadyCheckOutcome::Aborted(reason))
+        }
+    };
+    let stats_changed = outcome == ReadyCheckOutcome::Succeeded;
+
+    let record = pReadyCheck.into_record(
+        pSquadMembers,
+        outcome,
+        &result.reaction_times,
+        pNow,
+        pNowLocal,
+    );
+    let duration = record.duration;
+    let reason = match &record.outcome {
+        ReadyCheckOutcome::Succeeded => None,
+        ReadyCheckOutcome::Aborted(reason) => Some(reason.clone()),
+    };
+    let id = pHistory.add(record);
+    let kind = match reason {
+        None => SquadEventKind::ReadyCheckCompleted { id, duration },
+        Some(reason) => SquadEventKind::ReadyCheckAborted {
+            id,
+            duration,
+            reason,
+        },
+    };
+    pEvents.push(pNowLocal, kind);
+
+    stats_changed
+}
+
+fn add_pending_user_record(
+    pHistory: &mut VecDeque<PendingUserRecord>,
+    pAccountName: &str,
+    pState: PendingUserState,
+    pOutcome: PendingUserOutcome,
+    pNow: DateTime<Local>,
+) {
+    info!(
+        "Pending user {} was resolved - {:?} {:?}",
+        pAccountName, pState, pOutcome
+    );
+
+    while pHistory.len() >= PENDING_USER_HISTORY_SIZE {
+        pHistory.pop_front();
+    }
+    pHistory.push_back(PendingUserRecord {
+        account_name: pAccountName.to_string(),
+        role: pState.role,
+        join_time: pState.join_time,
+        resolved_at: pNow,
+        outcome: pOutcome,
+    });
 }
 
 pub struct SquadTracker {
     self_account_name: String,
     squad_members: HashMap<String, SquadMemberState>,
+    pending_users: HashMap<String, PendingUserState>,
+    pending_user_history: VecDeque<PendingUserRecord>,
+    stats_path: Option<PathBuf>,
+    // Statistics loaded from disk for members which haven't been seen in a squad update yet
+    restored_stats: HashMap<String, PersistedMemberStats>,
+    stats_dirty: bool,
+    // The member who most recently got the squad leader role, None once they lost it again
+    squad_leader: Option<String>,
+    active_ready_check: Option<ActiveReadyCheck>,
+    ready_check_history: ReadyCheckHistory,
+    ready_check_limits: ReadyCheckLimits,
+    ready_check_limit_events: Vec<ReadyCheckLimitEvent>,
+    // The timeline and attendance are only updated from the squad events
+    timeline: SquadTimeline,
+    attendance: AttendanceTracker,
+    events: SquadEventQueue,
+    clock: Arc<dyn Clock>,
 }
 
 impl SquadTracker {
     pub fn new(self_account_name: &str) -> Self {
+        Self::with_clock(self_account_name, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(self_account_name: &str, pClock: Arc<dyn Clock>) -> Self {
         Self {
             self_account_name: String::from(self_account_name),
             squad_members: HashMap::new(),
+            pending_users: HashMap::new(),
+            pending_user_history: VecDeque::new(),
+            stats_path: None,
+            restored_stats: HashMap::new(),
+            stats_dirty: false,
+            squad_leader: None,
+            active_ready_check: None,
+            ready_check_history: ReadyCheckHistory::new(READY_CHECK_HISTORY_SIZE),
+            ready_check_limits: ReadyCheckLimits::default(),
+            ready_check_limit_events: Vec::new(),
+            timeline: SquadTimeline::new(DEFAULT_TIMELINE_SIZE),
+            attendance: AttendanceTracker::new(),
+            events: SquadEventQueue::new(),
+            clock: pClock,
+        }
+    }
+
+    // Current time according to the clock of the tracker, which is what all times in the squad member state are
+    // based on
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn now_local(&self) -> DateTime<Local> {
+        self.clock.now_local()
+    }
+
+    // Loads ready check statistics saved by a previous session and enables saving them to pPath through
+    // save_ready_check_stats. The loaded statistics are applied to squad members as they show up in squad updates
+    pub fn load_ready_check_stats(&mut self, pPath: &Path) {
+        self.stats_path = Some(pPath.to_path_buf());
+        self.restored_stats.clear();
+
+        let stats: PersistedReadyCheckStats = match load_json(pPath) {
+            Some(x) => x,
+            None => return,
+        };
+
+        if stats.version != READY_CHECK_STATS_VERSION {
+            warn!(
+                "Ignoring ready check stats in {:?} with unsupported version {} (expected {})",
+                pPath, stats.version, READY_CHECK_STATS_VERSION
+            );
+            return;
+        }
+        if stats.self_account_name != self.self_account_name {
+            info!(
+                "Ignoring ready check stats in {:?} which belong to {:?}",
+                pPath, stats.self_account_name
+            );
+            return;
+        }
+
+        info!(
+            "Loaded ready check stats for {} players from {:?}",
+            stats.members.len(),
+            pPath
+        );
+        self.restored_stats = stats.members;
+    }
+
+    // Whether the ready check stats changed since they were last saved. Saving is left to the caller, squad updates
+    // come in on the game thread where file I/O would stall the frame
+    pub fn has_unsaved_stats(&self) -> bool {
+        self.stats_dirty
+    }
+
+    pub fn save_ready_check_stats(&mut self) {
+        self.stats_dirty = false;
+
+        let path = match &self.stats_path {
+            Some(x) => x,
+            None => return,
+        };
+
+        // Statistics that were loaded but not yet claimed by a squad member are kept, the member might just not have
+        // been part of a squad update yet
+        let mut members = self.restored_stats.clone();
+        for (account_name, state) in self.squad_members.iter() {
+            members.insert(
+                account_name.clone(),
+                PersistedMemberStats {
+                    join_time: state.join_time,
+                    total_ready_check_time: state.total_ready_check_time,
+                    last_unready_duration: state.last_unready_duration,
+                },
+            );
+        }
+
+        let stats = PersistedReadyCheckStats {
+            version: READY_CHECK_STATS_VERSION,
+            self_account_name: self.self_account_name.clone(),
+            members,
+        };
+        if save_json(path, &stats) == true {
+            debug!("Saved ready check stats to {:?}", path);
         }
     }
 
-    pub fn squad_update(&mut self, pUsers: UserInfoIter) {
-        let now = Instant::now();
+    pub fn squad_update<'a>(&mut self, pUsers: impl ExactSizeIterator<Item = UserInfo<'a>>) {
+        let now = self.clock.now();
+        let now_local = self.clock.now_local();
 
         let SquadTracker {
             self_account_name,
             squad_members,
+            pending_users,
+            pending_user_history,
+            stats_path: _,
+            restored_stats,
+            stats_dirty,
+            squad_leader,
+            active_ready_check,
+            ready_check_history,
+            ready_check_limits,
+            ready_check_limit_events: _,
+            timeline: _,
+            attendance: _,
+            events,
+            clock: _,
         } = &mut *self;
 
         info!("Receiving {:?} updates", pUsers.len());
@@ -156,6 +641,16 @@ impl SquadTracker {
 
             match user_update.role {
                 UserRole::SquadLeader | UserRole::Lieutenant | UserRole::Member => {
+                    if let Some(pending_user) = pending_users.remove(account_name) {
+                        add_pending_user_record(
+                            pending_user_history,
+                            account_name,
+                            pending_user,
+                            PendingUserOutcome::Accepted,
+                            now_local,
+                        );
+                    }
+
                     // Either insert a new entry or update the existing one. Returns a reference to the user state if
                     // the ready check status updated (meaning further handling needs to be done to update fields)
                     let entry = squad_members.entry(account_name.to_string());
@@ -163,8 +658,35 @@ impl SquadTracker {
                         Entry::Occupied(entry) => {
                             let user = entry.into_mut();
                             let old_ready_status = user.is_ready;
+                            let old_role = user.role;
+                            let old_subgroup = user.subgroup;
                             user.update_user(&user_update);
 
+                            if old_role != user.role {
+                                info!(
+                                    "{} changed role from {:?} to {:?}",
+                                    account_name, old_role, user.role
+                                );
+                                events.push(
+                                    now_local,
+                                    SquadEventKind::RoleChanged {
+                                        account_name: account_name.to_string(),
+                                        old_role,
+                                        new_role: user.role,
+                                    },
+                                );
+                            }
+                            if old_subgroup != user.subgroup {
+                                events.push(
+                                    now_local,
+                                    SquadEventKind::SubgroupChanged {
+                                        account_name: account_name.to_string(),
+                                        old_subgroup,
+                                        new_subgroup: user.subgroup,
+                                    },
+                                );
+                            }
+
                             if old_ready_status != user.is_ready {
                                 Some(user)
                             } else {
@@ -173,6 +695,16 @@ impl SquadTracker {
                         }
                         Entry::Vacant(entry) => {
                             info!("Adding new player ({:?}) to the squad", user_update);
+                            events.push(
+                                now_local,
+                                SquadEventKind::MemberJoined {
+                                    account_name: account_name.to_string(),
+                                    role: user_update.role,
+                                    subgroup: user_update.subgroup,
+                                    join_time: user_update.join_time,
+                                },
+                            );
+
                             let user = entry.insert(SquadMemberState::new(
                                 user_update.join_time,
                                 user_update.role,
@@ -180,6 +712,40 @@ impl SquadTracker {
                                 user_update.ready_status,
                             ));
 
+                            if account_name == self_account_name {
+                                // A different join time for self means this is a new squad, so none of the loaded
+                                // statistics apply anymore
+                                let same_squad = restored_stats
+                                    .get(account_name)
+                                    .map_or(true, |x| x.join_time == user.join_time);
+                                if same_squad == false && restored_stats.is_empty() == false {
+                                    info!("Self joined a new squad - discarding loaded ready check stats");
+                                    restored_stats.clear();
+                                    *stats_dirty = true;
+                                }
+                            }
+
+                            if let Some(stats) = restored_stats.remove(account_name) {
+                                if stats.join_time == user.join_time {
+                                    info!(
+                                        "Restoring ready check stats {:?} for {}",
+                                        stats, account_name
+                                    );
+                                    user.total_ready_check_time = stats.total_ready_check_time;
+                                    user.last_unready_duration = stats.last_unready_duration;
+                                } else {
+                                    info!(
+                                        "Discarding ready check stats {:?} for {} since they rejoined at {}",
+                                        stats, account_name, user.join_time
+                                    );
+                                }
+                                *stats_dirty = true;
+                            }
+
+                            if let Some(ready_check) = active_ready_check {
+                                ready_check.add_joined(account_name, &now);
+                            }
+
                             if user_update.ready_status == true {
                                 Some(user)
                             } else {
@@ -188,41 +754,416 @@ impl SquadTracker {
                         }
                     };
 
-                    let ready_check_started_time = if let Some(new_user_state) = new_user_state {
-                        handle_ready_status_changed((account_name, new_user_state), &now)
+                    let ready_status_changed = if let Some(new_user_state) = new_user_state {
+                        handle_ready_status_changed((account_name, new_user_state), &now);
+                        events.push(
+                            now_local,
+                            SquadEventKind::ReadyStatusChanged {
+                                account_name: account_name.to_string(),
+                                is_ready: user_update.ready_status,
+                            },
+                        );
+                        true
                     } else {
-                        None
+                        false
+                    };
+
+                    let is_ready = user_update.ready_status;
+                    let is_squad_leader = user_update.role == UserRole::SquadLeader;
+                    let was_squad_leader = squad_leader.as_deref() == Some(account_name);
+                    if is_squad_leader != was_squad_leader {
+                        let new_leader = is_squad_leader.then(|| account_name.to_string());
+                        let old_leader = std::mem::replace(squad_leader, new_leader.clone());
+                        info!(
+                            "Squad leader changed from {:?} to {:?}",
+                            old_leader, new_leader
+                        );
+                        events.push(
+                            now_local,
+                            SquadEventKind::SquadLeaderChanged {
+                                old_leader,
+                                new_leader,
+                            },
+                        );
+                    }
+
+                    // A squad leader readying starts a ready check. If a ready check of someone else is still running
+                    // it is ended first, since there can only be one
+                    let leads_ready_check = |x: Option<&ActiveReadyCheck>| {
+                        x.map_or(false, |y| y.leader == account_name)
                     };
+                    if ready_status_changed == true {
+                        if is_ready == true
+                            && is_squad_leader == true
+                            && leads_ready_check(active_ready_check.as_ref()) == false
+                        {
+                            if let Some(ready_check) = active_ready_check.take() {
+                                *stats_dirty |= end_ready_check(
+                                    ready_check,
+                                    ReadyCheckEnd::LeaderChanged {
+                                        new_leader: Some(account_name),
+                                    },
+                                    squad_members,
+                                    ready_check_limits.hard_limit,
+                                    ready_check_history,
+                                    events,
+                                    &now,
+                                    now_local,
+                                );
+                            }
+
+                            info!("Ready check started at {:?} by {}", now, account_name);
+                            *active_ready_check = Some(ActiveReadyCheck::new(account_name, now));
+                            events.push(
+                                now_local,
+                                SquadEventKind::ReadyCheckStarted {
+                                    started_by: account_name.to_string(),
+                                },
+                            );
+                        }
+                        if let Some(ready_check) = active_ready_check {
+                            ready_check.add_event(account_name, &now, is_ready);
+                        }
+
+                        if is_ready == false
+                            && leads_ready_check(active_ready_check.as_ref()) == true
+                        {
+                            let ready_check = active_ready_check.take().unwrap();
+                            *stats_dirty |= end_ready_check(
+                                ready_check,
+                                ReadyCheckEnd::LeaderUnreadied,
+                                squad_members,
+                                ready_check_limits.hard_limit,
+                                ready_check_history,
+                                events,
+                                &now,
+                                now_local,
+                            );
+                        }
+                    }
 
-                    if let Some(start_time) = ready_check_started_time {
-                        handle_ready_check_finished(squad_members, &start_time, &now);
+                    // Becoming squad leader, unless readying in the same update already started a ready check. The
+                    // role update can arrive before or after the ready update of the new squad leader, so a running
+                    // ready check is carried over if they are already ready and ended if they aren't. Without a
+                    // running ready check, a squad leader who is already ready started one when they readied and the
+                    // role update was just late
+                    if is_squad_leader == true
+                        && was_squad_leader == false
+                        && leads_ready_check(active_ready_check.as_ref()) == false
+                    {
+                        match active_ready_check.take() {
+                            Some(mut ready_check) if is_ready == true => {
+                                info!(
+                                    "Ready check started by {} was carried over from {} to {}",
+                                    ready_check.started_by, ready_check.leader, account_name
+                                );
+                                ready_check.leader = account_name.to_string();
+                                *active_ready_check = Some(ready_check);
+                            }
+                            Some(ready_check) => {
+                                *stats_dirty |= end_ready_check(
+                                    ready_check,
+                                    ReadyCheckEnd::LeaderChanged {
+                                        new_leader: Some(account_name),
+                                    },
+                                    squad_members,
+                                    ready_check_limits.hard_limit,
+                                    ready_check_history,
+                                    events,
+                                    &now,
+                                    now_local,
+                                );
+                            }
+                            None if is_ready == true => {
+                                // A ready from longer ago than a ready check can last isn't the start of this one
+                                let start_time = squad_members[account_name]
+                                    .last_ready_time
+                                    .filter(|x| {
+                                        now.saturating_duration_since(*x)
+                                            < ready_check_limits.hard_limit
+                                    })
+                                    .unwrap_or(now);
+                                info!(
+                                    "Ready check started at {:?} by {}, who readied before becoming squad leader",
+                                    start_time, account_name
+                                );
+
+                                let mut ready_check =
+                                    ActiveReadyCheck::new(account_name, start_time);
+                                for (member_name, member) in squad_members.iter() {
+                                    let ready_time =
+                                        member.last_ready_time.filter(|x| *x >= start_time);
+                                    if let (true, Some(ready_time)) = (member.is_ready, ready_time)
+                                    {
+                                        ready_check.add_event(member_name, &ready_time, true);
+                                    }
+                                }
+                                *active_ready_check = Some(ready_check);
+                                events.push(
+                                    now_local,
+                                    SquadEventKind::ReadyCheckStarted {
+                                        started_by: account_name.to_string(),
+                                    },
+                                );
+                            }
+                            None => {}
+                        }
                     }
                 }
                 UserRole::None => {
                     if account_name == self_account_name {
                         info!("Self ({}) left - clearing squad", account_name);
+                        events.push(now_local, SquadEventKind::SelfLeftSquad);
                         squad_members.clear();
+                        pending_users.clear();
+                        restored_stats.clear();
+                        *squad_leader = None;
+                        *active_ready_check = None;
+                        *stats_dirty = true;
                     } else {
                         let result = squad_members.remove(account_name);
                         if result.is_some() {
                             info!("Removed {} from the squad", account_name);
+                            events.push(
+                                now_local,
+                                SquadEventKind::MemberLeft {
+                                    account_name: account_name.to_string(),
+                                },
+                            );
+                            *stats_dirty = true;
+
+                            if let Some(ready_check) = active_ready_check {
+                                ready_check.add_left(account_name);
+                            }
+                            if squad_leader.as_deref() == Some(account_name) {
+                                info!("Squad leader {} left", account_name);
+                                *squad_leader = None;
+                                events.push(
+                                    now_local,
+                                    SquadEventKind::SquadLeaderChanged {
+                                        old_leader: Some(account_name.to_string()),
+                                        new_leader: None,
+                                    },
+                                );
+                            }
+                            // Nobody is left to end the ready check of a leader who left
+                            if active_ready_check
+                                .as_ref()
+                                .map_or(false, |x| x.leader == account_name)
+                            {
+                                let ready_check = active_ready_check.take().unwrap();
+                                *stats_dirty |= end_ready_check(
+                                    ready_check,
+                                    ReadyCheckEnd::LeaderChanged { new_leader: None },
+                                    squad_members,
+                                    ready_check_limits.hard_limit,
+                                    ready_check_history,
+                                    events,
+                                    &now,
+                                    now_local,
+                                );
+                            }
+                        } else if let Some(pending_user) = pending_users.remove(account_name) {
+                            add_pending_user_record(
+                                pending_user_history,
+                                account_name,
+                                pending_user,
+                                PendingUserOutcome::Cancelled,
+                                now_local,
+                            );
                         } else {
                             info!("Couldn't find {}, who left, in the squad map, they were probably invited and the invite was cancelled", account_name);
                         }
                     }
                 }
-                UserRole::Invited | UserRole::Applied | UserRole::Invalid => {}
+                UserRole::Invited | UserRole::Applied => {
+                    if squad_members.contains_key(account_name) {
+                        warn!("Squad member is now pending ({:?})", user_update);
+                        continue;
+                    }
+
+                    let state = PendingUserState {
+                        join_time: user_update.join_time,
+                        role: user_update.role,
+                    };
+                    match pending_users.insert(account_name.to_string(), state) {
+                        None => info!("Adding pending user ({:?})", user_update),
+                        Some(old_state) => debug!(
+                            "Updated pending user ({:?}), old state {:?}",
+                            user_update, old_state
+                        ),
+                    }
+                }
+                UserRole::Invalid => {}
             };
         }
+
+        for event in self.events.take_recent() {
+            self.timeline.handle_event(&event, &self.self_account_name);
+            self.attendance.handle_event(&event);
+        }
     }
 
     pub fn get_squad_members(&self) -> &HashMap<String, SquadMemberState> {
         &self.squad_members
     }
 
+    // Looks up a squad member by an account name from another source (such as chat), which might differ in whether it
+    // has the leading colon
+    pub fn find_member(&self, pAccountName: &str) -> Option<&SquadMemberState> {
+        let account_name = pAccountName.trim_start_matches(':');
+        self.squad_members
+            .get(account_name)
+            .or_else(|| self.squad_members.get(&format!(":{}", account_name)))
+    }
+
+    // Role and subgroup of the sender of a chat message, None if they aren't in the squad
+    pub fn sender_info(&self, pAccountName: &str) -> Option<SenderInfo> {
+        self.find_member(pAccountName).map(|x| SenderInfo {
+            role: x.role,
+            subgroup: x.subgroup,
+        })
+    }
+
+    pub fn get_self_account_name(&self) -> &str {
+        &self.self_account_name
+    }
+
+    pub fn get_pending_users(&self) -> &HashMap<String, PendingUserState> {
+        &self.pending_users
+    }
+
+    pub fn get_attendance(&self) -> Option<&AttendanceSession> {
+        self.attendance.session()
+    }
+
+    // Enables writing an attendance report whenever self leaves the squad (ending the session)
+    pub fn set_attendance_reports_enabled(&mut self, pEnabled: bool) {
+        self.attendance.set_reports_enabled(pEnabled);
+    }
+
+    pub fn get_timeline_size(&self) -> usize {
+        self.timeline.get_max_entries()
+    }
+
+    pub fn set_timeline_size(&mut self, pSize: usize) {
+        self.timeline.set_max_entries(pSize);
+    }
+
+    pub fn get_timeline(&self) -> &SquadTimeline {
+        &self.timeline
+    }
+
+    // Invites and applications that were resolved, ordered from oldest to newest
+    pub fn get_pending_user_history(&self) -> &VecDeque<PendingUserRecord> {
+        &self.pending_user_history
+    }
+
+    pub fn get_ready_check_history(&self) -> &ReadyCheckHistory {
+        &self.ready_check_history
+    }
+
+    pub fn get_last_ready_check_outcome(&self) -> Option<&ReadyCheckOutcome> {
+        self.ready_check_history.last().map(|x| &x.outcome)
+    }
+
+    pub fn get_ready_check_limits(&self) -> ReadyCheckLimits {
+        self.ready_check_limits
+    }
+
+    pub fn set_ready_check_limits(&mut self, pLimits: ReadyCheckLimits) {
+        self.ready_check_limits = ReadyCheckLimits {
+            soft_limit: pLimits.soft_limit,
+            hard_limit: pLimits.hard_limit.max(pLimits.soft_limit),
+        };
+    }
+
+    // When the currently running ready check was started, None if no ready check is running. A ready check carried over
+    // to a new squad leader keeps its original start
+    pub fn get_ready_check_start_time(&self) -> Option<Instant> {
+        self.active_ready_check.as_ref().map(|x| x.start_time)
+    }
+
+    // Time since the currently running ready check was started, None if no ready check is running
+    pub fn get_ready_check_elapsed(&self) -> Option<Duration> {
+        self.active_ready_check
+            .as_ref()
+            .map(|x| self.clock.now().saturating_duration_since(x.start_time))
+    }
+
+    // Checks the running ready check against the configured limits. Needs to be called periodically (every frame)
+    // since nothing else happens in the squad while everyone is waiting for the last players to ready up
+    pub fn check_ready_check_limits(&mut self) {
+        let now = self.clock.now();
+
+        let ready_check = match &mut self.active_ready_check {
+            Some(x) => x,
+            None => return,
+        };
+
+        let elapsed = now.saturating_duration_since(ready_check.start_time);
+        let passed_limits = ready_check.due_limits(elapsed, &self.ready_check_limits);
+        if passed_limits.is_empty() {
+            return;
+        }
+        for limit in passed_limits.iter() {
+            match limit {
+                ReadyCheckLimit::Soft => ready_check.soft_limit_passed = true,
+                ReadyCheckLimit::Hard => ready_check.hard_limit_passed = true,
+            }
+        }
+
+        let mut not_ready = self
+            .squad_members
+            .iter()
+            .filter(|(_, state)| state.is_ready == false)
+            .map(|(account_name, _)| account_name.clone())
+            .collect::<Vec<_>>();
+        not_ready.sort();
+
+        for limit in passed_limits {
+            let event = ReadyCheckLimitEvent {
+                limit,
+                elapsed,
+                not_ready: not_ready.clone(),
+            };
+            info!("Ready check passed a time limit - {:?}", event);
+            self.ready_check_limit_events.push(event);
+        }
+    }
+
+    // Whether there are events to drain or a ready check limit is due. Only needs a shared borrow, so it can be checked
+    // every frame without blocking squad updates
+    pub fn needs_frame_update(&self) -> bool {
+        if self.events.is_empty() == false || self.ready_check_limit_events.is_empty() == false {
+            return true;
+        }
+
+        self.active_ready_check.as_ref().map_or(false, |x| {
+            let elapsed = self.clock.now().saturating_duration_since(x.start_time);
+            x.due_limits(elapsed, &self.ready_check_limits).is_empty() == false
+        })
+    }
+
+    pub fn drain_ready_check_limit_events(&mut self) -> Vec<ReadyCheckLimitEvent> {
+        std::mem::take(&mut self.ready_check_limit_events)
+    }
+
+    // Squad events since the last call, ordered from oldest to newest
+    pub fn drain_events(&mut self) -> Vec<SquadEvent> {
+        self.events.drain()
+    }
+
+    // Registers pCallback to be called with every squad event as it happens, in addition to it being queued for
+    // drain_events. The callback is called while the tracker is being updated, so it must not access the tracker
+    #[allow(dead_code)]
+    pub fn subscribe(&mut self, pCallback: SquadEventCallback) {
+        self.events.subscribe(pCallback);
+    }
+
     #[allow(dead_code)]
     pub fn setup_mock_data_active_ready_check(&mut self) {
-        let now = Instant::now();
+        let now = self.clock.now();
 
         assert_eq!(self.squad_members, HashMap::new());
         self.squad_members.insert(
@@ -239,6 +1180,8 @@ impl SquadTracker {
         let bob = self.squad_members.get_mut("Bob").unwrap();
         bob.last_ready_time = Some(now - Duration::new(10, 0));
         bob.total_ready_check_time = Duration::new(200, 0);
+        self.squad_leader = Some("Bob".to_string());
+        self.active_ready_check = Some(ActiveReadyCheck::new("Bob", now - Duration::new(10, 0)));
 
         self.squad_members.insert(
             "Charlie".to_string(),
@@ -251,7 +1194,7 @@ impl SquadTracker {
 
     #[allow(dead_code)]
     pub fn setup_mock_data_inactive_ready_check(&mut self) {
-        let now = Instant::now();
+        let now = self.clock.now();
 
         assert_eq!(self.squad_members, HashMap::new());
         self.squad_members.insert(
@@ -270,6 +1213,7 @@ impl SquadTracker {
         bob.last_ready_time = Some(now - Duration::new(10, 0));
         bob.total_ready_check_time = Duration::new(200, 0);
         bob.last_unready_duration = Some(Duration::new(0, 0));
+        self.squad_leader = Some("Bob".to_string());
 
         self.squad_members.insert(
             "Charlie".to_string(),
@@ -284,13 +1228,24 @@ impl SquadTracker {
 
 #[cfg(test)]
 mod tests {
-    use super::SquadTracker;
+    use super::{
+        PendingUserOutcome, ReadyCheckLimit, ReadyCheckLimitEvent, ReadyCheckLimits,
+        SquadMemberState, SquadTracker,
+    };
+    use crate::clock::{Clock, ManualClock};
     use crate::infra::install_log_handler;
+    use crate::ready_check_history::{
+        ReadyCheckAbortReason, ReadyCheckOutcome, ReadyStatusChange, ReadyStatusEvent,
+    };
+    use crate::squad_events::{SquadEvent, SquadEventKind};
+    use crate::squad_timeline::TimelineEventKind;
     use arcdps::{RawUserInfo, UserInfoIter, UserRole};
-    use more_asserts::*;
+    use chrono::{Local, TimeZone};
     use rstest::rstest;
+    use std::collections::HashMap;
     use std::mem::MaybeUninit;
-    use std::time::{Duration, Instant};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     struct TestUser {
         account_name: String,
@@ -356,6 +1311,14 @@ mod tests {
         }
     }
 
+    fn new_tracker() -> (SquadTracker, ManualClock) {
+        let clock = ManualClock::new();
+        (
+            SquadTracker::with_clock("self", Arc::new(clock.clone())),
+            clock,
+        )
+    }
+
     fn ready_player(pPlayerName: &str, pTracker: &mut SquadTracker, pTestUsers: &mut TestUserList) {
         pTestUsers.users.clear();
         pTestUsers.users.push(TestUser::new(
@@ -366,22 +1329,13 @@ mod tests {
             true,
         ));
         let mut expected_state = pTracker.squad_members.clone();
-        let pre_op_time = Instant::now();
         unsafe {
             pTracker.squad_update(pTestUsers.get_iter());
         }
-        let post_op_time = Instant::now();
 
         if expected_state[pPlayerName].is_ready == false {
-            assert_in_range!(
-                pTracker.squad_members[pPlayerName].last_ready_time,
-                Some(pre_op_time),
-                Some(post_op_time)
-            );
-
             expected_state.get_mut(pPlayerName).unwrap().is_ready = true;
-            expected_state.get_mut(pPlayerName).unwrap().last_ready_time =
-                pTracker.squad_members[pPlayerName].last_ready_time;
+            expected_state.get_mut(pPlayerName).unwrap().last_ready_time = Some(pTracker.now());
         }
 
         assert_eq!(pTracker.squad_members, expected_state);
@@ -401,47 +1355,100 @@ mod tests {
             false,
         ));
         let mut expected_state = pTracker.squad_members.clone();
-        let pre_op_time = Instant::now();
         unsafe {
             pTracker.squad_update(pTestUsers.get_iter());
         }
-        let post_op_time = Instant::now();
 
         if expected_state[pPlayerName].is_ready == true {
-            assert_in_range!(
-                pTracker.squad_members[pPlayerName].last_unready_time,
-                Some(pre_op_time),
-                Some(post_op_time)
-            );
-
             expected_state.get_mut(pPlayerName).unwrap().is_ready = false;
             expected_state
                 .get_mut(pPlayerName)
                 .unwrap()
-                .last_unready_time = pTracker.squad_members[pPlayerName].last_unready_time;
+                .last_unready_time = Some(pTracker.now());
         }
 
         assert_eq!(pTracker.squad_members, expected_state);
     }
 
-    // Test that when self leaves squad, all squad members are dereregistered
-    #[test]
-    fn deregister_self() {
-        install_log_handler().unwrap();
+    // Unreadies all players at the same instant, the way the game does it when the squad leader ends the ready check.
+    // Returns the state from before with only the unreadies applied, the statistics are up to the caller
+    fn finish_ready_check(
+        pTracker: &mut SquadTracker,
+        pTestUsers: &mut TestUserList,
+    ) -> HashMap<String, SquadMemberState> {
+        let mut expected_state = pTracker.squad_members.clone();
+        for user in ["self", "squad_leader", "peer"] {
+            pTestUsers.users.clear();
+            let role = if user == "squad_leader" {
+                UserRole::SquadLeader
+            } else {
+                UserRole::Member
+            };
+            pTestUsers
+                .users
+                .push(TestUser::new(user.to_string(), 12345, role, 0, false));
+            unsafe {
+                pTracker.squad_update(pTestUsers.get_iter());
+            }
 
-        let mut tracker = SquadTracker::new("self");
+            if expected_state[user].is_ready == true {
+                assert_eq!(
+                    pTracker.squad_members[user].last_unready_time,
+                    Some(pTracker.now())
+                );
+                expected_state.get_mut(user).unwrap().last_unready_time = Some(pTracker.now());
+                expected_state.get_mut(user).unwrap().is_ready = false;
+            }
+        }
+
+        expected_state
+    }
+
+    fn three_member_squad(pLeaderReady: bool, pPeerReady: bool) -> TestUserList {
         let mut test_users = TestUserList::new();
         test_users.users.push(TestUser::new(
-            "self".to_string(),
+            "squad_leader".to_string(),
             12345,
-            UserRole::Member,
+            UserRole::SquadLeader,
             0,
-            false,
+            pLeaderReady,
         ));
         test_users.users.push(TestUser::new(
-            "squad_leader".to_string(),
+            "self".to_string(),
             12345,
-            UserRole::SquadLeader,
+            UserRole::Member,
+            0,
+            false,
+        ));
+        test_users.users.push(TestUser::new(
+            "peer".to_string(),
+            12345,
+            UserRole::Member,
+            0,
+            pPeerReady,
+        ));
+
+        test_users
+    }
+
+    // Test that when self leaves squad, all squad members are dereregistered
+    #[test]
+    fn deregister_self() {
+        install_log_handler().unwrap();
+
+        let mut tracker = SquadTracker::new("self");
+        let mut test_users = TestUserList::new();
+        test_users.users.push(TestUser::new(
+            "self".to_string(),
+            12345,
+            UserRole::Member,
+            0,
+            false,
+        ));
+        test_users.users.push(TestUser::new(
+            "squad_leader".to_string(),
+            12345,
+            UserRole::SquadLeader,
             0,
             false,
         ));
@@ -476,36 +1483,11 @@ mod tests {
     ) {
         install_log_handler().unwrap();
 
-        let mut tracker = SquadTracker::new("self");
-        let mut test_users = TestUserList::new();
-
-        // Squad setup
-        test_users.users.push(TestUser::new(
-            "squad_leader".to_string(),
-            12345,
-            UserRole::SquadLeader,
-            0,
-            false,
-        ));
-        test_users.users.push(TestUser::new(
-            "self".to_string(),
-            12345,
-            UserRole::Member,
-            0,
-            false,
-        ));
-        test_users.users.push(TestUser::new(
-            "peer".to_string(),
-            12345,
-            UserRole::Member,
-            0,
-            false,
-        ));
-        let pre_op_time = Instant::now();
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
         unsafe {
             tracker.squad_update(test_users.get_iter());
         }
-        let post_op_time = Instant::now();
         assert_eq!(tracker.squad_members.len(), 3);
 
         let initial_ready_check_time_spent = Duration::new(5, 0);
@@ -517,212 +1499,1211 @@ mod tests {
                 .total_ready_check_time = initial_ready_check_time_spent;
 
             assert_eq!(tracker.squad_members[user].is_ready, false);
-            assert_le!(
-                tracker.squad_members[user].last_ready_time,
-                Some(pre_op_time)
-            );
-            assert_le!(
-                tracker.squad_members[user].last_unready_time,
-                Some(post_op_time)
-            );
+            assert_eq!(tracker.squad_members[user].last_ready_time, None);
+            assert_eq!(tracker.squad_members[user].last_unready_time, None);
         }
 
+        clock.advance(Duration::from_secs(1));
         ready_player("squad_leader", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(2));
         ready_player("peer", &mut tracker, &mut test_users);
         if pReadyAndUnready == true {
+            clock.advance(Duration::from_secs(1));
             unready_player("peer", &mut tracker, &mut test_users);
+            clock.advance(Duration::from_secs(1));
             ready_player("peer", &mut tracker, &mut test_users);
         }
+        let peer_reaction_time = if pReadyAndUnready {
+            Duration::from_secs(4)
+        } else {
+            Duration::from_secs(2)
+        };
 
+        clock.advance(Duration::from_secs(3));
         if pAborted == false {
             ready_player("self", &mut tracker, &mut test_users);
         }
+        let self_reaction_time = peer_reaction_time + Duration::from_secs(3);
 
-        // Ready check finished. Players unready in "random" order. If aborted, the state should not have changed from
-        // before the ready check was started
-        let mut expected_state = tracker.squad_members.clone();
-        for user in ["self", "squad_leader", "peer"] {
-            test_users.users.clear();
-            let role = if user == "squad_leader" {
-                UserRole::SquadLeader
-            } else {
-                UserRole::Member
-            };
-            test_users
-                .users
-                .push(TestUser::new(user.to_string(), 12345, role, 0, false));
-            let pre_op_time = Instant::now();
-            unsafe {
-                tracker.squad_update(test_users.get_iter());
-            }
-            let post_op_time = Instant::now();
+        // Ready check finished. If aborted, the statistics should not have changed from before the ready check was
+        // started
+        clock.advance(Duration::from_secs(1));
+        let mut expected_state = finish_ready_check(&mut tracker, &mut test_users);
+        let ready_check_duration = self_reaction_time + Duration::from_secs(1);
 
-            if expected_state[user].is_ready == true {
-                assert_in_range!(
-                    tracker.squad_members[user].last_unready_time,
-                    Some(pre_op_time),
-                    Some(post_op_time)
-                );
-                expected_state.get_mut(user).unwrap().last_unready_time =
-                    tracker.squad_members[user].last_unready_time;
-                expected_state.get_mut(user).unwrap().is_ready = false;
+        if pAborted == false {
+            for (user, reaction_time) in [
+                ("self", self_reaction_time),
+                ("peer", peer_reaction_time),
+                ("squad_leader", Duration::ZERO),
+            ] {
+                let expected_user = expected_state.get_mut(user).unwrap();
+                expected_user.total_ready_check_time =
+                    initial_ready_check_time_spent + reaction_time;
+                expected_user.last_unready_duration = Some(reaction_time);
             }
         }
+        assert_eq!(tracker.squad_members, expected_state);
 
-        for user in ["self", "peer"] {
-            let actual_user = tracker.squad_members.get(user).unwrap();
-            let expected_user = expected_state.get_mut(user).unwrap();
+        assert_eq!(tracker.ready_check_history.records().len(), 1);
+        let record = tracker.ready_check_history.last().unwrap();
+        let expected_outcome = if pAborted {
+            ReadyCheckOutcome::Aborted(ReadyCheckAbortReason::LeaderCancelled {
+                leader: "squad_leader".to_string(),
+                not_ready: vec!["self".to_string()],
+            })
+        } else {
+            ReadyCheckOutcome::Succeeded
+        };
+        assert_eq!(record.outcome, expected_outcome);
+        assert_eq!(record.started_by, "squad_leader");
+        assert_eq!(record.duration, ready_check_duration);
+        assert_eq!(record.members.len(), 3);
+        for member in record.members.iter() {
+            let expected_reaction_time = match member.account_name.as_str() {
+                "self" if pAborted == true => None,
+                "self" => Some(self_reaction_time),
+                "peer" => Some(peer_reaction_time),
+                _ => Some(Duration::ZERO),
+            };
+            assert_eq!(
+                member.reaction_time, expected_reaction_time,
+                "{}",
+                member.account_name
+            );
+        }
 
-            if pAborted == false {
-                assert_gt!(
-                    actual_user.total_ready_check_time,
-                    initial_ready_check_time_spent
-                );
-                assert_eq!(
-                    actual_user.last_unready_duration,
-                    Some(actual_user.total_ready_check_time - initial_ready_check_time_spent)
-                );
+        // Peer's final unready happens after the squad leader ended the ready check, so it is not part of the record
+        let peer_record = record
+            .members
+            .iter()
+            .find(|x| x.account_name == "peer")
+            .unwrap();
+        let event = |pSeconds: u64, pChange: ReadyStatusChange| ReadyStatusEvent {
+            offset: Duration::from_secs(pSeconds),
+            change: pChange,
+        };
+        let expected_peer_events = if pReadyAndUnready {
+            vec![
+                event(2, ReadyStatusChange::Readied),
+                event(3, ReadyStatusChange::Unreadied),
+                event(4, ReadyStatusChange::Readied),
+            ]
+        } else {
+            vec![event(2, ReadyStatusChange::Readied)]
+        };
+        assert_eq!(peer_record.events, expected_peer_events);
+    }
 
-                expected_user.total_ready_check_time = actual_user.total_ready_check_time;
-                expected_user.last_unready_duration = actual_user.last_unready_duration;
-            }
+    #[rstest]
+    fn ready_check_during_restart(
+        #[values(false, true)] pAborted: bool,
+        #[values(false, true)] pReadyAndUnready: bool,
+    ) {
+        install_log_handler().unwrap();
+
+        // Squad setup - two players are already ready of which one is the squad leader
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(true, true);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
         }
-        if pAborted == false {
-            expected_state
-                .get_mut("squad_leader")
+
+        assert_eq!(tracker.squad_members.len(), 3);
+        let initial_ready_check_time_spent = Duration::new(5, 0);
+        for user in ["self", "peer", "squad_leader"] {
+            tracker
+                .squad_members
+                .get_mut(user)
                 .unwrap()
-                .last_unready_duration = Some(Duration::new(0, 0));
+                .total_ready_check_time = initial_ready_check_time_spent;
         }
 
+        // Peer readied at the first possible moment, so they only spent time in the ready check if they did a
+        // ready-unready cycle
+        let mut peer_reaction_time = Duration::ZERO;
+        if pReadyAndUnready == true {
+            clock.advance(Duration::from_secs(1));
+            unready_player("peer", &mut tracker, &mut test_users);
+            clock.advance(Duration::from_secs(1));
+            ready_player("peer", &mut tracker, &mut test_users);
+            peer_reaction_time = Duration::from_secs(2);
+        }
+
+        clock.advance(Duration::from_secs(2));
+        if pAborted == false {
+            ready_player("self", &mut tracker, &mut test_users);
+        }
+        let self_reaction_time = peer_reaction_time + Duration::from_secs(2);
+
+        clock.advance(Duration::from_secs(1));
+        let mut expected_state = finish_ready_check(&mut tracker, &mut test_users);
+
+        // If aborted, the statistics should not have changed from before the ready check was started
+        if pAborted == false {
+            for (user, reaction_time) in [
+                ("self", self_reaction_time),
+                ("peer", peer_reaction_time),
+                ("squad_leader", Duration::ZERO),
+            ] {
+                let expected_user = expected_state.get_mut(user).unwrap();
+                expected_user.total_ready_check_time =
+                    initial_ready_check_time_spent + reaction_time;
+                expected_user.last_unready_duration = Some(reaction_time);
+            }
+        }
         assert_eq!(tracker.squad_members, expected_state);
     }
 
+    // Test that a member who unreadied shortly before the squad leader ended the ready check is counted as part of the
+    // squad leader ending it, while an earlier unready aborts the ready check
     #[rstest]
-    fn ready_check_during_restart(
-        #[values(false, true)] pAborted: bool,
-        #[values(false, true)] pReadyAndUnready: bool,
+    fn unready_before_finish(
+        #[values(Duration::ZERO, Duration::from_millis(499), Duration::from_millis(501))]
+        pUnreadyLead: Duration,
     ) {
         install_log_handler().unwrap();
 
-        let mut tracker = SquadTracker::new("self");
-        let mut test_users = TestUserList::new();
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
 
-        // Squad setup - two players are already ready of which one is the squad leader
+        ready_player("squad_leader", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(1));
+        ready_player("peer", &mut tracker, &mut test_users);
+        ready_player("self", &mut tracker, &mut test_users);
+
+        clock.advance(Duration::from_secs(1));
+        unready_player("peer", &mut tracker, &mut test_users);
+        clock.advance(pUnreadyLead);
+        unready_player("self", &mut tracker, &mut test_users);
+        test_users.users.clear();
         test_users.users.push(TestUser::new(
             "squad_leader".to_string(),
             12345,
             UserRole::SquadLeader,
             0,
-            true,
+            false,
         ));
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+
+        let expected_outcome = if pUnreadyLead > Duration::from_millis(500) {
+            ReadyCheckOutcome::Aborted(ReadyCheckAbortReason::MemberUnreadied {
+                accounts: vec!["peer".to_string()],
+            })
+        } else {
+            ReadyCheckOutcome::Succeeded
+        };
+        assert_eq!(
+            tracker.get_last_ready_check_outcome(),
+            Some(&expected_outcome)
+        );
+    }
+
+    // Test the reason a failed ready check is recorded with. Members that only appear in the update which started the
+    // ready check were already in the squad, so they don't count as a roster change
+    #[rstest]
+    fn ready_check_abort_reason(
+        #[values(
+            "cancelled",
+            "unreadied",
+            "joined",
+            "joined_in_starting_update",
+            "left",
+            "unreadied_and_joined",
+            "timed_out"
+        )]
+        pCase: &str,
+    ) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        tracker.set_ready_check_limits(ReadyCheckLimits {
+            soft_limit: Duration::from_secs(10),
+            hard_limit: Duration::from_secs(30),
+        });
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+
+        clock.advance(Duration::from_secs(1));
+        if pCase == "joined_in_starting_update" {
+            send_update(
+                &[
+                    ("squad_leader", UserRole::SquadLeader, true),
+                    ("newcomer", UserRole::Member, false),
+                ],
+                &mut tracker,
+                &mut test_users,
+            );
+        } else {
+            ready_player("squad_leader", &mut tracker, &mut test_users);
+        }
+
+        clock.advance(Duration::from_secs(1));
+        if pCase == "unreadied" || pCase == "unreadied_and_joined" {
+            ready_player("peer", &mut tracker, &mut test_users);
+            clock.advance(Duration::from_millis(500));
+            unready_player("peer", &mut tracker, &mut test_users);
+        }
+        if pCase == "joined" || pCase == "unreadied_and_joined" {
+            send_update(
+                &[("newcomer", UserRole::Member, false)],
+                &mut tracker,
+                &mut test_users,
+            );
+        }
+        if pCase == "left" {
+            send_update(
+                &[("peer", UserRole::None, false)],
+                &mut tracker,
+                &mut test_users,
+            );
+        }
+
+        if pCase == "timed_out" {
+            clock.advance(Duration::from_secs(30));
+        } else {
+            clock.advance(Duration::from_secs(2));
+        }
+        send_update(
+            &[("squad_leader", UserRole::SquadLeader, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let names = |pAccountNames: &[&str]| {
+            pAccountNames
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+        };
+        let expected_reason = match pCase {
+            "cancelled" => ReadyCheckAbortReason::LeaderCancelled {
+                leader: "squad_leader".to_string(),
+                not_ready: names(&["peer", "self"]),
+            },
+            "unreadied" | "unreadied_and_joined" => ReadyCheckAbortReason::MemberUnreadied {
+                accounts: names(&["peer"]),
+            },
+            "joined" => ReadyCheckAbortReason::RosterChanged {
+                joined: names(&["newcomer"]),
+                left: Vec::new(),
+            },
+            "joined_in_starting_update" => ReadyCheckAbortReason::LeaderCancelled {
+                leader: "squad_leader".to_string(),
+                not_ready: names(&["newcomer", "peer", "self"]),
+            },
+            "left" => ReadyCheckAbortReason::RosterChanged {
+                joined: Vec::new(),
+                left: names(&["peer"]),
+            },
+            "timed_out" => ReadyCheckAbortReason::TimedOut {
+                not_ready: names(&["peer", "self"]),
+            },
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            tracker.get_last_ready_check_outcome(),
+            Some(&ReadyCheckOutcome::Aborted(expected_reason))
+        );
+    }
+
+    // Test that each limit raises exactly one event at the moment it passes, and that a ready check running past the
+    // hard limit counts as timed out
+    #[test]
+    fn ready_check_limits() {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        tracker.set_ready_check_limits(ReadyCheckLimits {
+            soft_limit: Duration::from_secs(10),
+            hard_limit: Duration::from_secs(30),
+        });
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+
+        ready_player("squad_leader", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(5));
+        ready_player("peer", &mut tracker, &mut test_users);
+        assert_eq!(
+            tracker.get_ready_check_elapsed(),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(tracker.needs_frame_update(), true);
+        tracker.drain_events();
+
+        clock.advance(Duration::from_millis(4999));
+        assert_eq!(tracker.needs_frame_update(), false);
+        tracker.check_ready_check_limits();
+        assert!(tracker.drain_ready_check_limit_events().is_empty());
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(tracker.needs_frame_update(), true);
+        tracker.check_ready_check_limits();
+        tracker.check_ready_check_limits();
+        assert_eq!(
+            tracker.drain_ready_check_limit_events(),
+            vec![ReadyCheckLimitEvent {
+                limit: ReadyCheckLimit::Soft,
+                elapsed: Duration::from_secs(10),
+                not_ready: vec!["self".to_string()],
+            }]
+        );
+        assert_eq!(tracker.needs_frame_update(), false);
+
+        clock.advance(Duration::from_secs(25));
+        tracker.check_ready_check_limits();
+        assert_eq!(
+            tracker.drain_ready_check_limit_events(),
+            vec![ReadyCheckLimitEvent {
+                limit: ReadyCheckLimit::Hard,
+                elapsed: Duration::from_secs(35),
+                not_ready: vec!["self".to_string()],
+            }]
+        );
+
+        finish_ready_check(&mut tracker, &mut test_users);
+        assert_eq!(tracker.get_ready_check_elapsed(), None);
+        assert_eq!(
+            tracker.get_last_ready_check_outcome(),
+            Some(&ReadyCheckOutcome::Aborted(
+                ReadyCheckAbortReason::TimedOut {
+                    not_ready: vec!["self".to_string()],
+                }
+            ))
+        );
+
+        // The next ready check raises its own events
+        clock.advance(Duration::from_secs(1));
+        ready_player("squad_leader", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(10));
+        tracker.check_ready_check_limits();
+        assert_eq!(
+            tracker.drain_ready_check_limit_events(),
+            vec![ReadyCheckLimitEvent {
+                limit: ReadyCheckLimit::Soft,
+                elapsed: Duration::from_secs(10),
+                not_ready: vec!["peer".to_string(), "self".to_string()],
+            }]
+        );
+    }
+
+    // Test that every change to the squad produces exactly one event, delivered both to callbacks and through draining
+    #[test]
+    fn squad_events() {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let callback_received = received.clone();
+        tracker.subscribe(Box::new(move |pEvent: &SquadEvent| {
+            callback_received.lock().unwrap().push(pEvent.kind.clone());
+        }));
+
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+        let joined = |pName: &str, pRole: UserRole| SquadEventKind::MemberJoined {
+            account_name: pName.to_string(),
+            role: pRole,
+            subgroup: 0,
+            join_time: 12345,
+        };
+        let ready = |pName: &str, pIsReady: bool| SquadEventKind::ReadyStatusChanged {
+            account_name: pName.to_string(),
+            is_ready: pIsReady,
+        };
+        let mut expected = vec![
+            joined("squad_leader", UserRole::SquadLeader),
+            SquadEventKind::SquadLeaderChanged {
+                old_leader: None,
+                new_leader: Some("squad_leader".to_string()),
+            },
+            joined("self", UserRole::Member),
+            joined("peer", UserRole::Member),
+        ];
+
+        ready_player("squad_leader", &mut tracker, &mut test_users);
+        expected.push(ready("squad_leader", true));
+        expected.push(SquadEventKind::ReadyCheckStarted {
+            started_by: "squad_leader".to_string(),
+        });
+        ready_player("peer", &mut tracker, &mut test_users);
+        expected.push(ready("peer", true));
+        ready_player("self", &mut tracker, &mut test_users);
+        expected.push(ready("self", true));
+        clock.advance(Duration::from_secs(3));
+        finish_ready_check(&mut tracker, &mut test_users);
+        expected.push(ready("self", false));
+        expected.push(ready("squad_leader", false));
+        expected.push(SquadEventKind::ReadyCheckCompleted {
+            id: tracker.get_ready_check_history().last().unwrap().id,
+            duration: Duration::from_secs(3),
+        });
+        expected.push(ready("peer", false));
+
+        test_users.users.clear();
         test_users.users.push(TestUser::new(
-            "self".to_string(),
+            "peer".to_string(),
             12345,
-            UserRole::Member,
-            0,
+            UserRole::Lieutenant,
+            2,
             false,
         ));
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+        expected.push(SquadEventKind::RoleChanged {
+            account_name: "peer".to_string(),
+            old_role: UserRole::Member,
+            new_role: UserRole::Lieutenant,
+        });
+        expected.push(SquadEventKind::SubgroupChanged {
+            account_name: "peer".to_string(),
+            old_subgroup: 0,
+            new_subgroup: 2,
+        });
+
+        update_user("peer", 12345, UserRole::None, &mut tracker, &mut test_users);
+        expected.push(SquadEventKind::MemberLeft {
+            account_name: "peer".to_string(),
+        });
+        update_user("self", 12345, UserRole::None, &mut tracker, &mut test_users);
+        expected.push(SquadEventKind::SelfLeftSquad);
+
+        let drained = tracker.drain_events();
+        assert_eq!(
+            drained.iter().map(|x| x.kind.clone()).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(*received.lock().unwrap(), expected);
+        assert!(tracker.drain_events().is_empty());
+
+        // Event times come from the clock of the tracker
+        assert_eq!(drained.last().unwrap().time, clock.now_local());
+    }
+
+    // Test that every roster change ends up on the timeline, and that the timeline outlives the squad until a new one is
+    // joined
+    #[test]
+    fn timeline() {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+        let joined_at = clock.now_local();
+
+        clock.advance(Duration::from_secs(1));
+        test_users.users.clear();
         test_users.users.push(TestUser::new(
             "peer".to_string(),
             12345,
-            UserRole::Member,
-            0,
-            true,
+            UserRole::Lieutenant,
+            2,
+            false,
         ));
         unsafe {
             tracker.squad_update(test_users.get_iter());
         }
+        let changed_at = clock.now_local();
 
-        assert_eq!(tracker.squad_members.len(), 3);
-        let initial_ready_check_time_spent = Duration::new(5, 0);
-        for user in ["self", "peer", "squad_leader"] {
-            tracker
-                .squad_members
-                .get_mut(user)
-                .unwrap()
-                .total_ready_check_time = initial_ready_check_time_spent;
+        clock.advance(Duration::from_secs(1));
+        send_update(
+            &[("peer", UserRole::None, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+        let left_at = clock.now_local();
+        clock.advance(Duration::from_secs(1));
+        send_update(
+            &[("self", UserRole::None, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+        let self_left_at = clock.now_local();
+
+        let entries = tracker
+            .get_timeline()
+            .entries()
+            .iter()
+            .map(|x| (x.time, x.account_name.as_str(), x.kind.clone()))
+            .collect::<Vec<_>>();
+        let joined = |pRole: UserRole| TimelineEventKind::Joined {
+            role: pRole,
+            subgroup: 0,
+        };
+        assert_eq!(
+            entries,
+            vec![
+                (joined_at, "squad_leader", joined(UserRole::SquadLeader)),
+                (joined_at, "self", joined(UserRole::Member)),
+                (joined_at, "peer", joined(UserRole::Member)),
+                (
+                    changed_at,
+                    "peer",
+                    TimelineEventKind::RoleChanged {
+                        old_role: UserRole::Member,
+                        new_role: UserRole::Lieutenant,
+                    }
+                ),
+                (
+                    changed_at,
+                    "peer",
+                    TimelineEventKind::SubgroupChanged {
+                        old_subgroup: 0,
+                        new_subgroup: 2,
+                    }
+                ),
+                (left_at, "peer", TimelineEventKind::Left),
+                (self_left_at, "self", TimelineEventKind::SelfLeft),
+            ]
+        );
+
+        // Joining the next squad starts a new timeline
+        clock.advance(Duration::from_secs(1));
+        send_update(
+            &[
+                ("self", UserRole::Member, false),
+                ("other_leader", UserRole::SquadLeader, false),
+            ],
+            &mut tracker,
+            &mut test_users,
+        );
+        let names = tracker
+            .get_timeline()
+            .entries()
+            .iter()
+            .map(|x| x.account_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["self", "other_leader"]);
+    }
+
+    // Sends a single squad update containing every entry of pUsers, given as (account name, role, ready status)
+    fn send_update(
+        pUsers: &[(&str, UserRole, bool)],
+        pTracker: &mut SquadTracker,
+        pTestUsers: &mut TestUserList,
+    ) {
+        pTestUsers.users.clear();
+        for (account_name, role, is_ready) in pUsers.iter() {
+            pTestUsers.users.push(TestUser::new(
+                account_name.to_string(),
+                12345,
+                *role,
+                0,
+                *is_ready,
+            ));
+        }
+        unsafe {
+            pTracker.squad_update(pTestUsers.get_iter());
         }
+    }
 
-        if pReadyAndUnready == true {
-            unready_player("peer", &mut tracker, &mut test_users);
-            ready_player("peer", &mut tracker, &mut test_users);
+    // Drains the events of pTracker and returns the squad leader changes and ready check starts and ends among them
+    fn drain_leader_events(pTracker: &mut SquadTracker) -> Vec<SquadEventKind> {
+        pTracker
+            .drain_events()
+            .into_iter()
+            .map(|x| x.kind)
+            .filter(|x| {
+                matches!(
+                    x,
+                    SquadEventKind::SquadLeaderChanged { .. }
+                        | SquadEventKind::ReadyCheckStarted { .. }
+                        | SquadEventKind::ReadyCheckCompleted { .. }
+                        | SquadEventKind::ReadyCheckAborted { .. }
+                )
+            })
+            .collect()
+    }
+
+    fn leader_changed(pOldLeader: Option<&str>, pNewLeader: Option<&str>) -> SquadEventKind {
+        SquadEventKind::SquadLeaderChanged {
+            old_leader: pOldLeader.map(|x| x.to_string()),
+            new_leader: pNewLeader.map(|x| x.to_string()),
         }
+    }
 
-        if pAborted == false {
+    // A ready check started by squad_leader 3 seconds ago, which squad_leader and peer (after 2 seconds) are ready for
+    fn running_ready_check() -> (SquadTracker, ManualClock, TestUserList) {
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+
+        clock.advance(Duration::from_secs(1));
+        ready_player("squad_leader", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(2));
+        ready_player("peer", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(1));
+        tracker.drain_events();
+
+        (tracker, clock, test_users)
+    }
+
+    // Test that a ready check is carried over to a new squad leader who is already ready, in whichever order the role
+    // updates of the old and new squad leader arrive, and that it then ends when the new squad leader unreadies
+    #[rstest]
+    fn squad_leader_handover_ready(
+        #[values(
+            "demote_first",
+            "promote_first",
+            "same_update_demote_first",
+            "same_update_promote_first"
+        )]
+        pOrdering: &str,
+    ) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock, mut test_users) = running_ready_check();
+        let demote = ("squad_leader", UserRole::Member, true);
+        let promote = ("peer", UserRole::SquadLeader, true);
+        match pOrdering {
+            "demote_first" => {
+                send_update(&[demote], &mut tracker, &mut test_users);
+                send_update(&[promote], &mut tracker, &mut test_users);
+            }
+            "promote_first" => {
+                send_update(&[promote], &mut tracker, &mut test_users);
+                send_update(&[demote], &mut tracker, &mut test_users);
+            }
+            "same_update_demote_first" => {
+                send_update(&[demote, promote], &mut tracker, &mut test_users)
+            }
+            "same_update_promote_first" => {
+                send_update(&[promote, demote], &mut tracker, &mut test_users)
+            }
+            _ => unreachable!(),
+        }
+
+        let expected = if pOrdering.ends_with("demote_first") {
+            vec![
+                leader_changed(Some("squad_leader"), None),
+                leader_changed(None, Some("peer")),
+            ]
+        } else {
+            vec![leader_changed(Some("squad_leader"), Some("peer"))]
+        };
+        assert_eq!(drain_leader_events(&mut tracker), expected);
+        assert_eq!(tracker.squad_leader.as_deref(), Some("peer"));
+        assert_eq!(tracker.active_ready_check.as_ref().unwrap().leader, "peer");
+        assert_eq!(
+            tracker.get_ready_check_elapsed(),
+            Some(Duration::from_secs(3))
+        );
+        // The start is the ready of the old squad leader, not that of the new one
+        assert_eq!(
+            tracker.get_ready_check_start_time(),
+            Some(clock.now() - Duration::from_secs(3))
+        );
+        assert_ne!(
+            tracker.get_ready_check_start_time(),
+            tracker.squad_members["peer"].last_ready_time
+        );
+
+        clock.advance(Duration::from_secs(1));
+        ready_player("self", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(1));
+        send_update(
+            &[("peer", UserRole::SquadLeader, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let record = tracker.ready_check_history.last().unwrap().clone();
+        assert_eq!(
+            drain_leader_events(&mut tracker),
+            vec![SquadEventKind::ReadyCheckCompleted {
+                id: record.id,
+                duration: Duration::from_secs(5)
+            }]
+        );
+        assert_eq!(record.outcome, ReadyCheckOutcome::Succeeded);
+        assert_eq!(record.started_by, "squad_leader");
+        assert!(tracker.active_ready_check.is_none());
+        for (user, reaction_time) in [
+            ("squad_leader", Duration::ZERO),
+            ("peer", Duration::from_secs(2)),
+            ("self", Duration::from_secs(4)),
+        ] {
+            assert_eq!(
+                tracker.squad_members[user].total_ready_check_time, reaction_time,
+                "{}",
+                user
+            );
+        }
+    }
+
+    // Test that a ready check ends when someone who isn't ready becomes squad leader, and that readying in the same
+    // update restarts it with the new squad leader instead
+    #[rstest]
+    fn squad_leader_handover_not_ready(#[values(false, true)] pReadyInSameUpdate: bool) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock, mut test_users) = running_ready_check();
+        send_update(
+            &[("squad_leader", UserRole::Member, true)],
+            &mut tracker,
+            &mut test_users,
+        );
+        send_update(
+            &[("self", UserRole::SquadLeader, pReadyInSameUpdate)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let record = tracker.ready_check_history.last().unwrap().clone();
+        let not_ready = if pReadyInSameUpdate {
+            Vec::new()
+        } else {
+            vec!["self".to_string()]
+        };
+        let reason = ReadyCheckAbortReason::LeaderChanged {
+            old_leader: "squad_leader".to_string(),
+            new_leader: Some("self".to_string()),
+            not_ready,
+        };
+        assert_eq!(record.outcome, ReadyCheckOutcome::Aborted(reason.clone()));
+        assert_eq!(record.duration, Duration::from_secs(3));
+
+        let mut expected = vec![
+            leader_changed(Some("squad_leader"), None),
+            leader_changed(None, Some("self")),
+            SquadEventKind::ReadyCheckAborted {
+                id: record.id,
+                duration: Duration::from_secs(3),
+                reason,
+            },
+        ];
+        if pReadyInSameUpdate == false {
+            assert!(tracker.active_ready_check.is_none());
+
+            // The new squad leader readying later starts a new ready check as usual
+            clock.advance(Duration::from_secs(1));
             ready_player("self", &mut tracker, &mut test_users);
         }
+        expected.push(SquadEventKind::ReadyCheckStarted {
+            started_by: "self".to_string(),
+        });
+        assert_eq!(drain_leader_events(&mut tracker), expected);
+        assert_eq!(tracker.get_ready_check_elapsed(), Some(Duration::ZERO));
+        assert_eq!(tracker.active_ready_check.as_ref().unwrap().leader, "self");
+
+        // Nothing was counted for the aborted ready check
+        for user in ["self", "peer", "squad_leader"] {
+            assert_eq!(
+                tracker.squad_members[user].total_ready_check_time,
+                Duration::ZERO,
+                "{}",
+                user
+            );
+        }
+    }
 
-        // Ready check finished. Players unready in "random" order. If aborted, the state should not have changed from
-        // before the ready check was started
-        let mut expected_state = tracker.squad_members.clone();
-        for user in ["self", "squad_leader", "peer"] {
-            test_users.users.clear();
-            let role = if user == "squad_leader" {
-                UserRole::SquadLeader
-            } else {
-                UserRole::Member
+    // Test that a squad leader who loses the role without anyone else getting it still ends the ready check by
+    // unreadying, whether the unready arrives together with the role change or later
+    #[rstest]
+    fn demoted_leader_ends_ready_check(#[values(false, true)] pSameUpdate: bool) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock, mut test_users) = running_ready_check();
+        ready_player("self", &mut tracker, &mut test_users);
+        if pSameUpdate == false {
+            send_update(
+                &[("squad_leader", UserRole::Member, true)],
+                &mut tracker,
+                &mut test_users,
+            );
+            assert_eq!(tracker.squad_leader, None);
+            assert_eq!(
+                tracker.active_ready_check.as_ref().unwrap().leader,
+                "squad_leader"
+            );
+            clock.advance(Duration::from_secs(1));
+        }
+        send_update(
+            &[("squad_leader", UserRole::Member, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let duration = if pSameUpdate {
+            Duration::from_secs(3)
+        } else {
+            Duration::from_secs(4)
+        };
+        let record = tracker.ready_check_history.last().unwrap().clone();
+        assert_eq!(
+            drain_leader_events(&mut tracker),
+            vec![
+                leader_changed(Some("squad_leader"), None),
+                SquadEventKind::ReadyCheckCompleted {
+                    id: record.id,
+                    duration
+                },
+            ]
+        );
+        assert_eq!(record.outcome, ReadyCheckOutcome::Succeeded);
+        assert!(tracker.active_ready_check.is_none());
+        assert_eq!(
+            tracker.squad_members["self"].total_ready_check_time,
+            Duration::from_secs(3)
+        );
+    }
+
+    // Test that a late role update only backdates the ready check to the ready of the new squad leader if that ready
+    // could still belong to a running ready check
+    #[rstest]
+    fn ready_check_start_before_role_limit(#[values(false, true)] pPastHardLimit: bool) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
+        test_users.users[0].role = UserRole::Member;
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+
+        send_update(
+            &[("squad_leader", UserRole::Member, true)],
+            &mut tracker,
+            &mut test_users,
+        );
+        let hard_limit = tracker.get_ready_check_limits().hard_limit;
+        let since_ready = if pPastHardLimit {
+            hard_limit
+        } else {
+            hard_limit - Duration::from_secs(1)
+        };
+        clock.advance(since_ready);
+        send_update(
+            &[("squad_leader", UserRole::SquadLeader, true)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let expected_elapsed = if pPastHardLimit {
+            Duration::ZERO
+        } else {
+            since_ready
+        };
+        assert_eq!(tracker.get_ready_check_elapsed(), Some(expected_elapsed));
+        assert_eq!(
+            tracker.active_ready_check.as_ref().unwrap().started_by,
+            "squad_leader"
+        );
+    }
+
+    // Test that a ready check starts when the squad leader readies, even if the ready update arrives before the role
+    // update that made them squad leader
+    #[rstest]
+    fn ready_check_start_before_role(#[values(false, true)] pRoleFirst: bool) {
+        install_log_handler().unwrap();
+
+        let (mut tracker, clock) = new_tracker();
+        let mut test_users = three_member_squad(false, false);
+        test_users.users[0].role = UserRole::Member;
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
+        assert_eq!(tracker.squad_leader, None);
+
+        clock.advance(Duration::from_secs(1));
+        if pRoleFirst == true {
+            send_update(
+                &[("squad_leader", UserRole::SquadLeader, false)],
+                &mut tracker,
+                &mut test_users,
+            );
+            clock.advance(Duration::from_secs(1));
+            send_update(
+                &[("squad_leader", UserRole::SquadLeader, true)],
+                &mut tracker,
+                &mut test_users,
+            );
+        } else {
+            clock.advance(Duration::from_secs(1));
+            send_update(
+                &[("squad_leader", UserRole::Member, true)],
+                &mut tracker,
+                &mut test_users,
+            );
+            assert!(tracker.active_ready_check.is_none());
+        }
+        clock.advance(Duration::from_secs(1));
+        ready_player("peer", &mut tracker, &mut test_users);
+        if pRoleFirst == false {
+            clock.advance(Duration::from_secs(1));
+            send_update(
+                &[("squad_leader", UserRole::SquadLeader, true)],
+                &mut tracker,
+                &mut test_users,
+            );
+        }
+        assert_eq!(
+            drain_leader_events(&mut tracker),
+            vec![
+                leader_changed(None, Some("squad_leader")),
+                SquadEventKind::ReadyCheckStarted {
+                    started_by: "squad_leader".to_string()
+                },
+            ]
+        );
+
+        // Both ways the ready check started when squad_leader readied, 2 seconds in
+        clock.advance(Duration::from_secs(1));
+        ready_player("self", &mut tracker, &mut test_users);
+        clock.advance(Duration::from_secs(1));
+        send_update(
+            &[("squad_leader", UserRole::SquadLeader, false)],
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let record = tracker.ready_check_history.last().unwrap().clone();
+        let self_reaction_time = if pRoleFirst {
+            Duration::from_secs(2)
+        } else {
+            Duration::from_secs(3)
+        };
+        assert_eq!(record.outcome, ReadyCheckOutcome::Succeeded);
+        assert_eq!(record.duration, self_reaction_time + Duration::from_secs(1));
+        for member in record.members.iter() {
+            let (reaction_time, readied_at) = match member.account_name.as_str() {
+                "squad_leader" => (Duration::ZERO, Duration::ZERO),
+                "peer" => (Duration::from_secs(1), Duration::from_secs(1)),
+                _ => (self_reaction_time, self_reaction_time),
             };
-            test_users
-                .users
-                .push(TestUser::new(user.to_string(), 12345, role, 0, false));
-            let pre_op_time = Instant::now();
+            assert_eq!(
+                member.reaction_time,
+                Some(reaction_time),
+                "{}",
+                member.account_name
+            );
+            assert_eq!(
+                member.events.first(),
+                Some(&ReadyStatusEvent {
+                    offset: readied_at,
+                    change: ReadyStatusChange::Readied
+                }),
+                "{}",
+                member.account_name
+            );
+        }
+    }
+
+    // Test that a ready check is aborted when its squad leader leaves the squad
+    #[test]
+    fn squad_leader_left_during_ready_check() {
+        install_log_handler().unwrap();
+
+        let (mut tracker, _clock, mut test_users) = running_ready_check();
+        update_user(
+            "squad_leader",
+            12345,
+            UserRole::None,
+            &mut tracker,
+            &mut test_users,
+        );
+
+        let record = tracker.ready_check_history.last().unwrap().clone();
+        let reason = ReadyCheckAbortReason::LeaderChanged {
+            old_leader: "squad_leader".to_string(),
+            new_leader: None,
+            not_ready: vec!["self".to_string()],
+        };
+        assert_eq!(record.outcome, ReadyCheckOutcome::Aborted(reason.clone()));
+        assert_eq!(
+            drain_leader_events(&mut tracker),
+            vec![
+                leader_changed(Some("squad_leader"), None),
+                SquadEventKind::ReadyCheckAborted {
+                    id: record.id,
+                    duration: Duration::from_secs(3),
+                    reason,
+                },
+            ]
+        );
+        assert_eq!(tracker.squad_leader, None);
+        assert!(tracker.active_ready_check.is_none());
+    }
+
+    // Test that ready check stats are restored for members that are still in the same squad session and discarded for
+    // members that rejoined in the meantime
+    #[rstest]
+    fn ready_check_stats_persistence(#[values(false, true)] pSelfRejoined: bool) {
+        install_log_handler().unwrap();
+
+        let stats_path = std::env::temp_dir().join(format!(
+            "arcdps_squad_manager_ready_check_stats_{}.json",
+            pSelfRejoined
+        ));
+        let _ = std::fs::remove_file(&stats_path);
+
+        let mut test_users = TestUserList::new();
+        for user in ["self", "peer", "rejoined_peer"] {
+            test_users.users.push(TestUser::new(
+                user.to_string(),
+                12345,
+                UserRole::Member,
+                0,
+                false,
+            ));
+        }
+
+        {
+            let mut tracker = SquadTracker::new("self");
+            tracker.load_ready_check_stats(&stats_path);
             unsafe {
                 tracker.squad_update(test_users.get_iter());
             }
-            let post_op_time = Instant::now();
-
-            if expected_state[user].is_ready == true {
-                assert_in_range!(
-                    tracker.squad_members[user].last_unready_time,
-                    Some(pre_op_time),
-                    Some(post_op_time)
-                );
-                expected_state.get_mut(user).unwrap().last_unready_time =
-                    tracker.squad_members[user].last_unready_time;
-                expected_state.get_mut(user).unwrap().is_ready = false;
+            for user in ["self", "peer", "rejoined_peer"] {
+                let state = tracker.squad_members.get_mut(user).unwrap();
+                state.total_ready_check_time = Duration::new(5, 0);
+                state.last_unready_duration = Some(Duration::new(2, 0));
             }
+            tracker.save_ready_check_stats();
         }
 
-        if pAborted == false {
-            let actual_self_user = tracker.squad_members.get("self").unwrap();
-            assert_gt!(
-                actual_self_user.total_ready_check_time,
-                initial_ready_check_time_spent
-            );
-            assert_eq!(
-                actual_self_user.last_unready_duration,
-                Some(actual_self_user.total_ready_check_time - initial_ready_check_time_spent)
-            );
+        let mut tracker = SquadTracker::new("self");
+        tracker.load_ready_check_stats(&stats_path);
+        assert_eq!(tracker.restored_stats.len(), 3);
+        assert!(tracker.has_unsaved_stats() == false);
 
-            let expected_self_user = expected_state.get_mut("self").unwrap();
-            expected_self_user.total_ready_check_time = actual_self_user.total_ready_check_time;
-            expected_self_user.last_unready_duration = actual_self_user.last_unready_duration;
-
-            // Peer readied at the first possible moment, so the increment could be zero unless they did a ready-unready cycle
-            let actual_peer_user = tracker.squad_members.get("peer").unwrap();
-            let expected_peer_user = expected_state.get_mut("peer").unwrap();
-            if pReadyAndUnready == true {
-                assert_gt!(
-                    actual_peer_user.total_ready_check_time,
-                    initial_ready_check_time_spent
-                );
-                assert_eq!(
-                    actual_peer_user.last_unready_duration,
-                    Some(actual_peer_user.total_ready_check_time - initial_ready_check_time_spent)
-                );
+        test_users.users[0].join_time = if pSelfRejoined { 23456 } else { 12345 };
+        test_users.users[2].join_time = 23456;
+        let saved_stats = std::fs::read_to_string(&stats_path).unwrap();
+        unsafe {
+            tracker.squad_update(test_users.get_iter());
+        }
 
-                expected_peer_user.total_ready_check_time = actual_peer_user.total_ready_check_time;
-                expected_peer_user.last_unready_duration = actual_peer_user.last_unready_duration;
+        // Squad updates only mark the stats as changed, saving them is up to the caller
+        assert!(tracker.has_unsaved_stats() == true);
+        assert_eq!(std::fs::read_to_string(&stats_path).unwrap(), saved_stats);
+
+        let peer_stats_restored = pSelfRejoined == false;
+        for (user, restored) in [
+            ("self", peer_stats_restored),
+            ("peer", peer_stats_restored),
+            ("rejoined_peer", false),
+        ] {
+            let state = &tracker.squad_members[user];
+            if restored {
+                assert_eq!(state.total_ready_check_time, Duration::new(5, 0));
+                assert_eq!(state.last_unready_duration, Some(Duration::new(2, 0)));
             } else {
-                expected_peer_user.last_unready_duration = Some(Duration::new(0, 0));
+                assert_eq!(state.total_ready_check_time, Duration::new(0, 0));
+                assert_eq!(state.last_unready_duration, None);
             }
+        }
+        assert_eq!(tracker.restored_stats.len(), 0);
 
-            expected_state
-                .get_mut("squad_leader")
-                .unwrap()
-                .last_unready_duration = Some(Duration::new(0, 0));
+        let _ = std::fs::remove_file(&stats_path);
+    }
+
+    fn update_user(
+        pPlayerName: &str,
+        pJoinTime: u64,
+        pRole: UserRole,
+        pTracker: &mut SquadTracker,
+        pTestUsers: &mut TestUserList,
+    ) {
+        pTestUsers.users.clear();
+        pTestUsers.users.push(TestUser::new(
+            pPlayerName.to_string(),
+            pJoinTime,
+            pRole,
+            0,
+            false,
+        ));
+        unsafe {
+            pTracker.squad_update(pTestUsers.get_iter());
         }
+    }
 
-        assert_eq!(tracker.squad_members, expected_state);
+    // Test that invited and applied users are tracked separately from squad members until they join, and that the way
+    // they stopped being pending is recorded
+    #[rstest]
+    fn pending_users(
+        #[values(UserRole::Invited, UserRole::Applied)] pPendingRole: UserRole,
+        #[values(false, true)] pAccepted: bool,
+    ) {
+        install_log_handler().unwrap();
+
+        let clock = ManualClock::starting_at(Local.timestamp(1000, 0));
+        let mut tracker = SquadTracker::with_clock("self", Arc::new(clock.clone()));
+        let mut test_users = TestUserList::new();
+        update_user(
+            "self",
+            100,
+            UserRole::SquadLeader,
+            &mut tracker,
+            &mut test_users,
+        );
+
+        update_user("pending", 200, pPendingRole, &mut tracker, &mut test_users);
+        assert_eq!(tracker.squad_members.len(), 1);
+        assert_eq!(tracker.pending_users.len(), 1);
+        assert_eq!(tracker.pending_users["pending"].role, pPendingRole);
+        assert_eq!(tracker.pending_users["pending"].join_time, 200);
+
+        // The time waited follows the clock of the tracker
+        let waiting = |pTracker: &SquadTracker| {
+            pTracker.pending_users["pending"].waiting(&pTracker.now_local())
+        };
+        assert_eq!(waiting(&tracker), Duration::from_secs(800));
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(waiting(&tracker), Duration::from_secs(860));
+        assert_eq!(
+            tracker.pending_users["pending"].waiting(&Local.timestamp(100, 0)),
+            Duration::ZERO
+        );
+
+        // Repeated updates while pending don't resolve anything
+        update_user("pending", 200, pPendingRole, &mut tracker, &mut test_users);
+        assert_eq!(tracker.pending_users.len(), 1);
+        assert_eq!(tracker.pending_user_history.len(), 0);
+
+        let (role, expected_outcome) = if pAccepted {
+            (UserRole::Member, PendingUserOutcome::Accepted)
+        } else {
+            (UserRole::None, PendingUserOutcome::Cancelled)
+        };
+        update_user("pending", 300, role, &mut tracker, &mut test_users);
+
+        assert_eq!(tracker.pending_users.len(), 0);
+        assert_eq!(tracker.squad_members.len(), if pAccepted { 2 } else { 1 });
+        assert_eq!(tracker.pending_user_history.len(), 1);
+        let record = &tracker.pending_user_history[0];
+        assert_eq!(record.account_name, "pending");
+        assert_eq!(record.role, pPendingRole);
+        assert_eq!(record.join_time, 200);
+        assert_eq!(record.outcome, expected_outcome);
+
+        // Self leaving the squad drops everything that is still pending, without resolving it
+        update_user(
+            "pending_2",
+            400,
+            pPendingRole,
+            &mut tracker,
+            &mut test_users,
+        );
+        assert_eq!(tracker.pending_users.len(), 1);
+        update_user("self", 100, UserRole::None, &mut tracker, &mut test_users);
+        assert_eq!(tracker.pending_users.len(), 0);
+        assert_eq!(tracker.pending_user_history.len(), 1);
     }
 }
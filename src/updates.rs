@@ -1,12 +1,14 @@
+use std::{io::Read, mem::swap};
+#[cfg(windows)]
 use std::{
     fs::{self, File},
-    io::{Read, Write},
-    mem::swap,
+    io::Write,
     path::PathBuf,
 };
 
 use serde::Deserialize;
 use version_compare::Version;
+#[cfg(windows)]
 use winapi::{
     shared::minwindef::{HINSTANCE, MAX_PATH},
     um::{errhandlingapi::GetLastError, libloaderapi::GetModuleFileNameW},
@@ -159,6 +161,13 @@ fn download_data(pDownloadUrl: &String) -> Option<Vec<u8>> {
     Some(bytes)
 }
 
+#[cfg(not(windows))]
+fn replace_binary(_pData: Vec<u8>) -> bool {
+    warn!("Replacing the addon binary is only supported on Windows");
+    false
+}
+
+#[cfg(windows)]
 fn replace_binary(pData: Vec<u8>) -> bool {
     let self_path_str = unsafe {
         extern "C" {